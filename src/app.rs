@@ -9,110 +9,43 @@
 use std::collections::HashMap;
 use std::io::{Cursor, prelude::*};
 use std::net::TcpStream;
-use std::sync::atomic::AtomicBool;
 use core::str;
 use image::DynamicImage;
 use refimage::{GenericImageOwned, ImageRef, DynamicImageRef, ColorSpace};
 use eframe::egui;
 use eframe::egui::{Visuals, load::Bytes};
 use egui::{menu, ImageSource, Ui};
-use ewebsock::{WsEvent, WsMessage, WsReceiver, WsSender};
+use ewebsock::{WsEvent, WsMessage};
 use circular_buffer::CircularBuffer;
 use gencam_packet::GenCamPacket;
+use crate::auto_exposure::AutoExposure;
+use crate::camera_profile::{self, ProfileAction, ProfileLibrary, ProfileManager, ProfileSettings};
+use crate::calibration::{Calibration, CalibrationAction, FrameInfo, MasterFrame};
+use crate::capture_group::{GroupMember, GroupRun, SyncCapture};
+use crate::capture_plan::{CapturePlan, FrameType, PlanRunner};
+use crate::clock;
+use crate::connect_dialog::ConnectDialog;
+use crate::connection::{CameraKey, ConnectionManager, ConnectionStatus};
+use crate::command::{CameraCommand, CameraInfo, CameraReply, CameraState, CameraStatus, CaptureFormat, ServerRequest};
+use crate::fits::{self, HeaderCard, HeaderValue};
+use crate::display::DisplaySettings;
+use crate::exposure::ExposureProgress;
+use crate::focus::FocusAssistant;
+use crate::frame::{FloatFrame, FrameRequest, RawFrame, Region};
+use crate::live_stack::{LiveStack, LiveStackAction};
+use crate::overlay::Overlays;
+use crate::profile::LineProfile;
+use crate::sensor::{PresetLibrary, SensorAction, SensorControls};
+use crate::ser::SerRecorder;
+use crate::settings::GuiSettings;
+use crate::stars::StarAnalysis;
+use crate::stats::{StatsPanel, StatsScope};
+use crate::stacking::{BuilderAction, MasterBuilder};
+use crate::timelapse::{TimeLapse, TimeLapseRun};
+use crate::trigger::{TriggerAction, TriggerControls};
 // use std::future::Future;
 // use rfd::AsyncFileDialog;
 
-struct WsBackend {
-    ws_sender: WsSender,
-    ws_receiver: WsReceiver,
-    events: Vec<WsEvent>,
-    pub image_events: Vec<WsEvent>,
-    pub new_image_event: AtomicBool,
-    message: String,
-}
-
-impl WsBackend {
-    fn connect(uri: &str, ctx: &Option<egui::Context>) -> Option<WsBackend> {
-        let res = if let Some(ctx) = ctx {
-            let ctx = ctx.clone();
-            let wakeup = move || ctx.request_repaint();
-            ewebsock::connect_with_wakeup(uri, Default::default(), wakeup)
-        } else {
-            ewebsock::connect(uri, Default::default())
-        };
-        match res {
-            Ok((ws_sender, ws_receiver)) => {
-                let ws = WsBackend {
-                    ws_sender,
-                    ws_receiver,
-                    events: Vec::new(),
-                    image_events: Vec::new(),
-                    new_image_event: AtomicBool::new(false),
-                    message: String::new(),
-                };
-                Some(ws)
-            }
-            Err(e) => {
-                eprintln!("Failed to connect to websocket: {}", e);
-                None
-            }
-        }
-    }
-
-    fn close(&mut self) {
-        self.ws_sender.close();
-    }
-
-    fn ui(&mut self, ui: &mut Ui) {
-        // Push any event to either the general event vector or the image vector.
-        while let Some(event) = self.ws_receiver.try_recv() {
-            match event.clone() {
-                WsEvent::Message(WsMessage::Binary(data)) => { // All messages should be binary
-                    let pkt: GenCamPacket = serde_json::from_slice(&data).expect("Failed to deserialize packet.");
-                    match pkt {
-                        GenCamPacket::Image { .. } => {
-                            self.image_events.push(event);
-                            self.new_image_event = AtomicBool::new(true);
-                        },
-                        _ => {
-                            self.events.push(event);
-                        },
-                    }
-                }
-                _ => {
-                    self.events.push(event);
-                }
-            }
-        }
-        
-        ui.horizontal(|ui| {
-            if ui.button("Send Ack").clicked() {
-                let pkt = GenCamPacket::ack();
-                // Set msg to serialized pkt.
-                let msg = serde_json::to_vec(&pkt).unwrap();
-                // Send
-                self.ws_sender.send(WsMessage::Binary(msg));
-            }
-
-            if ui.button("Send NAck").clicked() {
-                let pkt = GenCamPacket::nack();
-                // Set msg to serialized pkt.
-                let msg = serde_json::to_vec(&pkt).unwrap();
-                // Send
-                self.ws_sender.send(WsMessage::Binary(msg));
-            }
-
-            if ui.button("Send ImgReq").clicked() {
-                let pkt = GenCamPacket::image_request();
-                // Set msg to serialized pkt.
-                let msg = serde_json::to_vec(&pkt).unwrap();
-                // Send
-                self.ws_sender.send(WsMessage::Binary(msg));
-            }
-        });
-    }
-}
-
 #[derive(Debug, Clone)]
 enum DialogType {
     Debug,
@@ -132,10 +65,95 @@ impl DialogType {
     }
 }
 
-// TODO: This is an example for the sake of GUI functionality.
-#[derive(Debug, Clone)]
+/// Settings and state kept separately for each camera.
+///
+/// The active camera's values live in the matching `GenCamGUI` fields; the others are parked
+/// here and swapped in when their camera is selected.
+struct CameraSettings {
+    raw_frame: Option<RawFrame>,
+    exposure_slider: f32,
+    long_exp_checkbox: bool,
+    auto_exp_checkbox: bool,
+    auto_exposure: AutoExposure,
+    sensor: SensorControls,
+    trigger: TriggerControls,
+    exposure_progress: ExposureProgress,
+    curr_cam_temp: f32,
+    cooler_status: CoolerStatus,
+    cooler_target: f32,
+    color_space: ColorSpaceOpt,
+    roi: [f32; 4],
+    roi_type: ROITypes,
+    roi_enabled: bool,
+    binning: u8,
+    cam_status: CameraStatus,
+    calibration: Calibration,
+    display: DisplaySettings,
+    overlays: Overlays,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            raw_frame: None,
+            exposure_slider: 0.0,
+            long_exp_checkbox: false,
+            auto_exp_checkbox: false,
+            auto_exposure: AutoExposure::default(),
+            sensor: SensorControls::default(),
+            trigger: TriggerControls::default(),
+            exposure_progress: ExposureProgress::default(),
+            curr_cam_temp: 0.0,
+            cooler_status: CoolerStatus::Off,
+            cooler_target: 0.0,
+            color_space: ColorSpaceOpt::Gray,
+            roi: [0.0, 0.0, 0.0, 0.0],
+            roi_type: ROITypes::Center,
+            roi_enabled: false,
+            binning: 1,
+            cam_status: CameraStatus::default(),
+            calibration: Calibration::default(),
+            display: DisplaySettings::default(),
+            overlays: Overlays::default(),
+        }
+    }
+}
+
+impl CameraSettings {
+    /// Settings for a newly listed camera, starting from the saved exposure and ROI.
+    fn from_saved(saved: &GuiSettings) -> Self {
+        Self {
+            exposure_slider: saved.exposure_slider,
+            long_exp_checkbox: saved.long_exposure,
+            roi: saved.roi,
+            roi_type: if saved.roi_centered { ROITypes::Center } else { ROITypes::Corner },
+            roi_enabled: saved.roi_enabled,
+            binning: saved.binning,
+            overlays: saved.overlays.clone(),
+            display: saved.display.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// The exposure selected on the parked exposure slider, in seconds.
+    fn exposure_seconds(&self) -> f64 {
+        if self.long_exp_checkbox {
+            self.exposure_slider as f64
+        } else {
+            self.exposure_slider as f64 / 1000.0
+        }
+    }
+}
+
+/// A camera on the server, as shown in the camera list.
 struct CamData {
-    name: String,
+    info: CameraInfo,
+    /// Parked settings; stale while this is the active camera.
+    settings: CameraSettings,
+    /// The parked frame rendered for the tiled view.
+    texture: Option<egui::TextureHandle>,
+    /// `texture` no longer matches the parked frame or display settings.
+    texture_dirty: bool,
 }
 
 // #[derive(Clone)]
@@ -150,10 +168,36 @@ pub struct GenCamGUI {
     comms_stream: Option<TcpStream>,
     // comms_buffer: [u8; 4096],
     // server_connection: bool,
-    // connected_cameras: HashMap<String, CamData>,
+    /// Cameras reported by all connected servers.
+    connected_cameras: HashMap<CameraKey, CamData>,
+    /// The camera whose settings are shown and which commands are sent to.
+    active_camera: Option<CameraKey>,
 
     data: Option<Bytes>,
     img_uri: String,
+    /// The most recent frame, before conversion for display.
+    raw_frame: Option<RawFrame>,
+    /// Frames decoded since the last update, oldest first.
+    new_frames: Vec<RawFrame>,
+    /// The displayed image needs rebuilding from `raw_frame`.
+    display_dirty: bool,
+    calibration: Calibration,
+    live_stack: LiveStack,
+    star_analysis: StarAnalysis,
+    display: DisplaySettings,
+    /// Paint saturated pixels red and zero pixels blue in the viewer.
+    highlight_clipping: bool,
+    /// Show every camera's live view side by side instead of only the active one.
+    tiled_view: bool,
+    /// Columns in the tiled view, or 0 to choose from the number of cameras.
+    tile_columns: usize,
+    focus: FocusAssistant,
+    stats: StatsPanel,
+    profile: LineProfile,
+    viewer_tool: ViewerTool,
+    overlays: Overlays,
+    /// Image pixel where the current drag on the viewer began.
+    drag_start: Option<(f32, f32)>,
 
     frame: egui::Frame,
 
@@ -164,22 +208,74 @@ pub struct GenCamGUI {
     long_exp_checkbox: bool,
     exposure_slider: f32,
     auto_exp_checkbox: bool,
+    auto_exposure: AutoExposure,
+    sensor: SensorControls,
+    /// Sensor presets for every camera model, shared by all cameras.
+    sensor_presets: PresetLibrary,
+    trigger: TriggerControls,
+    exposure_progress: ExposureProgress,
+    /// JSON file holding the per-model sensor presets.
+    sensor_presets_path: String,
+    /// The preset file has been read since start-up.
+    sensor_presets_loaded: bool,
     min_cam_temp: f32,
     max_cam_temp: f32,
     curr_cam_temp: f32,
     cooler_status: CoolerStatus,
+    /// Cooler setpoint in °C.
+    cooler_target: f32,
     color_space: ColorSpaceOpt,
     roi: [f32; 4],
     roi_type: ROITypes,
     roi_enabled: bool,
     img_width: i32,
     img_height: i32,
+    binning: u8,
+    cam_status: CameraStatus,
+
+    // Capture Plan
+    show_capture_plan: bool,
+    capture_plan: CapturePlan,
+    plan_path: String,
+    plan_output_dir: String,
+    plan_runner: Option<PlanRunner>,
+
+    // Master Builder
+    show_master_builder: bool,
+    master_builder: MasterBuilder,
+
+    // Camera Profiles
+    /// Camera profiles for every camera model, shared by all cameras.
+    camera_profiles: ProfileLibrary,
+    /// Image request sent after applying a profile with a ROI. The ROI is sent once the frame
+    /// answering it shows the sensor size at the profile's binning.
+    pending_roi: Option<FrameRequest>,
+    /// JSON file holding the camera profiles.
+    camera_profiles_path: String,
+    /// The profile file has been read since start-up.
+    camera_profiles_loaded: bool,
+    profile_manager: ProfileManager,
+
+    // Synchronized Capture
+    show_sync_capture: bool,
+    sync_capture: SyncCapture,
+
+    // Time-Lapse
+    timelapse: TimeLapse,
+    timelapse_run: Option<TimeLapseRun>,
+
+    // Video Recording
+    ser_recorder: SerRecorder,
 
     // Websocket
-    /// The URI of the websocket server.
+    /// The URI of the next websocket server to connect to.
     pub uri: String,
-    /// The websocket backend.
-    pub ws: Option<WsBackend>,
+    /// Open server connections.
+    pub connections: ConnectionManager,
+    show_connect_dialog: bool,
+    connect_dialog: ConnectDialog,
+    /// Settings restored at start-up; newly listed cameras start from their exposure and ROI.
+    saved_settings: GuiSettings,
     /// The egui context.
     pub ctx: Option<egui::Context>,
 }
@@ -190,6 +286,14 @@ enum ROITypes {
     Corner,
 }
 
+/// What dragging on the image does.
+#[derive(Debug, PartialEq)]
+enum ViewerTool {
+    StatsBox,
+    Line,
+    Reticle,
+}
+
 #[derive(Debug, PartialEq)]
 enum ColorSpaceOpt {
     Gray,
@@ -218,10 +322,27 @@ impl Default for GenCamGUI {
             // comms_buffer: [0; 4096],
             // server_connection: false,
 
-            // connected_cameras: HashMap::new(),
+            connected_cameras: HashMap::new(),
+            active_camera: None,
 
             data: None,
             img_uri: "image/png".into(),
+            raw_frame: None,
+            new_frames: Vec::new(),
+            display_dirty: false,
+            calibration: Calibration::default(),
+            live_stack: LiveStack::default(),
+            star_analysis: StarAnalysis::default(),
+            display: DisplaySettings::default(),
+            highlight_clipping: false,
+            tiled_view: false,
+            tile_columns: 0,
+            focus: FocusAssistant::default(),
+            stats: StatsPanel::default(),
+            profile: LineProfile::default(),
+            viewer_tool: ViewerTool::StatsBox,
+            overlays: Overlays::default(),
+            drag_start: None,
 
             frame: egui::Frame {
                 inner_margin: 6.0.into(),
@@ -242,20 +363,56 @@ impl Default for GenCamGUI {
             long_exp_checkbox: false,
             exposure_slider: 0.0,
             auto_exp_checkbox: false,
+            auto_exposure: AutoExposure::default(),
+            sensor: SensorControls::default(),
+            sensor_presets: PresetLibrary::default(),
+            trigger: TriggerControls::default(),
+            exposure_progress: ExposureProgress::default(),
+            sensor_presets_path: "sensor_presets.json".into(),
+            sensor_presets_loaded: false,
             min_cam_temp: -80.0,
             max_cam_temp: 10.0,
             curr_cam_temp: 0.0,
             cooler_status: CoolerStatus::Off,
+            cooler_target: 0.0,
             color_space: ColorSpaceOpt::Gray,
             roi: [0.0, 0.0, 0.0, 0.0],
             roi_type: ROITypes::Center,
             roi_enabled: false,
             img_height: 0,
             img_width: 0,
+            binning: 1,
+            cam_status: CameraStatus::default(),
+
+            show_capture_plan: false,
+            capture_plan: CapturePlan::default(),
+            plan_path: "plan.json".into(),
+            plan_output_dir: ".".into(),
+            plan_runner: None,
+
+            show_master_builder: false,
+            master_builder: MasterBuilder::default(),
+
+            camera_profiles: ProfileLibrary::default(),
+            pending_roi: None,
+            camera_profiles_path: "camera_profiles.json".into(),
+            camera_profiles_loaded: false,
+            profile_manager: ProfileManager::default(),
+
+            show_sync_capture: false,
+            sync_capture: SyncCapture::default(),
+
+            timelapse: TimeLapse::default(),
+            timelapse_run: None,
+
+            ser_recorder: SerRecorder::default(),
 
             msg_list: CircularBuffer::new(),
             uri: "ws://localhost:9001".into(),
-            ws: None,
+            connections: ConnectionManager::default(),
+            show_connect_dialog: false,
+            connect_dialog: ConnectDialog::default(),
+            saved_settings: GuiSettings::default(),
             ctx: None,
         }
     }
@@ -393,8 +550,8 @@ impl GenCamGUI {
         Ok(())
     }
 
-    // Assuems the last binary data we received is a valid image (change this later).
-    fn update_test_image(&mut self) -> std::io::Result<()> {
+    // Assumes binary data we received is a valid image (change this later).
+    fn update_test_image(&mut self, now: f64) {
         // self.msg_list.push_back("Attempting to update image...".to_owned());
         // let mut stream = self.comms_stream.as_ref().unwrap();
         // let mut buffer = [0; 4096];
@@ -408,54 +565,862 @@ impl GenCamGUI {
         //     str::from_utf8(&buffer).unwrap()
         // );
 
-        // Cant update if we have no connection
-        if self.ws.is_none() {
-            return Ok(());
+        // Decode every image received since the last update, not just the newest, so that
+        // recordings see every frame.
+        let mut events = Vec::new();
+        for ws in self.connections.connections.iter_mut() {
+            for image in std::mem::take(&mut ws.image_events) {
+                let key = image.source.map(|id| CameraKey { server: ws.uri.clone(), id });
+                events.push((key, image.request, image.info, image.event));
+            }
         }
-
-        let binding = self.ws.as_ref().unwrap().image_events.clone();
-        let latest_event = binding.last().unwrap();
-        
-        match latest_event {
-            WsEvent::Message(WsMessage::Binary(data)) => {
+        for (key, request, info, event) in events {
+            if let WsEvent::Message(WsMessage::Binary(data)) = event {
                 // The 'image event' should contain a serialized GenCamPacket. We have to deserialize it to get the image data.
+                // The connection already decoded it once, so this only fails if the packet was corrupted since.
+                let pkt: GenCamPacket = match serde_json::from_slice(&data) {
+                    Ok(pkt) => pkt,
+                    Err(e) => {
+                        log::warn!("Dropping malformed image packet: {}", e);
+                        continue;
+                    }
+                };
 
-                let pkt: GenCamPacket = serde_json::from_slice(data).unwrap();
-
-                if let GenCamPacket::Image { header: _, mut data, width, height, .. } = pkt {
-                    // We have an image packet.
-                    // let pkt = pkt.unwrap();
-                    // let x_dim = pkt;
-                    // let y_dim = pkt.y_dim;
-                    // let mut img_data = pkt.data.unwrap();
-
-                    // Generic_Image conversions...
-                    // RX and deserialize...
-                    // let x_dim = pkt;
-                    // let y_dim = pkt.;
-                    // let mut img_data = pkt.data.unwrap();
-    
-                    // Generic_Image conversions...
-                    // RX and deserialize...
-                    let img = ImageRef::new(&mut data, width as usize, height as usize, ColorSpace::Rgb).unwrap();
-                    let img = DynamicImageRef::from(img);
-                    
-                    let img: DynamicImage = img.try_into().expect("Could not convert image");
-            
-                    let mut data = Cursor::new(Vec::new());
-                    img.write_to(&mut data, image::ImageFormat::Png).unwrap();
-                    self.data = Some(data.into_inner().into());
+                if let GenCamPacket::Image { header: _, data, width, height, .. } = pkt {
+                    if let Some(mut frame) = RawFrame::from_packet(&data, width as usize, height as usize, info.as_ref(), clock::unix_time()) {
+                        frame.request = request;
+                        if let (Some(run), Some(k)) = (&mut self.sync_capture.run, key.as_ref().or(self.active_camera.as_ref())) {
+                            run.offer(k, &frame);
+                        }
+                        // Frames from cameras in the background only replace their latest frame.
+                        match key.filter(|k| self.active_camera.as_ref() != Some(k)).and_then(|k| self.connected_cameras.get_mut(&k)) {
+                            Some(cam) => {
+                                cam.settings.trigger.frame_received(now);
+                                cam.settings.raw_frame = Some(frame);
+                                cam.texture_dirty = true;
+                            }
+                            None => self.new_frames.push(frame),
+                        }
+                    }
                 }
+            }
+        }
 
-                Ok(())
-            },
-            _ => {
-                Ok(())
+        if let Some(frame) = self.new_frames.last().cloned() {
+            self.img_width = frame.width as i32;
+            self.img_height = frame.height as i32;
+            self.raw_frame = Some(frame);
+            self.display_dirty = true;
+        }
+    }
+
+    /// Whether commands for the active camera have a connection to go out on.
+    fn camera_connected(&self) -> bool {
+        self.connections.serves(self.active_camera.as_ref())
+    }
+
+    /// The acquisition settings the current frames are being taken with.
+    fn frame_info(&self) -> FrameInfo {
+        FrameInfo {
+            exposure: self.exposure_seconds(),
+            temperature: self.cam_status.temperature,
+            binning: self.binning,
+        }
+    }
+
+    /// Applies display calibration to a frame, if enabled.
+    fn calibrate_for_display(&mut self, frame: RawFrame) -> RawFrame {
+        if self.calibration.enabled && self.calibration.has_masters() {
+            let info = self.frame_info();
+            self.calibration.apply(&frame, &info)
+        } else {
+            frame
+        }
+    }
+
+    /// Rebuilds the displayed image from the latest raw frame, or the live stack when stacking.
+    fn refresh_display(&mut self) {
+        if let Some(raw) = self.raw_frame.take() {
+            let roi = self.roi_region(&raw);
+            self.stats.update(&raw, roi);
+            self.profile.update(&raw);
+            self.raw_frame = Some(raw);
+        }
+        let stack = if self.live_stack.enabled { self.live_stack.result().map(|s| s.to_raw()) } else { None };
+        let frame = match (stack, self.raw_frame.clone()) {
+            (Some(stack), _) => stack,
+            (None, Some(frame)) => self.calibrate_for_display(frame),
+            (None, None) => return,
+        };
+        if self.star_analysis.enabled {
+            self.star_analysis.analyze(&frame);
+        }
+        if let Err(e) = self.show_frame(&frame) {
+            self.data = None;
+            self.msg_list.push_back(format!("Cannot display frame: {}", e));
+        }
+    }
+
+    /// The ROI sliders as a pixel region of `frame`, if the ROI is enabled.
+    ///
+    /// X and Y are pixel positions of the ROI's centre or top-left corner, and the size sliders
+    /// are percentages of the frame's width and height.
+    fn roi_region(&self, frame: &RawFrame) -> Option<Region> {
+        if !self.roi_enabled {
+            return None;
+        }
+        let width = (self.roi[2] / 100.0 * frame.width as f32) as usize;
+        let height = (self.roi[3] / 100.0 * frame.height as f32) as usize;
+        let (x, y) = match self.roi_type {
+            ROITypes::Center => (self.roi[0] - width as f32 / 2.0, self.roi[1] - height as f32 / 2.0),
+            ROITypes::Corner => (self.roi[0], self.roi[1]),
+        };
+        Some(Region { x: x.max(0.0) as usize, y: y.max(0.0) as usize, width, height })
+    }
+
+    /// Measures newly arrived frames for the focus assistant, if it is running.
+    fn measure_focus(&mut self, frames: &[RawFrame]) {
+        if !self.focus.enabled {
+            return;
+        }
+        for frame in frames {
+            match self.roi_region(frame) {
+                Some(region) => self.focus.push(&frame.crop(&region), (region.x, region.y)),
+                None => self.focus.push(frame, (0, 0)),
+            }
+        }
+    }
+
+    /// Queues newly arrived frames for the live stack, if it is running, and shows frames the
+    /// stack has finished aligning.
+    fn stack_frames(&mut self, frames: &[RawFrame]) {
+        if self.live_stack.enabled {
+            for frame in frames {
+                let frame = self.calibrate_for_display(frame.clone());
+                self.live_stack.push(&frame);
+            }
+        }
+        if self.live_stack.poll() {
+            self.display_dirty = true;
+        }
+    }
+
+    fn save_live_stack(&mut self, path: &str) {
+        let (Some(stack), Some(received)) = (self.live_stack.result(), self.live_stack.reference_time()) else {
+            return;
+        };
+        let mut cards = fits::frame_cards("Light Frame", self.exposure_seconds(), received, stack.bit_depth, self.cam_status.temperature);
+        cards.push(HeaderCard::new("NCOMBINE", HeaderValue::Int(self.live_stack.stacked() as i64), "number of frames combined"));
+        match fits::write_fits_f32(std::path::Path::new(path), &stack, &cards) {
+            Ok(()) => {
+                self.msg_list.push_back(format!("Saved stack of {} frames to {}", self.live_stack.stacked(), path));
+            }
+            Err(e) => self.dialog(DialogType::Error, &format!("Failed to save stack: {}", e)),
+        }
+    }
+
+    /// Converts a frame to PNG for the image viewer.
+    fn show_frame(&mut self, frame: &RawFrame) -> Result<(), String> {
+        // Generic_Image conversions...
+        let (mut data, rgb) = self.display.render(frame)?;
+        let out_channels = if rgb { 3 } else { 1 };
+        let mut color_space = if rgb { ColorSpace::Rgb } else { ColorSpace::Gray };
+
+        // Clipping is judged on the sensor's own values, not on a calibrated or stacked frame.
+        let raw = self.raw_frame.as_ref().filter(|r| r.width == frame.width && r.height == frame.height);
+        if let (true, Some(raw)) = (self.highlight_clipping, raw) {
+            // Paint clipped pixels into the image itself so they scale with it at any zoom.
+            let max = raw.max_value();
+            data = raw
+                .data
+                .chunks_exact(raw.channels)
+                .zip(data.chunks_exact(out_channels))
+                .flat_map(|(raw, px)| {
+                    if raw.iter().any(|&v| v >= max) {
+                        [255, 0, 0]
+                    } else if raw.iter().all(|&v| v == 0) {
+                        [0, 0, 255]
+                    } else if out_channels == 1 {
+                        [px[0]; 3]
+                    } else {
+                        [px[0], px[1], px[2]]
+                    }
+                })
+                .collect();
+            color_space = ColorSpace::Rgb;
+        }
+
+        let img = ImageRef::new(&mut data, frame.width, frame.height, color_space).map_err(|e| e.to_string())?;
+        let img = DynamicImageRef::from(img);
+
+        let img = DynamicImage::try_from(img).map_err(|e| e.to_string())?;
+
+        let mut data = Cursor::new(Vec::new());
+        img.write_to(&mut data, image::ImageFormat::Png).map_err(|e| e.to_string())?;
+        self.data = Some(data.into_inner().into());
+        Ok(())
+    }
+
+    /// Appends frames to the SER recording, if one is running.
+    fn record_frames(&mut self, frames: &[RawFrame], now: f64) {
+        if !self.ser_recorder.is_recording() {
+            return;
+        }
+        for frame in frames {
+            match self.ser_recorder.push(frame, now) {
+                Ok(false) => {}
+                Ok(true) => {
+                    self.stop_recording();
+                    break;
+                }
+                Err(e) => {
+                    self.stop_recording();
+                    self.dialog(DialogType::Error, &format!("Recording failed: {}", e));
+                    break;
+                }
+            }
+        }
+    }
+
+    fn stop_recording(&mut self) {
+        match self.ser_recorder.stop() {
+            Ok(msg) => {
+                self.msg_list.push_back(msg);
+            }
+            Err(e) => self.dialog(DialogType::Error, &format!("Failed to finalize recording: {}", e)),
+        }
+    }
+
+    /// Applies any control replies the server has sent since the last frame, received at GUI time
+    /// `now`. Replies from cameras in the background update their parked settings.
+    fn handle_replies(&mut self, now: f64) {
+        let mut replies = Vec::new();
+        for ws in self.connections.connections.iter_mut() {
+            for (source, reply) in std::mem::take(&mut ws.replies) {
+                replies.push((ws.uri.clone(), source, reply));
+            }
+        }
+        for (server, source, reply) in replies {
+            if let CameraReply::Cameras(list) = reply {
+                self.update_camera_list(&server, list);
+                continue;
+            }
+            let key = source.map(|id| CameraKey { server, id });
+            if let (Some(key), CameraReply::Status(status)) = (&key, &reply) {
+                if let Some(cam) = self.connected_cameras.get_mut(key) {
+                    cam.info.state = status.state;
+                    cam.info.temperature = status.temperature;
+                }
+            }
+            match key.filter(|k| self.active_camera.as_ref() != Some(k)).and_then(|k| self.connected_cameras.remove_entry(&k)) {
+                Some((key, mut cam)) => {
+                    self.swap_camera_settings(&mut cam.settings);
+                    self.apply_reply(reply, now);
+                    self.swap_camera_settings(&mut cam.settings);
+                    self.connected_cameras.insert(key, cam);
+                }
+                None => self.apply_reply(reply, now),
+            }
+        }
+    }
+
+    /// Applies one control reply to the active camera.
+    fn apply_reply(&mut self, reply: CameraReply, now: f64) {
+        match reply {
+            CameraReply::Status(status) => {
+                if let Some(t) = status.temperature {
+                    self.curr_cam_temp = t;
+                }
+                self.exposure_progress.update(&status, now);
+                self.cam_status = status;
+            }
+            CameraReply::Capabilities(caps) => {
+                if !self.sensor_presets_loaded {
+                    self.sensor_presets_loaded = true;
+                    match PresetLibrary::load(std::path::Path::new(&self.sensor_presets_path)) {
+                        Ok(presets) => self.sensor_presets = presets,
+                        Err(e) => {
+                            self.msg_list.push_back(format!("Failed to load sensor presets: {}", e));
+                        }
+                    }
+                }
+                if let Some(gain) = caps.gain {
+                    self.auto_exposure.min_gain = gain.min;
+                    self.auto_exposure.max_gain = gain.max;
+                }
+                self.sensor.set_capabilities(caps, &mut self.sensor_presets);
+            }
+            CameraReply::Aborted => {
+                self.exposure_progress.aborted();
+                self.msg_list.push_back("Exposure aborted".into());
+            }
+            CameraReply::Cameras(_) | CameraReply::Source(_) | CameraReply::Request(_) | CameraReply::Image(_) => {}
+        }
+    }
+
+    /// Brings the cameras listed for `server` in line with the server's list, selecting a camera
+    /// if none is active.
+    fn update_camera_list(&mut self, server: &str, list: Vec<CameraInfo>) {
+        self.connected_cameras.retain(|k, _| k.server != server || list.iter().any(|c| c.id == k.id));
+        for info in list {
+            let key = CameraKey { server: server.to_owned(), id: info.id.clone() };
+            match self.connected_cameras.get_mut(&key) {
+                Some(cam) => cam.info = info,
+                None => {
+                    let settings = CameraSettings::from_saved(&self.saved_settings);
+                    self.connected_cameras.insert(key, CamData { info, settings, texture: None, texture_dirty: true });
+                }
+            }
+        }
+        self.check_active_camera();
+    }
+
+    /// Drops every camera on `server`, after its connection closed.
+    fn forget_server(&mut self, server: &str) {
+        self.connected_cameras.retain(|k, _| k.server != server);
+        self.check_active_camera();
+    }
+
+    /// Clears the active camera if it has gone, and selects the first remaining one if none is
+    /// active.
+    fn check_active_camera(&mut self) {
+        if self.active_camera.as_ref().is_some_and(|k| !self.connected_cameras.contains_key(k)) {
+            self.active_camera = None;
+        }
+        if self.active_camera.is_none() {
+            if let Some(key) = self.connected_cameras.keys().min().cloned() {
+                self.select_camera(&key);
+            }
+        }
+    }
+
+    /// Exchanges the active camera's settings with `settings`.
+    fn swap_camera_settings(&mut self, settings: &mut CameraSettings) {
+        use std::mem::swap;
+        swap(&mut self.raw_frame, &mut settings.raw_frame);
+        swap(&mut self.exposure_slider, &mut settings.exposure_slider);
+        swap(&mut self.long_exp_checkbox, &mut settings.long_exp_checkbox);
+        swap(&mut self.auto_exp_checkbox, &mut settings.auto_exp_checkbox);
+        swap(&mut self.auto_exposure, &mut settings.auto_exposure);
+        swap(&mut self.sensor, &mut settings.sensor);
+        swap(&mut self.trigger, &mut settings.trigger);
+        swap(&mut self.exposure_progress, &mut settings.exposure_progress);
+        swap(&mut self.curr_cam_temp, &mut settings.curr_cam_temp);
+        swap(&mut self.cooler_status, &mut settings.cooler_status);
+        swap(&mut self.cooler_target, &mut settings.cooler_target);
+        swap(&mut self.color_space, &mut settings.color_space);
+        swap(&mut self.roi, &mut settings.roi);
+        swap(&mut self.roi_type, &mut settings.roi_type);
+        swap(&mut self.roi_enabled, &mut settings.roi_enabled);
+        swap(&mut self.binning, &mut settings.binning);
+        swap(&mut self.cam_status, &mut settings.cam_status);
+        swap(&mut self.calibration, &mut settings.calibration);
+        swap(&mut self.display, &mut settings.display);
+        swap(&mut self.overlays, &mut settings.overlays);
+    }
+
+    /// Makes camera `key` the active one, parking the current camera's settings.
+    fn select_camera(&mut self, key: &CameraKey) {
+        if self.active_camera.as_ref() == Some(key) || !self.connected_cameras.contains_key(key) {
+            return;
+        }
+        // Runs send their commands to whichever camera is active, so they must not follow a switch.
+        if self.capture_running() {
+            if self.active_camera.is_some() {
+                self.msg_list.push_back("Stop the running capture or recording to switch cameras.".into());
+                return;
+            }
+            self.stop_captures("camera disconnected");
+        }
+        if let Some(old) = self.active_camera.take() {
+            if let Some(mut cam) = self.connected_cameras.remove(&old) {
+                self.swap_camera_settings(&mut cam.settings);
+                cam.texture_dirty = true;
+                self.connected_cameras.insert(old, cam);
+            }
+        }
+        let mut cam = self.connected_cameras.remove(key).unwrap(); // checked above
+        self.swap_camera_settings(&mut cam.settings);
+        self.connected_cameras.insert(key.clone(), cam);
+        self.active_camera = Some(key.clone());
+        if let Some(ws) = self.connections.get_mut(&key.server) {
+            ws.select(&key.id);
+        }
+
+        // Stacks, focus history and a pending profile ROI belong to the previous camera.
+        self.pending_roi = None;
+        self.live_stack.reset();
+        self.focus.reset();
+        if let Some(frame) = &self.raw_frame {
+            self.img_width = frame.width as i32;
+            self.img_height = frame.height as i32;
+        }
+        self.display_dirty = true;
+    }
+
+    /// Whether a capture plan, time-lapse or SER recording is running on the active camera.
+    fn capture_running(&self) -> bool {
+        self.plan_runner.as_ref().is_some_and(|r| !r.is_finished())
+            || self.timelapse_run.as_ref().is_some_and(|r| !r.is_finished())
+            || self.ser_recorder.is_recording()
+    }
+
+    /// Stops every running capture plan, time-lapse and SER recording, giving `reason`.
+    fn stop_captures(&mut self, reason: &str) {
+        if let Some(runner) = self.plan_runner.as_mut().filter(|r| !r.is_finished()) {
+            runner.stop(reason);
+            self.msg_list.push_back(runner.status());
+        }
+        if let Some(run) = self.timelapse_run.as_mut().filter(|r| !r.is_finished()) {
+            run.stop();
+            self.msg_list.push_back(format!("Time-lapse stopped: {}. {}", reason, run.status(clock::unix_time())));
+        }
+        if self.ser_recorder.is_recording() {
+            self.stop_recording();
+        }
+    }
+
+    /// Advances a running capture plan, forwarding its requests to the server.
+    ///
+    /// `raw` are the frames that arrived since the last update. Light frames are calibrated with
+    /// the running step's settings before they are saved, if calibrating saved frames is enabled.
+    fn poll_capture_plan(&mut self, now: f64, raw: &[RawFrame]) {
+        let Some(runner) = &mut self.plan_runner else {
+            return;
+        };
+        if runner.is_finished() {
+            return;
+        }
+
+        let Some(ws) = self.connections.for_camera(self.active_camera.as_ref()) else {
+            runner.stop("server connection lost");
+            self.msg_list.push_back(runner.status());
+            return;
+        };
+
+        let calibrated: Vec<RawFrame>;
+        let frames = match runner.frame_info(self.cam_status.temperature) {
+            Some(info)
+                if runner.current_frame_type() == Some(FrameType::Light)
+                    && self.calibration.apply_to_saved
+                    && self.calibration.has_masters() =>
+            {
+                calibrated = raw.iter().map(|f| self.calibration.apply(f, &info)).collect();
+                &calibrated
+            }
+            _ => raw,
+        };
+        for request in runner.poll(now, self.cam_status.temperature, frames) {
+            if let Some(id) = ws.send(&request) {
+                let exposing = self.cam_status.state != CameraState::Idle;
+                runner.requested(FrameRequest::new(id, ws.echoes_requests, exposing));
+            }
+        }
+
+        if runner.is_finished() {
+            self.msg_list.push_back(runner.status());
+        }
+    }
+
+    /// Requests one frame from every camera selected for synchronized capture, starting a new
+    /// group at GUI time `now`.
+    fn start_capture_group(&mut self, now: f64) {
+        let mut members = Vec::new();
+        for key in &self.sync_capture.members {
+            let Some(cam) = self.connected_cameras.get(key) else {
+                continue;
+            };
+            let exposure = if self.active_camera.as_ref() == Some(key) {
+                self.exposure_seconds()
+            } else {
+                cam.settings.exposure_seconds()
+            };
+            members.push(GroupMember {
+                key: key.clone(),
+                label: format!("{} {}", cam.info.model, cam.info.serial),
+                exposure,
+                temperature: cam.info.temperature,
+                request: None,
+                frame: None,
+            });
+        }
+
+        // Send every request in the same update so the exposures start as close together as possible.
+        for m in &mut members {
+            if let Some(ws) = self.connections.get_mut(&m.key.server) {
+                let exposing = self.connected_cameras.get(&m.key).is_some_and(|c| c.info.state != CameraState::Idle);
+                ws.select(&m.key.id);
+                m.request = ws.send(&ServerRequest::Image).map(|id| FrameRequest::new(id, ws.echoes_requests, exposing));
+            }
+        }
+        if let Some(key) = &self.active_camera {
+            if let Some(ws) = self.connections.get_mut(&key.server) {
+                ws.select(&key.id);
+            }
+        }
+
+        let id = self.sync_capture.next_id;
+        self.sync_capture.next_id += 1;
+        self.msg_list.push_back(format!("Group {}: triggered {} cameras", id, members.len()));
+        self.sync_capture.run = Some(GroupRun::new(id, members, now));
+    }
+
+    /// Finishes the running capture group once its frames are in or it times out.
+    fn poll_capture_group(&mut self, now: f64) {
+        let Some(run) = &mut self.sync_capture.run else {
+            return;
+        };
+        let result = run.poll(now, std::path::Path::new(&self.sync_capture.output_dir));
+        let missed = !run.missed().is_empty();
+        match result {
+            // A camera that missed the group needs attention before the next one is taken.
+            Some(Ok(msg)) if missed => self.dialog(DialogType::Warn, &msg),
+            Some(Ok(msg)) => {
+                self.msg_list.push_back(msg);
+            }
+            Some(Err(e)) => self.dialog(DialogType::Error, &format!("Failed to save capture group: {}", e)),
+            None => {}
+        }
+    }
+
+    /// Model of the active camera, from the camera list or its reported capabilities.
+    fn active_model(&self) -> Option<String> {
+        self.active_camera.as_ref()
+            .and_then(|k| self.connected_cameras.get(k))
+            .map(|cam| cam.info.model.clone())
+            .or_else(|| self.sensor.capabilities.as_ref().map(|c| c.model.clone()))
+    }
+
+    /// The active camera's configuration, as a profile would record it.
+    fn profile_settings(&self) -> ProfileSettings {
+        ProfileSettings {
+            exposure: self.exposure_seconds(),
+            gain: self.sensor.settings.gain,
+            offset: self.sensor.settings.offset,
+            binning: self.binning,
+            roi: self.roi_enabled.then_some(self.roi),
+            roi_centered: self.roi_type == ROITypes::Center,
+            format: match self.color_space {
+                ColorSpaceOpt::Gray => CaptureFormat::Gray,
+                ColorSpaceOpt::Bayer => CaptureFormat::Bayer,
+                ColorSpaceOpt::Rgb => CaptureFormat::Rgb,
             },
+            cooler_target: (self.cooler_status == CoolerStatus::On).then_some(self.cooler_target),
+        }
+    }
+
+    /// Configures the active camera from a profile and sends the settings to it.
+    fn apply_profile(&mut self, settings: ProfileSettings) {
+        // Gain and offset are clamped to the camera's limits, and skipped if it has no such control.
+        let caps = self.sensor.capabilities.as_ref();
+        let gain = caps.map_or(Some(settings.gain), |c| c.gain.map(|r| r.clamp(settings.gain)));
+        let offset = caps.map_or(Some(settings.offset), |c| c.offset.map(|r| r.clamp(settings.offset)));
+
+        self.set_exposure_slider(settings.exposure);
+        if let Some(gain) = gain {
+            self.sensor.settings.gain = gain;
+        }
+        if let Some(offset) = offset {
+            self.sensor.settings.offset = offset;
+        }
+        self.binning = settings.binning;
+        self.roi_enabled = settings.roi.is_some();
+        if let Some(roi) = settings.roi {
+            self.roi = roi;
+        }
+        self.roi_type = if settings.roi_centered { ROITypes::Center } else { ROITypes::Corner };
+        self.color_space = match settings.format {
+            CaptureFormat::Gray => ColorSpaceOpt::Gray,
+            CaptureFormat::Bayer => ColorSpaceOpt::Bayer,
+            CaptureFormat::Rgb => ColorSpaceOpt::Rgb,
+        };
+        match settings.cooler_target {
+            Some(t) => {
+                self.cooler_status = CoolerStatus::On;
+                self.cooler_target = t;
+            }
+            None => self.cooler_status = CoolerStatus::Off,
+        }
+        self.display_dirty = true;
+        self.pending_roi = None;
+
+        let Some(ws) = self.connections.for_camera(self.active_camera.as_ref()) else {
+            return;
+        };
+        // The ROI is sent in pixels of the binned sensor, so start from the full sensor and send
+        // it once a frame at the new binning shows how large that is.
+        let mut commands = vec![
+            CameraCommand::SetExposure(settings.exposure),
+            CameraCommand::SetBinning(settings.binning),
+            CameraCommand::SetRoi(None),
+            CameraCommand::SetFormat(settings.format),
+            CameraCommand::SetCoolerTarget(settings.cooler_target),
+        ];
+        commands.extend(gain.map(CameraCommand::SetGain));
+        commands.extend(offset.map(CameraCommand::SetOffset));
+        for command in commands {
+            ws.send(&ServerRequest::Command(command));
+        }
+        if settings.roi.is_some() {
+            if let Some(id) = ws.send(&ServerRequest::Image) {
+                let exposing = self.cam_status.state != CameraState::Idle;
+                self.pending_roi = Some(FrameRequest::new(id, ws.echoes_requests, exposing));
+            }
+        }
+    }
+
+    /// Sends the ROI of an applied profile once the frame showing the new sensor size arrives.
+    fn send_pending_roi(&mut self, frames: &[RawFrame]) {
+        let Some(request) = &mut self.pending_roi else {
+            return;
+        };
+        let Some(frame) = frames.iter().find(|f| request.accepts(f)) else {
+            return;
+        };
+        self.pending_roi = None;
+        let roi = self.roi_region(frame).map(|r| [r.x as u32, r.y as u32, r.width as u32, r.height as u32]);
+        if let Some(ws) = self.connections.for_camera(self.active_camera.as_ref()) {
+            ws.send(&ServerRequest::Command(CameraCommand::SetRoi(roi)));
+        }
+    }
+
+    /// The exposure currently selected on the exposure slider, in seconds.
+    fn exposure_seconds(&self) -> f64 {
+        if self.long_exp_checkbox {
+            self.exposure_slider as f64
+        } else {
+            self.exposure_slider as f64 / 1000.0
+        }
+    }
+
+    /// Moves the exposure slider to `seconds`, switching between the ms and s ranges as needed.
+    fn set_exposure_slider(&mut self, seconds: f64) {
+        self.long_exp_checkbox = seconds > 5.0;
+        self.exposure_slider = if self.long_exp_checkbox { seconds as f32 } else { (seconds * 1000.0) as f32 };
+    }
+
+    /// Lets the auto-exposure controller adjust exposure and gain from the latest frame.
+    ///
+    /// Capture plans and time-lapses set their own exposures, so the controller pauses while
+    /// either is running.
+    fn run_auto_exposure(&mut self, frame: Option<&RawFrame>) {
+        if !self.auto_exp_checkbox {
+            self.auto_exposure.reset();
+            return;
+        }
+        let busy = self.plan_runner.as_ref().is_some_and(|r| !r.is_finished())
+            || self.timelapse_run.as_ref().is_some_and(|r| !r.is_finished());
+        let (Some(frame), false) = (frame, busy) else {
+            return;
+        };
+        if self.connections.for_camera(self.active_camera.as_ref()).is_none() {
+            return;
+        }
+
+        let measured = match self.roi_region(frame) {
+            Some(region) => frame.crop(&region),
+            None => frame.clone(),
+        };
+        let Some(request) = self.auto_exposure.update(&measured, self.exposure_seconds(), self.sensor.settings.gain) else {
+            return;
+        };
+        self.set_exposure_slider(request.exposure);
+        let ws = self.connections.for_camera(self.active_camera.as_ref()).unwrap(); // checked above
+        ws.send(&ServerRequest::Command(CameraCommand::SetExposure(request.exposure)));
+        if let Some(gain) = request.gain {
+            self.sensor.settings.gain = gain;
+            ws.send(&ServerRequest::Command(CameraCommand::SetGain(gain)));
+        }
+    }
+
+    /// Advances a running time-lapse, forwarding its requests to the server.
+    fn poll_timelapse(&mut self, frames: &[RawFrame]) {
+        let Some(run) = &mut self.timelapse_run else {
+            return;
+        };
+        if run.is_finished() {
+            return;
+        }
+
+        let now = clock::unix_time();
+        let Some(ws) = self.connections.for_camera(self.active_camera.as_ref()) else {
+            run.stop();
+            self.msg_list.push_back(format!("Time-lapse stopped: server connection lost. {}", run.status(now)));
+            return;
+        };
+
+        match run.poll(now, self.cam_status.temperature, frames) {
+            Ok(requests) => {
+                for request in requests {
+                    if let Some(id) = ws.send(&request) {
+                        let exposing = self.cam_status.state != CameraState::Idle;
+                        run.requested(FrameRequest::new(id, ws.echoes_requests, exposing));
+                    }
+                }
+            }
+            Err(e) => {
+                run.stop();
+                self.dialog(DialogType::Error, &format!("Time-lapse stopped, failed to save frame: {}", e));
+                return;
+            }
         }
 
+        if run.is_finished() {
+            self.msg_list.push_back(run.status(now));
+        }
+    }
+
+    fn ui_capture_plan(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_capture_plan;
+        egui::Window::new("Capture Plan")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                let running = self.plan_runner.as_ref().is_some_and(|r| !r.is_finished());
+
+                ui.add_enabled_ui(!running, |ui| {
+                    self.capture_plan.ui(ui);
+                });
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("Plan File");
+                    ui.text_edit_singleline(&mut self.plan_path);
+                    if ui.add_enabled(!running, egui::Button::new("Load")).clicked() {
+                        match CapturePlan::load(std::path::Path::new(&self.plan_path)) {
+                            Ok(plan) => self.capture_plan = plan,
+                            Err(e) => self.dialog(DialogType::Error, &format!("Failed to load plan: {}", e)),
+                        }
+                    }
+                    if ui.button("Save").clicked() {
+                        if let Err(e) = self.capture_plan.save(std::path::Path::new(&self.plan_path)) {
+                            self.dialog(DialogType::Error, &format!("Failed to save plan: {}", e));
+                        }
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Output Directory");
+                    ui.add_enabled(!running, egui::TextEdit::singleline(&mut self.plan_output_dir));
+                });
+
+                ui.horizontal(|ui| {
+                    if running {
+                        if ui.button("Stop").clicked() {
+                            if let Some(runner) = &mut self.plan_runner {
+                                runner.stop("stopped by user");
+                                self.msg_list.push_back(runner.status());
+                            }
+                        }
+                    } else if ui
+                        .add_enabled(self.camera_connected() && !self.capture_plan.steps.is_empty(), egui::Button::new("Run"))
+                        .on_disabled_hover_text("Connect to a server and add at least one step.")
+                        .clicked()
+                    {
+                        self.plan_runner = Some(PlanRunner::new(
+                            self.capture_plan.clone(),
+                            self.plan_output_dir.clone().into(),
+                        ));
+                    }
+                    ui.label(format!("{} frames total", self.capture_plan.total_frames()));
+                });
+
+                if let Some(runner) = &self.plan_runner {
+                    ui.add(egui::ProgressBar::new(runner.progress()).show_percentage());
+                    ui.label(runner.status());
+                }
+            });
+        self.show_capture_plan = open;
     }
-    
+
+    fn ui_master_builder(&mut self, ctx: &egui::Context) {
+        match self.master_builder.poll() {
+            Some(Ok(msg)) => {
+                self.msg_list.push_back(msg);
+            }
+            Some(Err(e)) => self.dialog(DialogType::Error, &format!("Failed to build master: {}", e)),
+            None => {}
+        }
+
+        let mut open = self.show_master_builder;
+        egui::Window::new("Master Builder")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                match self.master_builder.ui(ui, self.raw_frame.is_some()) {
+                    Some(BuilderAction::AddCurrent) => {
+                        if let Some(frame) = self.raw_frame.clone() {
+                            let label = clock::format_utc(frame.timestamp);
+                            self.master_builder.add(frame, self.frame_info(), label);
+                        }
+                    }
+                    Some(BuilderAction::AddFile(path)) => {
+                        match MasterFrame::load(std::path::Path::new(&path)) {
+                            Ok(m) => {
+                                self.master_builder.add(m.frame.to_raw(), m.info, m.source);
+                            }
+                            Err(e) => self.dialog(DialogType::Error, &format!("Failed to load {}: {}", path, e)),
+                        }
+                    }
+                    Some(BuilderAction::UseMaster) => {
+                        if let Some(master) = self.master_builder.result.clone() {
+                            self.calibration.set_master(self.master_builder.kind, master);
+                            self.display_dirty = true;
+                        }
+                    }
+                    None => {}
+                }
+            });
+        self.show_master_builder = open;
+    }
+
+    fn ui_sync_capture(&mut self, ctx: &egui::Context) {
+        let mut cameras: Vec<(CameraKey, String)> = self.connected_cameras.iter()
+            .map(|(k, cam)| (k.clone(), format!("{} {} ({})", cam.info.model, cam.info.serial, k.server)))
+            .collect();
+        cameras.sort();
+
+        let mut open = self.show_sync_capture;
+        let mut capture = false;
+        egui::Window::new("Synchronized Capture")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                capture = self.sync_capture.ui(ui, &cameras);
+            });
+        self.show_sync_capture = open;
+        if capture {
+            self.start_capture_group(ctx.input(|i| i.time));
+        }
+    }
+
+    /// Opens a connection to `uri`, reporting failures in a dialog.
+    fn connect_to(&mut self, uri: &str) {
+        match self.connections.connect(uri, &self.ctx) {
+            Ok(()) => self.connect_dialog.history.used(uri),
+            Err(e) => self.dialog(DialogType::Error, &format!("Failed to connect to {}: {}", uri, e)),
+        }
+    }
+
+    /// Shows the connect dialog and starts looking for servers.
+    fn open_connect_dialog(&mut self, now: f64) {
+        self.show_connect_dialog = true;
+        self.connect_dialog.search(now);
+    }
+
+    fn ui_connect_dialog(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_connect_dialog;
+        let mut connect = None;
+        egui::Window::new("Connect to Server")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                connect = self.connect_dialog.ui(ui, &mut self.uri, ctx.input(|i| i.time));
+            });
+        self.show_connect_dialog = open;
+        if let Some(uri) = connect {
+            self.show_connect_dialog = false;
+            self.connect_to(&uri);
+        }
+    }
+
     fn ui_developer_controls(&mut self, ctx: &egui::Context) {
         // Debug Controls Window for Developer Use Only
         egui::Window::new("Developer Controls").show(ctx, |ui| {
@@ -479,32 +1444,38 @@ impl GenCamGUI {
                 }
             });
 
-            ui.horizontal(|ui| {
-                ui.label("Server Connection:");
-                let mut disconnect = false;
-                if let Some(ws) = &mut self.ws {
-                    ui.horizontal(|ui| {
-                        ui.label("WebSocket URI: ");
-                        ui.label(&self.uri);
-                        if ui.button("Disconnect").clicked() {
-                            disconnect = true;
-                        }
+            ui.label("Server Connections:");
+            let mut disconnect = None;
+            for ws in self.connections.connections.iter_mut() {
+                ui.horizontal(|ui| {
+                    ui.label(&ws.uri);
+                    ui.label(match &ws.status {
+                        ConnectionStatus::Connecting => "Connecting…".to_owned(),
+                        ConnectionStatus::Connected => "Connected".to_owned(),
+                        ConnectionStatus::Closed => "Closed".to_owned(),
+                        ConnectionStatus::Error(e) => format!("Error: {}", e),
                     });
-                    ws.ui(ui);
-                } else {
-                    ui.horizontal(|ui| {
-                        ui.label("WebSocket URI: ");
-                        ui.text_edit_singleline(&mut self.uri);
-                        if ui.button("Connect").clicked() {
-                            self.ws = WsBackend::connect(&self.uri, &self.ctx);
-                        }
-                    }); 
+                    if ui.button("Disconnect").clicked() {
+                        disconnect = Some(ws.uri.clone());
+                    }
+                });
+                ws.ui(ui);
+            }
+            ui.horizontal(|ui| {
+                ui.label("WebSocket URI: ");
+                ui.text_edit_singleline(&mut self.uri);
+                if ui.button("Connect").clicked() {
+                    self.connect_to(&self.uri.clone());
                 }
-                if disconnect {
-                    self.ws.as_mut().unwrap().close(); // safe to unwrap since disconnect is only true if ws is Some
-                    self.ws = None;
+                if ui.button("Find Servers…").clicked() {
+                    self.open_connect_dialog(ui.input(|i| i.time));
                 }
             });
+            if let Some(uri) = disconnect {
+                self.connections.disconnect(&uri);
+                self.forget_server(&uri);
+                self.msg_list.push_back(format!("Disconnected from {}", uri));
+            }
         });
     }
 
@@ -519,6 +1490,26 @@ impl GenCamGUI {
                 ui.horizontal(|ui| {
                     menu::bar(ui, |ui| {
                         ui.menu_button("File", |ui| {
+                            if ui.button("Connect…").clicked() {
+                                self.open_connect_dialog(ui.input(|i| i.time));
+                                ui.close_menu();
+                            }
+                            let recent = self.connect_dialog.history.recent.clone();
+                            ui.add_enabled_ui(!recent.is_empty(), |ui| {
+                                ui.menu_button("Recent Servers", |ui| {
+                                    for uri in recent {
+                                        let text = match self.connect_dialog.history.nickname(&uri) {
+                                            Some(nickname) => format!("{} ({})", nickname, uri),
+                                            None => uri.clone(),
+                                        };
+                                        if ui.button(text).clicked() {
+                                            self.connect_to(&uri);
+                                            ui.close_menu();
+                                        }
+                                    }
+                                });
+                            });
+                            ui.separator();
                             if ui.button("Open").clicked() {
                                 // …
                             }
@@ -528,6 +1519,20 @@ impl GenCamGUI {
                                 // …
                             }
                         });
+                        ui.menu_button("Capture", |ui| {
+                            if ui.button("Capture Plan…").clicked() {
+                                self.show_capture_plan = true;
+                                ui.close_menu();
+                            }
+                            if ui.button("Master Builder…").clicked() {
+                                self.show_master_builder = true;
+                                ui.close_menu();
+                            }
+                            if ui.button("Synchronized Capture…").clicked() {
+                                self.show_sync_capture = true;
+                                ui.close_menu();
+                            }
+                        });
                         ui.menu_button("View", |ui| match self.dark_mode {
                             true => {
                                 if ui.button("Switch to Light Mode").clicked() {
@@ -575,14 +1580,118 @@ impl GenCamGUI {
             .default_width(ctx.available_rect().width() / (6.0 / w_scale))
             .show(ctx, |ui| {
                 ui.set_enabled(!self.modal_active);
-                ui.label("Window Controls");
+                ui.label("Acquisition Controls");
                 ui.separator(); // Placeholder to enable dragging (expands to fill).
 
-                ui.label(format!(
-                    "{:?}",
-                    (w_view / (8.0 / w_scale))..=(w_view / (4.0 / w_scale))
-                ));
-                ui.label("Left Panel");
+                self.frame.show(ui, |ui| {
+                    egui::CollapsingHeader::new("Cameras")
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            if self.connected_cameras.is_empty() {
+                                ui.label(if self.connections.is_empty() {
+                                    "Connect to a server to list its cameras."
+                                } else {
+                                    "No server has reported any cameras."
+                                });
+                                return;
+                            }
+
+                            // Runs send their commands to whichever camera is active, so keep it fixed.
+                            let busy = self.capture_running();
+                            let mut keys: Vec<&CameraKey> = self.connected_cameras.keys().collect();
+                            keys.sort();
+                            let mut selected = None;
+                            ui.add_enabled_ui(!busy, |ui| {
+                                egui::Grid::new("CameraList").striped(true).show(ui, |ui| {
+                                    let mut server = None;
+                                    for key in keys {
+                                        if server != Some(&key.server) {
+                                            server = Some(&key.server);
+                                            ui.strong(&key.server);
+                                            ui.end_row();
+                                        }
+                                        let info = &self.connected_cameras[key].info;
+                                        let active = self.active_camera.as_ref() == Some(key);
+                                        if ui.selectable_label(active, &info.model).clicked() {
+                                            selected = Some(key.clone());
+                                        }
+                                        ui.label(&info.serial);
+                                        ui.label(format!("{:?}", info.state));
+                                        ui.label(info.temperature.map_or("—".to_owned(), |t| format!("{:.1} °C", t)));
+                                        ui.end_row();
+                                    }
+                                });
+                            }).response.on_disabled_hover_text("Stop the running capture or recording to switch cameras.");
+                            if let Some(key) = selected {
+                                self.select_camera(&key);
+                            }
+                        });
+                });
+
+                self.frame.show(ui, |ui| {
+                    egui::CollapsingHeader::new("Time-Lapse")
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            let running = self.timelapse_run.as_ref().is_some_and(|r| !r.is_finished());
+
+                            ui.add_enabled_ui(!running, |ui| {
+                                self.timelapse.ui(ui);
+                            });
+
+                            ui.horizontal(|ui| {
+                                if running {
+                                    if ui.button("Stop").clicked() {
+                                        if let Some(run) = &mut self.timelapse_run {
+                                            run.stop();
+                                            self.msg_list.push_back(run.status(clock::unix_time()));
+                                        }
+                                    }
+                                } else if ui
+                                    .add_enabled(self.camera_connected(), egui::Button::new("Start"))
+                                    .on_disabled_hover_text("Connect to a server first.")
+                                    .clicked()
+                                {
+                                    self.timelapse_run = Some(TimeLapseRun::new(
+                                        self.timelapse.clone(),
+                                        clock::unix_time(),
+                                        self.exposure_seconds(),
+                                    ));
+                                }
+                            });
+
+                            if let Some(run) = &self.timelapse_run {
+                                ui.add(egui::Label::new(run.status(clock::unix_time())).wrap());
+                            }
+                        });
+                });
+
+                self.frame.show(ui, |ui| {
+                    egui::CollapsingHeader::new("Focus Assistant")
+                        .default_open(false)
+                        .show(ui, |ui| {
+                            self.focus.ui(ui);
+                        });
+                });
+
+                self.frame.show(ui, |ui| {
+                    egui::CollapsingHeader::new("Video Recording")
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            self.ser_recorder.ui(ui);
+
+                            if self.ser_recorder.is_recording() {
+                                if ui.button("Stop Recording").clicked() {
+                                    self.stop_recording();
+                                }
+                            } else if ui
+                                .add_enabled(self.camera_connected(), egui::Button::new("Record SER"))
+                                .on_disabled_hover_text("Connect to a server first.")
+                                .clicked()
+                            {
+                                self.ser_recorder.start(ui.input(|i| i.time));
+                            }
+                        });
+                });
             });
         }
         
@@ -616,6 +1725,8 @@ impl GenCamGUI {
                                     ui.checkbox(&mut self.auto_exp_checkbox, "Auto");
                                 });
 
+                                self.auto_exposure.ui(ui);
+
                                 ui.horizontal(|ui| {
                                     ui.add_enabled_ui(!self.auto_exp_checkbox, |ui| {
                                         ui.spacing_mut().slider_width = w_view / (6.0 / w_scale);
@@ -627,6 +1738,29 @@ impl GenCamGUI {
                                     });
                                 });
 
+                                let now = ctx.input(|i| i.time);
+                                let exposure = self.exposure_seconds();
+                                let action = ui.add_enabled_ui(self.camera_connected(), |ui| {
+                                    self.trigger.ui(ui, now, self.cam_status.armed, exposure)
+                                }).inner;
+                                if let (Some(action), Some(ws)) = (action, self.connections.for_camera(self.active_camera.as_ref())) {
+                                    let command = match action {
+                                        TriggerAction::SetMode(mode) => CameraCommand::SetTriggerMode(mode),
+                                        TriggerAction::Fire => CameraCommand::SoftwareTrigger,
+                                        TriggerAction::StartBulb(seconds) => CameraCommand::StartBulb(seconds),
+                                        TriggerAction::StopBulb => CameraCommand::StopExposure,
+                                    };
+                                    ws.send(&ServerRequest::Command(command));
+                                }
+
+                                if self.exposure_progress.ui(ui, now) {
+                                    if let Some(ws) = self.connections.for_camera(self.active_camera.as_ref()) {
+                                        ws.abort();
+                                        self.exposure_progress.abort();
+                                        self.trigger.bulb_run = None;
+                                    }
+                                }
+
                                 ui.horizontal(|ui| {
                                     // ui.label("Options");
                                     if ui.button("Options").clicked() {
@@ -647,6 +1781,32 @@ impl GenCamGUI {
                             });
                     });
 
+                    self.frame.show(ui, |ui| {
+                        egui::CollapsingHeader::new("Sensor Controls")
+                            .default_open(true)
+                            .show(ui, |ui| {
+                                ui.add_visible_ui(false, |ui| {
+                                    ui.separator();
+                                });
+
+                                match self.sensor.ui(ui, &mut self.sensor_presets) {
+                                    Some(SensorAction::Send(commands)) => {
+                                        if let Some(ws) = self.connections.for_camera(self.active_camera.as_ref()) {
+                                            for command in commands {
+                                                ws.send(&ServerRequest::Command(command));
+                                            }
+                                        }
+                                    }
+                                    Some(SensorAction::PresetsChanged) => {
+                                        if let Err(e) = self.sensor_presets.save(std::path::Path::new(&self.sensor_presets_path)) {
+                                            self.dialog(DialogType::Error, &format!("Failed to save sensor presets: {}", e));
+                                        }
+                                    }
+                                    None => {}
+                                }
+                            });
+                    });
+
                     self.frame.show(ui, |ui| {
                         egui::CollapsingHeader::new("Thermal Controls")
                             .default_open(true)
@@ -672,7 +1832,7 @@ impl GenCamGUI {
 
                                 ui.horizontal(|ui| {
                                     ui.label("Target");
-                                    ui.add(egui::Slider::new(&mut self.exposure_slider, self.min_cam_temp..=self.max_cam_temp).suffix(" °C"));
+                                    ui.add(egui::Slider::new(&mut self.cooler_target, self.min_cam_temp..=self.max_cam_temp).suffix(" °C"));
                                 });
                             });
                     });
@@ -703,6 +1863,107 @@ impl GenCamGUI {
                             });
                     });
 
+                    self.frame.show(ui, |ui| {
+                        egui::CollapsingHeader::new("Calibration")
+                            .default_open(false)
+                            .show(ui, |ui| {
+                                ui.add_visible_ui(false, |ui| {
+                                    ui.separator();
+                                });
+
+                                let enabled = self.calibration.enabled;
+                                let action = self.calibration.ui(ui, self.raw_frame.is_some());
+                                if enabled != self.calibration.enabled {
+                                    self.display_dirty = true;
+                                }
+                                match action {
+                                    Some(CalibrationAction::Load(kind, path)) => {
+                                        match MasterFrame::load(std::path::Path::new(&path)) {
+                                            Ok(master) => {
+                                                self.calibration.set_master(kind, master);
+                                                self.display_dirty = true;
+                                            }
+                                            Err(e) => self.dialog(DialogType::Error, &format!("Failed to load master {:?}: {}", kind, e)),
+                                        }
+                                    }
+                                    Some(CalibrationAction::UseCurrent(kind)) => {
+                                        if let Some(frame) = self.raw_frame.clone() {
+                                            let master = MasterFrame {
+                                                source: format!("Captured {}", clock::format_utc(frame.timestamp)),
+                                                frame: FloatFrame::from(&frame),
+                                                info: self.frame_info(),
+                                            };
+                                            self.calibration.set_master(kind, master);
+                                            self.display_dirty = true;
+                                        }
+                                    }
+                                    None => {}
+                                }
+                            });
+                    });
+
+                    self.frame.show(ui, |ui| {
+                        egui::CollapsingHeader::new("Live Stack")
+                            .default_open(false)
+                            .show(ui, |ui| {
+                                ui.add_visible_ui(false, |ui| {
+                                    ui.separator();
+                                });
+
+                                let enabled = self.live_stack.enabled;
+                                let action = self.live_stack.ui(ui);
+                                if enabled != self.live_stack.enabled {
+                                    self.display_dirty = true;
+                                }
+                                match action {
+                                    Some(LiveStackAction::Save(path)) => self.save_live_stack(&path),
+                                    None => {}
+                                }
+                            });
+                    });
+
+                    self.frame.show(ui, |ui| {
+                        egui::CollapsingHeader::new("Star Detection")
+                            .default_open(false)
+                            .show(ui, |ui| {
+                                ui.add_visible_ui(false, |ui| {
+                                    ui.separator();
+                                });
+
+                                let settings = (self.star_analysis.enabled, self.star_analysis.sigma);
+                                self.star_analysis.ui(ui);
+                                if settings != (self.star_analysis.enabled, self.star_analysis.sigma) {
+                                    self.display_dirty = true;
+                                }
+                            });
+                    });
+
+                    self.frame.show(ui, |ui| {
+                        egui::CollapsingHeader::new("Display")
+                            .default_open(false)
+                            .show(ui, |ui| {
+                                ui.add_visible_ui(false, |ui| {
+                                    ui.separator();
+                                });
+
+                                if self.display.ui(ui) {
+                                    self.display_dirty = true;
+                                }
+                            });
+                    });
+
+                    self.frame.show(ui, |ui| {
+                        egui::CollapsingHeader::new("Overlays")
+                            .default_open(false)
+                            .show(ui, |ui| {
+                                ui.add_visible_ui(false, |ui| {
+                                    ui.separator();
+                                });
+
+                                self.overlays.ui(ui);
+                            });
+                    });
+
                     self.frame.show(ui, |ui| {
                         egui::CollapsingHeader::new("File Saving")
                             .default_open(true)
@@ -768,16 +2029,70 @@ impl GenCamGUI {
 
                                 ui.horizontal(|ui| {
                                     ui.label("Binning");
-                                    egui::ComboBox::from_id_source("CoolerStatus")
-                                        .selected_text(format!("{:?}", self.cooler_status))
+                                    egui::ComboBox::from_id_source("Binning")
+                                        .selected_text(format!("{}x{}", self.binning, self.binning))
                                         .show_ui(ui, |ui| {
-                                            ui.selectable_value(&mut self.cooler_status, CoolerStatus::On, "On");
-                                            ui.selectable_value(&mut self.cooler_status, CoolerStatus::Off, "Off");
+                                            for b in 1..=4 {
+                                                ui.selectable_value(&mut self.binning, b, format!("{}x{}", b, b));
+                                            }
                                     });
                                 });
                             });
                     });
 
+                    self.frame.show(ui, |ui| {
+                        egui::CollapsingHeader::new("Camera Profiles")
+                            .default_open(false)
+                            .show(ui, |ui| {
+                                ui.add_visible_ui(false, |ui| {
+                                    ui.separator();
+                                });
+
+                                let Some(model) = self.active_model() else {
+                                    ui.label("Waiting for the camera to report its model.");
+                                    return;
+                                };
+                                if !self.camera_profiles_loaded {
+                                    self.camera_profiles_loaded = true;
+                                    match ProfileLibrary::load(std::path::Path::new(&self.camera_profiles_path)) {
+                                        Ok(profiles) => self.camera_profiles = profiles,
+                                        Err(e) => {
+                                            self.msg_list.push_back(format!("Failed to load camera profiles: {}", e));
+                                        }
+                                    }
+                                }
+
+                                let current = self.profile_settings();
+                                match self.profile_manager.ui(ui, &mut self.camera_profiles, &model, &current) {
+                                    Some(ProfileAction::Apply(settings)) => self.apply_profile(settings),
+                                    Some(ProfileAction::Changed) => {
+                                        if let Err(e) = self.camera_profiles.save(std::path::Path::new(&self.camera_profiles_path)) {
+                                            self.dialog(DialogType::Error, &format!("Failed to save camera profiles: {}", e));
+                                        }
+                                    }
+                                    Some(ProfileAction::Export(profiles, path)) => {
+                                        match camera_profile::export(&profiles, std::path::Path::new(&path)) {
+                                            Ok(()) => {
+                                                self.msg_list.push_back(format!("Exported {} profiles to {}", profiles.len(), path));
+                                            }
+                                            Err(e) => self.dialog(DialogType::Error, &format!("Failed to export profiles: {}", e)),
+                                        }
+                                    }
+                                    Some(ProfileAction::Import(path)) => {
+                                        let result = self.camera_profiles.import(std::path::Path::new(&path))
+                                            .and_then(|n| self.camera_profiles.save(std::path::Path::new(&self.camera_profiles_path)).map(|_| n));
+                                        match result {
+                                            Ok(n) => {
+                                                self.msg_list.push_back(format!("Imported {} profiles from {}", n, path));
+                                            }
+                                            Err(e) => self.dialog(DialogType::Error, &format!("Failed to import profiles: {}", e)),
+                                        }
+                                    }
+                                    None => {}
+                                }
+                            });
+                    });
+
                 });
 
                 ui.set_enabled(!self.modal_active);
@@ -805,21 +2120,18 @@ impl GenCamGUI {
 
                         ui.label("Events");
 
-                        match &self.ws {
-                            None => {
-                                ui.label("No websocket connection.");
-                            }
-                            Some(_) => {
-                                let events_list = self.ws.as_ref().unwrap().events.clone(); 
-                                for event in events_list.iter() {   
-                                    match event {
-                                        WsEvent::Message(WsMessage::Binary(data)) => {
-                                            let pkt: GenCamPacket = serde_json::from_slice(data).expect("Failed to deserialize packet.");
-                                            ui.add(egui::Label::new(format!("{:?}", pkt)).truncate());
-                                        }
-                                        _ => {
-                                            ui.add(egui::Label::new(format!("{:?}", event.clone())).truncate());
-                                        }
+                        if self.connections.is_empty() {
+                            ui.label("No websocket connection.");
+                        }
+                        for ws in self.connections.connections.iter() {
+                            for event in ws.events.iter() {
+                                match event {
+                                    WsEvent::Message(WsMessage::Binary(data)) => {
+                                        let pkt: GenCamPacket = serde_json::from_slice(data).expect("Failed to deserialize packet.");
+                                        ui.add(egui::Label::new(format!("{}: {:?}", ws.uri, pkt)).truncate());
+                                    }
+                                    _ => {
+                                        ui.add(egui::Label::new(format!("{}: {:?}", ws.uri, event)).truncate());
                                     }
                                 }
                             }
@@ -829,6 +2141,215 @@ impl GenCamGUI {
             });
     }
 
+    /// Converts a screen position over the image drawn in `rect` to frame pixel coordinates.
+    fn screen_to_image(&self, rect: egui::Rect, pos: egui::Pos2) -> Option<(f32, f32)> {
+        let frame = self.raw_frame.as_ref()?;
+        let x = (pos.x - rect.min.x) / rect.width() * frame.width as f32;
+        let y = (pos.y - rect.min.y) / rect.height() * frame.height as f32;
+        Some((x.clamp(0.0, frame.width as f32), y.clamp(0.0, frame.height as f32)))
+    }
+
+    /// Handles clicking and dragging on the image, which draws a statistics box or a profile
+    /// line, or places the reticle.
+    fn handle_viewer_drag(&mut self, response: &egui::Response) {
+        let Some(pos) = response.interact_pointer_pos() else {
+            return;
+        };
+        let Some((x, y)) = self.screen_to_image(response.rect, pos) else {
+            return;
+        };
+        if self.viewer_tool == ViewerTool::Reticle {
+            if response.clicked() || response.dragged() {
+                self.overlays.reticle = Some((x, y));
+            }
+            return;
+        }
+        if response.drag_started() {
+            self.drag_start = Some((x, y));
+        }
+        if let Some((x0, y0)) = self.drag_start {
+            match self.viewer_tool {
+                ViewerTool::StatsBox => {
+                    self.stats.scope = StatsScope::Selection;
+                    self.stats.selection = Some(Region {
+                        x: x0.min(x) as usize,
+                        y: y0.min(y) as usize,
+                        width: (x0 - x).abs().max(1.0) as usize,
+                        height: (y0 - y).abs().max(1.0) as usize,
+                    });
+                }
+                ViewerTool::Line => self.profile.line = Some(((x0, y0), (x, y))),
+                ViewerTool::Reticle => {}
+            }
+        }
+        if response.drag_stopped() {
+            self.drag_start = None;
+            self.display_dirty = true;
+        }
+    }
+
+    /// Draws framing overlays, detected stars, Bahtinov spikes, the statistics box and the profile
+    /// line over the image drawn in `rect`.
+    fn paint_overlays(&self, ui: &Ui, rect: egui::Rect) {
+        let Some(frame) = &self.raw_frame else {
+            return;
+        };
+        if frame.width == 0 || frame.height == 0 {
+            return;
+        }
+        let scale = rect.width() / frame.width as f32;
+        let to_screen = |x: f32, y: f32| rect.min + egui::vec2(x + 0.5, y + 0.5) * egui::vec2(scale, rect.height() / frame.height as f32);
+        let painter = ui.painter_at(rect);
+
+        self.overlays.paint(
+            &painter,
+            |x, y| to_screen(x - 0.5, y - 0.5),
+            scale,
+            (frame.width as f32, frame.height as f32),
+            self.star_analysis.plate_scale,
+        );
+
+        if self.star_analysis.enabled {
+            let stroke = egui::Stroke::new(1.5, egui::Color32::from_rgb(0, 255, 128));
+            for star in &self.star_analysis.stars {
+                painter.circle_stroke(to_screen(star.x, star.y), (3.0 * star.hfr * scale).max(4.0), stroke);
+            }
+        }
+
+        if let Some(r) = self.stats.selection.filter(|_| self.stats.scope == StatsScope::Selection) {
+            let rect = egui::Rect::from_two_pos(to_screen(r.x as f32 - 0.5, r.y as f32 - 0.5), to_screen((r.x + r.width) as f32 - 0.5, (r.y + r.height) as f32 - 0.5));
+            painter.rect_stroke(rect, 0.0, egui::Stroke::new(1.0, egui::Color32::LIGHT_BLUE));
+        }
+
+        if let Some(((x0, y0), (x1, y1))) = self.profile.line {
+            let stroke = egui::Stroke::new(1.5, egui::Color32::from_rgb(255, 128, 0));
+            painter.line_segment([to_screen(x0, y0), to_screen(x1, y1)], stroke);
+            let length = (x1 - x0).hypot(y1 - y0);
+            if self.profile.band > 1 && length > 0.0 {
+                // Mark the edges of the averaged band.
+                let half = self.profile.band as f32 / 2.0;
+                let (nx, ny) = (-(y1 - y0) / length * half, (x1 - x0) / length * half);
+                let stroke = egui::Stroke::new(0.5, egui::Color32::from_rgb(255, 128, 0));
+                for s in [-1.0, 1.0] {
+                    painter.line_segment([to_screen(x0 + s * nx, y0 + s * ny), to_screen(x1 + s * nx, y1 + s * ny)], stroke);
+                }
+            }
+        }
+
+        if let Some(b) = self.focus.bahtinov_result.filter(|_| self.focus.enabled) {
+            let stroke = egui::Stroke::new(1.0, egui::Color32::YELLOW);
+            let half = 2.0 * frame.width.max(frame.height) as f32;
+            for spike in b.spikes {
+                let ((px, py), (dx, dy)) = spike.point_and_direction();
+                let (px, py) = (b.center.0 + px, b.center.1 + py);
+                painter.line_segment([to_screen(px - dx * half, py - dy * half), to_screen(px + dx * half, py + dy * half)], stroke);
+            }
+            let color = if b.error.abs() < 0.5 { egui::Color32::GREEN } else { egui::Color32::RED };
+            painter.circle_filled(to_screen(b.center.0 + b.crossing.0, b.center.1 + b.crossing.1), 3.0, color);
+        }
+    }
+
+    /// Draws the active camera's image with its overlays and viewer tools.
+    fn ui_viewer(&mut self, ui: &mut Ui) {
+        let Some(data) = &self.data else {
+            ui.label("No image data.");
+            return;
+        };
+        let response = ui.add(
+            egui::Image::new(ImageSource::Bytes {
+                uri: self.img_uri.clone().into(),
+                bytes: data.clone(),
+            })
+            .rounding(10.0)
+            .sense(egui::Sense::click_and_drag())
+            // .fit_to_original_size(1.0),
+        );
+        self.handle_viewer_drag(&response);
+        self.paint_overlays(ui, response.rect);
+    }
+
+    /// Draws a background camera's latest frame with that camera's own display and overlay
+    /// settings. Returns whether the tile was clicked.
+    fn ui_parked_tile(&mut self, ui: &mut Ui, key: &CameraKey) -> bool {
+        let plate_scale = self.star_analysis.plate_scale;
+        let Some(cam) = self.connected_cameras.get_mut(key) else {
+            return false;
+        };
+        let settings = &mut cam.settings;
+        let Some(frame) = &settings.raw_frame else {
+            return ui.add(egui::Label::new("No image data.").sense(egui::Sense::click())).clicked();
+        };
+
+        if cam.texture_dirty || cam.texture.is_none() {
+            let (data, rgb) = match settings.display.render(frame) {
+                Ok(rendered) => rendered,
+                Err(e) => return ui.add(egui::Label::new(e).sense(egui::Sense::click())).clicked(),
+            };
+            let size = [frame.width, frame.height];
+            let image = if rgb { egui::ColorImage::from_rgb(size, &data) } else { egui::ColorImage::from_gray(size, &data) };
+            match &mut cam.texture {
+                Some(texture) => texture.set(image, Default::default()),
+                None => cam.texture = Some(ui.ctx().load_texture(format!("tile {} {}", key.server, key.id), image, Default::default())),
+            }
+            cam.texture_dirty = false;
+        }
+        let texture = cam.texture.as_ref().unwrap(); // rendered above
+        let response = ui.add(egui::Image::new(texture).rounding(10.0).sense(egui::Sense::click()));
+
+        let rect = response.rect;
+        let scale = rect.width() / frame.width as f32;
+        settings.overlays.paint(
+            &ui.painter_at(rect),
+            |x, y| rect.min + egui::vec2(x, y) * scale,
+            scale,
+            (frame.width as f32, frame.height as f32),
+            plate_scale,
+        );
+        response.clicked()
+    }
+
+    /// Draws every camera's live view in a grid, highlighting the active camera. Clicking a tile
+    /// makes its camera active.
+    fn ui_tiles(&mut self, ui: &mut Ui) {
+        let mut keys: Vec<CameraKey> = self.connected_cameras.keys().cloned().collect();
+        keys.sort();
+        let columns = match self.tile_columns {
+            0 => (keys.len() as f32).sqrt().ceil() as usize,
+            n => n,
+        };
+        let spacing = ui.spacing().item_spacing.x;
+        let tile_width = ((ui.available_width() - spacing * (columns - 1) as f32) / columns as f32 - 8.0).max(32.0);
+
+        let mut selected = None;
+        for row in keys.chunks(columns) {
+            ui.horizontal_top(|ui| {
+                for key in row {
+                    let active = self.active_camera.as_ref() == Some(key);
+                    let stroke = if active {
+                        egui::Stroke::new(2.0, ui.visuals().selection.stroke.color)
+                    } else {
+                        ui.visuals().widgets.noninteractive.bg_stroke
+                    };
+                    egui::Frame::none().stroke(stroke).rounding(3.0).inner_margin(2.0).show(ui, |ui| {
+                        ui.set_width(tile_width);
+                        ui.vertical(|ui| {
+                            let info = &self.connected_cameras[key].info;
+                            ui.label(format!("{} {}", info.model, info.serial));
+                            if active {
+                                self.ui_viewer(ui);
+                            } else if self.ui_parked_tile(ui, key) {
+                                selected = Some(key.clone());
+                            }
+                        });
+                    });
+                }
+            });
+        }
+        if let Some(key) = selected {
+            self.select_camera(&key);
+        }
+    }
+
     fn ui_central_panel(&mut self, ctx: &egui::Context) {
         egui::CentralPanel::default().show(ctx, |ui| {
             // ui.label("Test.");
@@ -848,17 +2369,13 @@ impl GenCamGUI {
             ui.vertical(|ui| {
                 // Here we show the image data.
                 self.frame.show(ui, |ui| {
-                    if let Some(data) = &self.data {
-                        ui.add(
-                            egui::Image::new(ImageSource::Bytes {
-                                uri: self.img_uri.clone().into(),
-                                bytes: data.clone(),
-                            })
-                            .rounding(10.0)
-                            // .fit_to_original_size(1.0),
-                        );
+                    if self.tiled_view && self.connected_cameras.len() > 1 {
+                        self.ui_tiles(ui);
                     } else {
-                        ui.label("No image data.");
+                        self.ui_viewer(ui);
+                    }
+                    if self.data.is_some() {
+                        self.display.colorbar(ui);
                     }
                 });
 
@@ -873,7 +2390,7 @@ impl GenCamGUI {
                             .on_hover_text("Swap the image data.")
                             .clicked()
                         {
-                            self.update_test_image().expect("Failed to update image.");
+                            self.update_test_image(ui.input(|i| i.time));
                         }
 
                         if ui
@@ -894,6 +2411,35 @@ impl GenCamGUI {
 
                             ui.ctx().forget_image(&self.img_uri.clone());
                         }
+
+                        egui::ComboBox::from_id_source("ViewerTool")
+                            .selected_text(match self.viewer_tool {
+                                ViewerTool::StatsBox => "Drag: Statistics Box",
+                                ViewerTool::Line => "Drag: Line Profile",
+                                ViewerTool::Reticle => "Click: Place Reticle",
+                            })
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut self.viewer_tool, ViewerTool::StatsBox, "Statistics Box");
+                                ui.selectable_value(&mut self.viewer_tool, ViewerTool::Line, "Line Profile");
+                                ui.selectable_value(&mut self.viewer_tool, ViewerTool::Reticle, "Place Reticle");
+                            });
+
+                        ui.checkbox(&mut self.tiled_view, "Tiled View")
+                            .on_hover_text("Show every camera side by side. Click a tile to make its camera active.");
+                        ui.add_enabled(
+                            self.tiled_view,
+                            egui::DragValue::new(&mut self.tile_columns)
+                                .range(0..=8)
+                                .custom_formatter(|v, _| if v == 0.0 { "Auto".into() } else { format!("{} columns", v) }),
+                        );
+
+                        if ui
+                            .checkbox(&mut self.highlight_clipping, "Highlight Clipping")
+                            .on_hover_text("Show saturated pixels in red and zero pixels in blue.")
+                            .changed()
+                        {
+                            self.display_dirty = true;
+                        }
                     });
                 });
             });
@@ -1008,11 +2554,31 @@ impl GenCamGUI {
             //     });
             // });
 
-        if let Some(data) = &self.data {
-            let sum: i64 = data.iter().map(|&x| x as i64).sum();
-            ui.label(format!("{}", sum));
-        } else {
-            ui.label("No image data.");
+        self.frame.show(ui, |ui| {
+            egui::CollapsingHeader::new("Statistics")
+                .default_open(true)
+                .show(ui, |ui| {
+                    if self.stats.ui(ui) {
+                        self.display_dirty = true;
+                    }
+                });
+        });
+
+        self.frame.show(ui, |ui| {
+            egui::CollapsingHeader::new("Line Profile")
+                .default_open(true)
+                .show(ui, |ui| {
+                    if self.profile.ui(ui) {
+                        self.display_dirty = true;
+                    }
+                });
+        });
+
+        if self.calibration.enabled && !self.calibration.warnings().is_empty() {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                format!("⚠ Calibration masters do not match this frame ({} warnings, see Calibration).", self.calibration.warnings().len()),
+            );
         }
 
         // Image controls
@@ -1036,13 +2602,115 @@ impl GenCamGUI {
                 ui.set_enabled(!self.modal_active);
 
                 ui.horizontal(|ui| {
-                    ui.label("Bottom Status Panel");
+                    if self.connections.is_empty() {
+                        ui.colored_label(ui.visuals().weak_text_color(), "○ Not connected");
+                    }
+                    for ws in &self.connections.connections {
+                        let name = self.connect_dialog.history.nickname(&ws.uri).unwrap_or(&ws.uri);
+                        let (color, text) = match &ws.status {
+                            ConnectionStatus::Connecting => (ui.visuals().warn_fg_color, format!("◌ {}: connecting…", name)),
+                            ConnectionStatus::Connected => (egui::Color32::GREEN, format!("● {}", name)),
+                            ConnectionStatus::Closed => (ui.visuals().weak_text_color(), format!("○ {}: closed", name)),
+                            ConnectionStatus::Error(e) => (ui.visuals().error_fg_color, format!("✖ {}: {}", name, e)),
+                        };
+                        ui.colored_label(color, text).on_hover_text(&ws.uri);
+                        ui.separator();
+                    }
+                    if ui.small_button("Connect…").clicked() {
+                        self.open_connect_dialog(ui.input(|i| i.time));
+                    }
                 });
             });
     }
 }
 
+impl GenCamGUI {
+    /// Creates the GUI, restoring the settings saved in eframe's storage.
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        let mut app = Self {
+            ctx: Some(cc.egui_ctx.clone()),
+            ..Default::default()
+        };
+        match cc.storage.and_then(GuiSettings::load) {
+            Some(Ok(settings)) => {
+                cc.egui_ctx.set_visuals(if settings.dark_mode { Visuals::dark() } else { Visuals::light() });
+                app.apply_settings(settings);
+            }
+            Some(Err(e)) => {
+                app.msg_list.push_back(format!("Failed to restore settings: {}", e));
+            }
+            None => {}
+        }
+        app
+    }
+
+    /// The settings to keep across restarts, taking the exposure and ROI from the active camera.
+    fn settings(&self) -> GuiSettings {
+        GuiSettings {
+            dark_mode: self.dark_mode,
+            uri: self.uri.clone(),
+            servers: self.connect_dialog.history.clone(),
+            exposure_slider: self.exposure_slider,
+            long_exposure: self.long_exp_checkbox,
+            roi: self.roi,
+            roi_centered: self.roi_type == ROITypes::Center,
+            roi_enabled: self.roi_enabled,
+            binning: self.binning,
+            overlays: self.overlays.clone(),
+            display: self.display.clone(),
+            tiled_view: self.tiled_view,
+            tile_columns: self.tile_columns,
+            highlight_clipping: self.highlight_clipping,
+            show_capture_plan: self.show_capture_plan,
+            show_master_builder: self.show_master_builder,
+            show_sync_capture: self.show_sync_capture,
+            plan_path: self.plan_path.clone(),
+            plan_output_dir: self.plan_output_dir.clone(),
+            sync_output_dir: self.sync_capture.output_dir.clone(),
+            sync_next_id: self.sync_capture.next_id,
+            sensor_presets_path: self.sensor_presets_path.clone(),
+            camera_profiles_path: self.camera_profiles_path.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Restores saved settings.
+    fn apply_settings(&mut self, settings: GuiSettings) {
+        let camera = CameraSettings::from_saved(&settings);
+        self.exposure_slider = camera.exposure_slider;
+        self.long_exp_checkbox = camera.long_exp_checkbox;
+        self.roi = camera.roi;
+        self.roi_type = camera.roi_type;
+        self.roi_enabled = camera.roi_enabled;
+        self.binning = camera.binning;
+        self.overlays = camera.overlays;
+        self.display = camera.display;
+        self.display_dirty = true;
+
+        self.dark_mode = settings.dark_mode;
+        self.uri = settings.uri.clone();
+        self.connect_dialog.history = settings.servers.clone();
+        self.tiled_view = settings.tiled_view;
+        self.tile_columns = settings.tile_columns;
+        self.highlight_clipping = settings.highlight_clipping;
+        self.show_capture_plan = settings.show_capture_plan;
+        self.show_master_builder = settings.show_master_builder;
+        self.show_sync_capture = settings.show_sync_capture;
+        self.plan_path = settings.plan_path.clone();
+        self.plan_output_dir = settings.plan_output_dir.clone();
+        self.sync_capture.output_dir = settings.sync_output_dir.clone();
+        self.sync_capture.next_id = settings.sync_next_id.max(1);
+        self.sensor_presets_path = settings.sensor_presets_path.clone();
+        self.camera_profiles_path = settings.camera_profiles_path.clone();
+        self.saved_settings = settings;
+    }
+}
+
 impl eframe::App for GenCamGUI {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.settings().save(storage);
+    }
+
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         ctx.set_pixels_per_point(1.5);
 
@@ -1064,13 +2732,70 @@ impl eframe::App for GenCamGUI {
 
         let w_view = ctx.screen_rect().width();
 
-        if self.ws.is_some() && self.ws.as_ref().unwrap().new_image_event.swap(false, std::sync::atomic::Ordering::Relaxed) {
-            self.update_test_image().unwrap();
-            ctx.forget_image(&self.img_uri.clone());
+        for (uri, status) in self.connections.poll() {
+            match status {
+                ConnectionStatus::Connected => {
+                    self.msg_list.push_back(format!("Connected to {}", uri));
+                }
+                ConnectionStatus::Closed => {
+                    self.msg_list.push_back(format!("Connection to {} closed", uri));
+                    self.forget_server(&uri);
+                }
+                ConnectionStatus::Error(e) => {
+                    self.msg_list.push_back(format!("Connection to {} failed: {}", uri, e));
+                    self.forget_server(&uri);
+                }
+                ConnectionStatus::Connecting => {}
+            }
+        }
+        // Clear every connection's flag, so no short-circuiting.
+        let new_images = self.connections.connections.iter_mut()
+            .filter(|ws| ws.new_image_event.swap(false, std::sync::atomic::Ordering::Relaxed))
+            .count();
+        if new_images > 0 {
+            self.update_test_image(ctx.input(|i| i.time));
             ctx.request_repaint(); // May not be able to keep this if we get spammed w/ images.
         }
+        self.handle_replies(ctx.input(|i| i.time));
+
+        let frames = std::mem::take(&mut self.new_frames);
+        if !frames.is_empty() {
+            self.trigger.frame_received(ctx.input(|i| i.time));
+        }
+        let saved_frames: Vec<RawFrame> = if self.calibration.apply_to_saved && self.calibration.has_masters() {
+            let info = self.frame_info();
+            frames.iter().map(|f| self.calibration.apply(f, &info)).collect()
+        } else {
+            frames.clone()
+        };
+        self.stack_frames(&frames);
+        self.measure_focus(&frames);
+        if self.display_dirty {
+            self.display_dirty = false;
+            self.refresh_display();
+            ctx.forget_image(&self.img_uri.clone());
+        }
+        self.record_frames(&saved_frames, ctx.input(|i| i.time));
+        self.send_pending_roi(&frames);
+        self.poll_capture_plan(ctx.input(|i| i.time), &frames);
+        self.poll_timelapse(&saved_frames);
+        self.poll_capture_group(ctx.input(|i| i.time));
+        self.run_auto_exposure(frames.last());
+        if self.master_builder.collect {
+            let info = self.frame_info();
+            for frame in frames {
+                let label = clock::format_utc(frame.timestamp);
+                if !self.master_builder.add(frame, info, label) {
+                    break;
+                }
+            }
+        }
 
         self.ui_developer_controls(ctx);
+        self.ui_connect_dialog(ctx);
+        self.ui_capture_plan(ctx);
+        self.ui_master_builder(ctx);
+        self.ui_sync_capture(ctx);
         self.ui_top_bar(ctx);
         self.ui_left_panel(ctx, w_view);
         self.ui_right_panel(ctx, w_view);
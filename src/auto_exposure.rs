@@ -0,0 +1,265 @@
+//!
+//! # Auto-Exposure
+//! A GUI-side controller that adjusts exposure, and optionally gain, to bring a chosen
+//! percentile of each frame to a target level.
+//!
+
+use eframe::egui;
+use egui::Ui;
+
+use crate::frame::RawFrame;
+use crate::stats;
+
+/// Number of consecutive direction reversals after which the controller is considered hunting.
+const HUNTING_REVERSALS: u32 = 3;
+
+/// Relative difference below which two exposures count as the same. Exposures round-trip
+/// through the `f32` exposure slider, so a clamped request never equals it exactly.
+const SAME_EXPOSURE: f64 = 1e-4;
+
+/// What the controller is currently doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeState {
+    /// Not running.
+    Off,
+    /// Waiting for a frame taken with the latest settings.
+    Settling,
+    /// Moving towards the target.
+    Adjusting,
+    /// Overshooting back and forth around the target; steps are damped.
+    Hunting,
+    /// Within tolerance of the target.
+    Converged,
+    /// The target needs an exposure (and gain) outside the allowed window.
+    Limited,
+}
+
+/// Exposure and gain the controller wants the camera set to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AeRequest {
+    /// Exposure in seconds.
+    pub exposure: f64,
+    /// Gain, if it changed.
+    pub gain: Option<i64>,
+}
+
+/// Auto-exposure settings and state.
+pub struct AutoExposure {
+    /// Percentile (0-100) of the frame driven to the target; 50 is the median.
+    pub percentile: f32,
+    /// Target level as a fraction of full scale.
+    pub target: f32,
+    /// Relative distance from the target considered converged.
+    pub tolerance: f32,
+    /// Shortest exposure the controller may choose, in seconds.
+    pub min_exposure: f64,
+    /// Longest exposure the controller may choose, in seconds.
+    pub max_exposure: f64,
+    /// Largest factor the exposure may change by in one step.
+    pub max_step: f64,
+    /// Raise gain once exposure is at its maximum, and lower it before shortening below minimum.
+    pub adjust_gain: bool,
+    /// Lowest gain the controller may choose.
+    pub min_gain: i64,
+    /// Highest gain the controller may choose.
+    pub max_gain: i64,
+    /// Gain change per step.
+    pub gain_step: i64,
+    /// Frames to ignore after a change, since they may have been exposed with the old settings.
+    pub settle_frames: u32,
+    state: AeState,
+    settle: u32,
+    last_direction: i8,
+    reversals: u32,
+    level: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self {
+            percentile: 50.0,
+            target: 0.25,
+            tolerance: 0.1,
+            min_exposure: 0.0001,
+            max_exposure: 10.0,
+            max_step: 4.0,
+            adjust_gain: false,
+            min_gain: 0,
+            max_gain: 300,
+            gain_step: 10,
+            settle_frames: 1,
+            state: AeState::Off,
+            settle: 0,
+            last_direction: 0,
+            reversals: 0,
+            level: 0.0,
+        }
+    }
+}
+
+impl AutoExposure {
+    /// Stops the controller and forgets its history.
+    pub fn reset(&mut self) {
+        self.state = AeState::Off;
+        self.settle = 0;
+        self.last_direction = 0;
+        self.reversals = 0;
+    }
+
+    /// Measures `frame`, taken with `exposure` seconds at `gain`, and returns new settings if
+    /// they should change.
+    pub fn update(&mut self, frame: &RawFrame, exposure: f64, gain: i64) -> Option<AeRequest> {
+        if self.settle > 0 {
+            self.settle -= 1;
+            self.state = AeState::Settling;
+            return None;
+        }
+
+        let full_scale = frame.max_value() as f64;
+        let measured = (stats::percentile(frame, self.percentile) as f64).max(1.0);
+        self.level = (measured / full_scale) as f32;
+        let ratio = self.target as f64 * full_scale / measured;
+        if (ratio - 1.0).abs() <= self.tolerance as f64 {
+            self.state = AeState::Converged;
+            self.last_direction = 0;
+            self.reversals = 0;
+            return None;
+        }
+
+        let direction = if ratio > 1.0 { 1 } else { -1 };
+        if self.last_direction != 0 && direction != self.last_direction {
+            self.reversals += 1;
+        } else if direction == self.last_direction {
+            self.reversals = 0;
+        }
+        self.last_direction = direction;
+
+        let mut step = ratio.clamp(1.0 / self.max_step, self.max_step);
+        if self.reversals >= HUNTING_REVERSALS {
+            // Halve the step in log space to stop overshooting.
+            step = step.sqrt();
+            self.state = AeState::Hunting;
+        } else {
+            self.state = AeState::Adjusting;
+        }
+
+        let wanted = exposure * step;
+        let mut request = AeRequest {
+            exposure: wanted.clamp(self.min_exposure, self.max_exposure),
+            gain: None,
+        };
+        if self.adjust_gain {
+            if wanted > self.max_exposure && gain < self.max_gain {
+                request.gain = Some((gain + self.gain_step).min(self.max_gain));
+            } else if wanted < self.min_exposure && gain > self.min_gain {
+                request.gain = Some((gain - self.gain_step).max(self.min_gain));
+            }
+        }
+
+        let unchanged = (request.exposure - exposure).abs() <= exposure * SAME_EXPOSURE;
+        if request.gain.is_none() && unchanged {
+            self.state = AeState::Limited;
+            return None;
+        }
+        self.settle = self.settle_frames;
+        Some(request)
+    }
+
+    /// Shows the state and the controller settings.
+    pub fn ui(&mut self, ui: &mut Ui) {
+        let (text, color) = match self.state {
+            AeState::Off => ("AE off", ui.visuals().weak_text_color()),
+            AeState::Settling => ("AE settling", ui.visuals().text_color()),
+            AeState::Adjusting => ("AE adjusting", ui.visuals().text_color()),
+            AeState::Hunting => ("AE hunting", ui.visuals().warn_fg_color),
+            AeState::Converged => ("AE converged", egui::Color32::GREEN),
+            AeState::Limited => ("AE at limit", ui.visuals().warn_fg_color),
+        };
+        ui.horizontal(|ui| {
+            ui.colored_label(color, text);
+            if self.state != AeState::Off {
+                ui.label(format!(
+                    "level {:.0}% / target {:.0}%",
+                    self.level * 100.0,
+                    self.target * 100.0
+                ));
+            }
+        });
+
+        egui::CollapsingHeader::new("Auto-Exposure Settings")
+            .default_open(false)
+            .show(ui, |ui| {
+                egui::Grid::new("AutoExposure").show(ui, |ui| {
+                    ui.label("Percentile");
+                    ui.add(egui::Slider::new(&mut self.percentile, 1.0..=99.9));
+                    ui.end_row();
+
+                    ui.label("Target");
+                    ui.add(
+                        egui::Slider::new(&mut self.target, 0.01..=0.95)
+                            .custom_formatter(|v, _| format!("{:.0}%", v * 100.0)),
+                    );
+                    ui.end_row();
+
+                    ui.label("Tolerance");
+                    ui.add(
+                        egui::Slider::new(&mut self.tolerance, 0.01..=0.5)
+                            .custom_formatter(|v, _| format!("±{:.0}%", v * 100.0)),
+                    );
+                    ui.end_row();
+
+                    ui.label("Exposure");
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut self.min_exposure)
+                                .range(0.00001..=self.max_exposure)
+                                .speed(0.001)
+                                .suffix(" s"),
+                        );
+                        ui.label("to");
+                        ui.add(
+                            egui::DragValue::new(&mut self.max_exposure)
+                                .range(self.min_exposure..=3600.0)
+                                .speed(0.1)
+                                .suffix(" s"),
+                        );
+                    });
+                    ui.end_row();
+
+                    ui.label("Max. Step");
+                    ui.add(
+                        egui::DragValue::new(&mut self.max_step)
+                            .range(1.1..=100.0)
+                            .speed(0.1)
+                            .prefix("×"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Settle");
+                    ui.add(
+                        egui::DragValue::new(&mut self.settle_frames)
+                            .range(0..=10)
+                            .suffix(" frames"),
+                    );
+                    ui.end_row();
+
+                    ui.checkbox(&mut self.adjust_gain, "Adjust Gain");
+                    ui.add_enabled_ui(self.adjust_gain, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::DragValue::new(&mut self.min_gain).range(0..=self.max_gain),
+                            );
+                            ui.label("to");
+                            ui.add(
+                                egui::DragValue::new(&mut self.max_gain)
+                                    .range(self.min_gain..=10_000),
+                            );
+                            ui.label("step");
+                            ui.add(egui::DragValue::new(&mut self.gain_step).range(1..=1000));
+                        });
+                    });
+                    ui.end_row();
+                });
+            });
+    }
+}
@@ -0,0 +1,326 @@
+//!
+//! # Frame Calibration
+//! Bias/dark subtraction and flat-field correction using master calibration frames.
+//!
+
+use std::path::Path;
+
+use eframe::egui;
+use egui::Ui;
+
+use crate::fits::{self, HeaderValue};
+use crate::frame::{FloatFrame, RawFrame};
+use crate::stats;
+
+/// Relative exposure difference above which a master dark is considered mismatched.
+const EXPOSURE_TOLERANCE: f64 = 0.01;
+
+/// Temperature difference (°C) above which a master dark is considered mismatched.
+const TEMPERATURE_TOLERANCE: f32 = 1.0;
+
+/// The role a master frame plays in calibration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MasterKind {
+    /// Read-out offset, subtracted from everything.
+    Bias,
+    /// Thermal signal plus bias, subtracted from lights.
+    Dark,
+    /// Optical response, divided out after normalizing to its median.
+    Flat,
+}
+
+impl MasterKind {
+    /// All kinds, in the order they are applied.
+    pub const ALL: [MasterKind; 3] = [MasterKind::Bias, MasterKind::Dark, MasterKind::Flat];
+}
+
+/// Acquisition settings a frame was taken with, used to match masters to lights.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameInfo {
+    /// Exposure time in seconds.
+    pub exposure: f64,
+    /// Sensor temperature in °C, if known.
+    pub temperature: Option<f32>,
+    /// Symmetric binning factor.
+    pub binning: u8,
+}
+
+impl FrameInfo {
+    /// Reads the acquisition settings recorded in a FITS header.
+    pub fn from_cards(cards: &[fits::HeaderCard]) -> FrameInfo {
+        let num = |key: &str| fits::find(cards, key).and_then(HeaderValue::as_f64);
+        FrameInfo {
+            exposure: num("EXPTIME").or_else(|| num("EXPOSURE")).unwrap_or(0.0),
+            temperature: num("CCD-TEMP").map(|t| t as f32),
+            binning: num("XBINNING").unwrap_or(1.0) as u8,
+        }
+    }
+}
+
+/// A master calibration frame and the settings it was taken with.
+#[derive(Debug, Clone)]
+pub struct MasterFrame {
+    /// Pixel data, in units of the master's own bit depth.
+    pub frame: FloatFrame,
+    /// Acquisition settings.
+    pub info: FrameInfo,
+    /// Where the master came from, for display.
+    pub source: String,
+}
+
+impl MasterFrame {
+    /// Loads a master frame from a FITS file.
+    pub fn load(path: &Path) -> Result<MasterFrame, String> {
+        let (frame, cards) = fits::read_fits(path)?;
+        Ok(MasterFrame {
+            frame,
+            info: FrameInfo::from_cards(&cards),
+            source: path.display().to_string(),
+        })
+    }
+}
+
+/// The master's samples, the factor that converts them to `frame`'s units and its settings, if it
+/// has `frame`'s geometry.
+fn matching<'a>(
+    master: &'a Option<MasterFrame>,
+    frame: &RawFrame,
+) -> Option<(&'a FloatFrame, f32, FrameInfo)> {
+    master
+        .as_ref()
+        .filter(|m| m.frame.geometry() == frame.geometry())
+        .map(|m| (&m.frame, m.frame.scale_to(frame.bit_depth), m.info))
+}
+
+/// Something the calibration panel asks the GUI to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalibrationAction {
+    /// Load a master of the given kind from a FITS file.
+    Load(MasterKind, String),
+    /// Use the most recent frame as a master of the given kind.
+    UseCurrent(MasterKind),
+}
+
+/// The loaded master frames and calibration options.
+#[derive(Default)]
+pub struct Calibration {
+    /// Apply calibration to displayed frames.
+    pub enabled: bool,
+    /// Also apply calibration to frames before they are saved.
+    pub apply_to_saved: bool,
+    bias: Option<MasterFrame>,
+    dark: Option<MasterFrame>,
+    flat: Option<MasterFrame>,
+    /// Per-channel median of the bias-subtracted flat, in units of the flat.
+    flat_median: Vec<f32>,
+    paths: [String; 3],
+    warnings: Vec<String>,
+}
+
+impl Calibration {
+    fn slot(&mut self, kind: MasterKind) -> &mut Option<MasterFrame> {
+        match kind {
+            MasterKind::Bias => &mut self.bias,
+            MasterKind::Dark => &mut self.dark,
+            MasterKind::Flat => &mut self.flat,
+        }
+    }
+
+    /// Installs a master frame, replacing any previous one of the same kind.
+    pub fn set_master(&mut self, kind: MasterKind, master: MasterFrame) {
+        *self.slot(kind) = Some(master);
+        self.update_flat_median();
+    }
+
+    fn update_flat_median(&mut self) {
+        self.flat_median.clear();
+        let Some(flat) = &self.flat else {
+            return;
+        };
+        let bias = self
+            .bias
+            .as_ref()
+            .filter(|b| b.frame.geometry() == flat.frame.geometry());
+        let bias_scale = bias.map_or(1.0, |b| b.frame.scale_to(flat.frame.bit_depth));
+        for c in 0..flat.frame.channels {
+            let mut values: Vec<f32> = (0..flat.frame.width * flat.frame.height)
+                .map(|i| {
+                    let j = i * flat.frame.channels + c;
+                    let b = bias.map_or(0.0, |b| b.frame.data[j] * bias_scale);
+                    flat.frame.data[j] - b
+                })
+                .collect();
+            self.flat_median.push(stats::median_f32(&mut values));
+        }
+    }
+
+    /// Whether any master frame is loaded.
+    pub fn has_masters(&self) -> bool {
+        self.bias.is_some() || self.dark.is_some() || self.flat.is_some()
+    }
+
+    /// Warnings from the most recent call to [`Calibration::apply`].
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Lists the ways the loaded masters do not match a frame taken with `info`.
+    fn check(&self, frame: &RawFrame, info: &FrameInfo) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for (name, master) in [
+            ("Bias", &self.bias),
+            ("Dark", &self.dark),
+            ("Flat", &self.flat),
+        ] {
+            let Some(master) = master else {
+                continue;
+            };
+            if master.frame.geometry() != frame.geometry() {
+                warnings.push(format!(
+                    "{} is {} but the frame is {}; not applied.",
+                    name,
+                    master.frame.shape(),
+                    frame.shape()
+                ));
+            }
+            if master.info.binning != info.binning {
+                warnings.push(format!(
+                    "{} binning {}x{} differs from the frame's {}x{}.",
+                    name, master.info.binning, master.info.binning, info.binning, info.binning
+                ));
+            }
+        }
+
+        if let Some(dark) = &self.dark {
+            let rel = (dark.info.exposure - info.exposure).abs() / info.exposure.max(1e-6);
+            if rel > EXPOSURE_TOLERANCE {
+                warnings.push(format!(
+                    "Dark exposure {:.3} s differs from the frame's {:.3} s{}.",
+                    dark.info.exposure,
+                    info.exposure,
+                    if self.bias.is_some() {
+                        "; dark current is scaled"
+                    } else {
+                        ""
+                    }
+                ));
+            }
+            match (dark.info.temperature, info.temperature) {
+                (Some(a), Some(b)) if (a - b).abs() > TEMPERATURE_TOLERANCE => {
+                    warnings.push(format!(
+                        "Dark temperature {:.1} °C differs from the sensor's {:.1} °C.",
+                        a, b
+                    ));
+                }
+                (None, _) | (_, None) => {
+                    warnings.push("Dark or sensor temperature is unknown.".into());
+                }
+                _ => {}
+            }
+        }
+        warnings
+    }
+
+    /// Calibrates `frame`, taken with `info`, and records any mismatch warnings.
+    ///
+    /// Computes `(light - dark) * median(flat - bias) / (flat - bias)`, where the dark is replaced
+    /// by the bias when no dark is loaded, and the dark current is scaled by exposure time when
+    /// both a bias and a mismatched dark are loaded. Masters of a different bit depth than the
+    /// frame are rescaled to its range first.
+    pub fn apply(&mut self, frame: &RawFrame, info: &FrameInfo) -> RawFrame {
+        self.warnings = self.check(frame, info);
+        self.calibrate(frame, info)
+    }
+
+    fn calibrate(&self, frame: &RawFrame, info: &FrameInfo) -> RawFrame {
+        let (bias, dark, flat) = (
+            matching(&self.bias, frame),
+            matching(&self.dark, frame),
+            matching(&self.flat, frame),
+        );
+
+        let dark_scale = match (dark, bias) {
+            (Some((_, _, d)), Some(_)) if d.exposure > 0.0 => (info.exposure / d.exposure) as f32,
+            _ => 1.0,
+        };
+
+        let max = frame.max_value() as f32;
+        let mut out = frame.clone();
+        for (i, v) in out.data.iter_mut().enumerate() {
+            let b = bias.map_or(0.0, |(m, scale, _)| m.data[i] * scale);
+            let mut x = *v as f32;
+            x -= match dark {
+                Some((d, scale, _)) => b + (d.data[i] * scale - b) * dark_scale,
+                None => b,
+            };
+            if let Some((f, scale, _)) = flat {
+                let median = self.flat_median[i % frame.channels] * scale;
+                let f = f.data[i] * scale - b;
+                if f > 0.0 {
+                    x *= median / f;
+                }
+            }
+            *v = x.round().clamp(0.0, max) as u16;
+        }
+        out
+    }
+
+    /// Shows the master frame list. Returns an action for the GUI to carry out, if any.
+    pub fn ui(&mut self, ui: &mut Ui, have_frame: bool) -> Option<CalibrationAction> {
+        let mut action = None;
+
+        ui.checkbox(&mut self.enabled, "Calibrate displayed frames");
+        ui.checkbox(&mut self.apply_to_saved, "Calibrate frames before saving");
+
+        for (i, kind) in MasterKind::ALL.into_iter().enumerate() {
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label(format!("Master {:?}", kind));
+                let mut clear = false;
+                match self.slot(kind) {
+                    Some(m) => {
+                        ui.label(format!(
+                            "{}x{}, {:.3} s, {}, bin {}",
+                            m.frame.width,
+                            m.frame.height,
+                            m.info.exposure,
+                            m.info
+                                .temperature
+                                .map_or("? °C".to_owned(), |t| format!("{:.1} °C", t)),
+                            m.info.binning
+                        ))
+                        .on_hover_text(m.source.clone());
+                        clear = ui.button("Clear").clicked();
+                    }
+                    None => {
+                        ui.label("None");
+                    }
+                }
+                if clear {
+                    *self.slot(kind) = None;
+                    self.update_flat_median();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.paths[i]);
+                if ui.button("Load").clicked() {
+                    action = Some(CalibrationAction::Load(kind, self.paths[i].clone()));
+                }
+                if ui
+                    .add_enabled(have_frame, egui::Button::new("Use Current Frame"))
+                    .clicked()
+                {
+                    action = Some(CalibrationAction::UseCurrent(kind));
+                }
+            });
+        }
+
+        if self.enabled {
+            for w in &self.warnings {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("⚠ {}", w));
+            }
+        }
+
+        action
+    }
+}
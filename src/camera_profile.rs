@@ -0,0 +1,329 @@
+//!
+//! # Camera Profiles
+//! Named snapshots of a camera's full configuration, kept per camera model and shared between
+//! users as JSON files. Applying a profile first shows what it would change.
+//!
+
+use std::path::Path;
+
+use eframe::egui;
+use egui::Ui;
+use serde::{Deserialize, Serialize};
+
+use crate::command::CaptureFormat;
+use crate::library::ModelLibrary;
+
+/// The camera configuration a profile captures.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileSettings {
+    /// Exposure time in seconds.
+    pub exposure: f64,
+    /// Sensor gain.
+    pub gain: i64,
+    /// Offset (black level).
+    pub offset: i64,
+    /// Symmetric binning factor.
+    pub binning: u8,
+    /// ROI sliders as `[x, y, width %, height %]`, or `None` for the full sensor.
+    pub roi: Option<[f32; 4]>,
+    /// The ROI position is its centre rather than its top-left corner.
+    pub roi_centered: bool,
+    /// Capture pixel format.
+    pub format: CaptureFormat,
+    /// Cooler setpoint in °C, or `None` with the cooler off.
+    pub cooler_target: Option<f32>,
+}
+
+impl Default for ProfileSettings {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            gain: 0,
+            offset: 0,
+            binning: 1,
+            roi: None,
+            roi_centered: false,
+            format: CaptureFormat::Gray,
+            cooler_target: None,
+        }
+    }
+}
+
+impl ProfileSettings {
+    /// Each setting that differs from `other`, as its name with this and the other value.
+    pub fn diff(&self, other: &ProfileSettings) -> Vec<(&'static str, String, String)> {
+        let mut out = Vec::new();
+        let mut check = |name, a: String, b: String| {
+            if a != b {
+                out.push((name, a, b));
+            }
+        };
+        check(
+            "Exposure",
+            format!("{} s", self.exposure),
+            format!("{} s", other.exposure),
+        );
+        check("Gain", self.gain.to_string(), other.gain.to_string());
+        check("Offset", self.offset.to_string(), other.offset.to_string());
+        check(
+            "Binning",
+            format!("{0}x{0}", self.binning),
+            format!("{0}x{0}", other.binning),
+        );
+        check(
+            "ROI",
+            roi_text(self.roi, self.roi_centered),
+            roi_text(other.roi, other.roi_centered),
+        );
+        check(
+            "Format",
+            format!("{:?}", self.format),
+            format!("{:?}", other.format),
+        );
+        check(
+            "Cooler",
+            cooler_text(self.cooler_target),
+            cooler_text(other.cooler_target),
+        );
+        out
+    }
+}
+
+fn roi_text(roi: Option<[f32; 4]>, centered: bool) -> String {
+    match roi {
+        Some([x, y, w, h]) => format!(
+            "{:.0}% x {:.0}% at ({:.0}, {:.0}) {}",
+            w,
+            h,
+            x,
+            y,
+            if centered { "centre" } else { "corner" }
+        ),
+        None => "Full sensor".into(),
+    }
+}
+
+fn cooler_text(target: Option<f32>) -> String {
+    match target {
+        Some(t) => format!("{:.1} °C", t),
+        None => "Off".into(),
+    }
+}
+
+/// A named camera configuration for one camera model.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraProfile {
+    /// Name shown in the profile list.
+    pub name: String,
+    /// Camera model the profile was made for.
+    pub model: String,
+    /// The configuration the profile applies.
+    pub settings: ProfileSettings,
+}
+
+/// What an exported file holds: one profile, or several.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ProfileFile {
+    One(CameraProfile),
+    Many(Vec<CameraProfile>),
+}
+
+/// Profiles for every camera model, stored as JSON.
+pub type ProfileLibrary = ModelLibrary<CameraProfile>;
+
+impl ProfileLibrary {
+    /// Adds `profile` under its model, replacing any profile of the same name.
+    fn insert(&mut self, profile: CameraProfile) {
+        let profiles = self.models.entry(profile.model.clone()).or_default();
+        match profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => profiles.push(profile),
+        }
+    }
+
+    /// Adds the profiles in an exported file, which may hold one profile or a list. Returns
+    /// how many were added.
+    pub fn import(&mut self, path: &Path) -> Result<usize, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let profiles = match serde_json::from_str(&text).map_err(|e| e.to_string())? {
+            ProfileFile::One(p) => vec![p],
+            ProfileFile::Many(ps) => ps,
+        };
+        if let Some(p) = profiles.iter().find(|p| p.settings.binning == 0) {
+            return Err(format!("profile \"{}\" has binning 0", p.name));
+        }
+        if let Some(p) = profiles
+            .iter()
+            .find(|p| !(p.settings.exposure > 0.0 && p.settings.exposure.is_finite()))
+        {
+            return Err(format!(
+                "profile \"{}\" has exposure {} s; it must be positive",
+                p.name, p.settings.exposure
+            ));
+        }
+        let count = profiles.len();
+        for p in profiles {
+            self.insert(p);
+        }
+        Ok(count)
+    }
+}
+
+/// Writes `profiles` to a JSON file for sharing.
+pub fn export(profiles: &[CameraProfile], path: &Path) -> Result<(), String> {
+    let text = match profiles {
+        [one] => serde_json::to_string_pretty(one),
+        many => serde_json::to_string_pretty(many),
+    }
+    .map_err(|e| e.to_string())?;
+    std::fs::write(path, text).map_err(|e| e.to_string())
+}
+
+/// Requests from the profile panel.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProfileAction {
+    /// Configure the camera with these settings.
+    Apply(ProfileSettings),
+    /// The library was edited and should be saved.
+    Changed,
+    /// Write these profiles to the file at this path.
+    Export(Vec<CameraProfile>, String),
+    /// Add the profiles in the file at this path.
+    Import(String),
+}
+
+/// Profile panel state.
+pub struct ProfileManager {
+    /// Name for the next saved profile.
+    name: String,
+    /// File to import from or export to.
+    pub path: String,
+    /// The profile waiting for its changes to be confirmed.
+    pending: Option<CameraProfile>,
+}
+
+impl Default for ProfileManager {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            path: "profiles_export.json".into(),
+            pending: None,
+        }
+    }
+}
+
+impl ProfileManager {
+    /// Shows the profiles for camera `model` in `library`. `current` is the camera's present
+    /// configuration, saved by Save Current and compared against before applying.
+    pub fn ui(
+        &mut self,
+        ui: &mut Ui,
+        library: &mut ProfileLibrary,
+        model: &str,
+        current: &ProfileSettings,
+    ) -> Option<ProfileAction> {
+        let mut action = None;
+
+        if let Some(pending) = self.pending.clone() {
+            ui.label(format!("Apply \"{}\"?", pending.name));
+            let changes = current.diff(&pending.settings);
+            if changes.is_empty() {
+                ui.label("The camera already matches this profile.");
+            } else {
+                egui::Grid::new("ProfileDiff").striped(true).show(ui, |ui| {
+                    ui.strong("Setting");
+                    ui.strong("Current");
+                    ui.strong("Profile");
+                    ui.end_row();
+                    for (name, from, to) in changes {
+                        ui.label(name);
+                        ui.label(from);
+                        ui.label(to);
+                        ui.end_row();
+                    }
+                });
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Apply").clicked() {
+                    action = Some(ProfileAction::Apply(pending.settings.clone()));
+                }
+                if ui.button("Cancel").clicked() {
+                    self.pending = None;
+                }
+            });
+            if action.is_some() {
+                self.pending = None;
+            }
+            return action;
+        }
+
+        ui.label(format!("Profiles for {}", model));
+        let profiles = library.models.entry(model.to_owned()).or_default();
+        let mut remove = None;
+        for (i, profile) in profiles.iter().enumerate() {
+            ui.horizontal(|ui| {
+                if ui.button("Apply…").clicked() {
+                    self.pending = Some(profile.clone());
+                }
+                ui.label(&profile.name);
+                if ui
+                    .small_button("Export")
+                    .on_hover_text("Write this profile to the file below.")
+                    .clicked()
+                {
+                    action = Some(ProfileAction::Export(
+                        vec![profile.clone()],
+                        self.path.clone(),
+                    ));
+                }
+                if ui.small_button("✖").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            profiles.remove(i);
+            action = Some(ProfileAction::Changed);
+        }
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.name);
+            let name = self.name.trim().to_owned();
+            if ui
+                .add_enabled(!name.is_empty(), egui::Button::new("Save Current"))
+                .clicked()
+            {
+                library.insert(CameraProfile {
+                    name,
+                    model: model.to_owned(),
+                    settings: current.clone(),
+                });
+                self.name.clear();
+                action = Some(ProfileAction::Changed);
+            }
+        });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("File");
+            ui.text_edit_singleline(&mut self.path);
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Import").clicked() {
+                action = Some(ProfileAction::Import(self.path.clone()));
+            }
+            let profiles = library.models.get(model).cloned().unwrap_or_default();
+            if ui
+                .add_enabled(!profiles.is_empty(), egui::Button::new("Export All"))
+                .on_hover_text("Write every profile for this model to the file.")
+                .clicked()
+            {
+                action = Some(ProfileAction::Export(profiles, self.path.clone()));
+            }
+        });
+        action
+    }
+}
@@ -0,0 +1,320 @@
+//!
+//! # Synchronized Capture
+//! Triggers one exposure on several cameras at once and saves the resulting frames as a group,
+//! tagged with a shared group id and how long after the group's first frame each arrived.
+//!
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use eframe::egui;
+use egui::Ui;
+
+use crate::connection::CameraKey;
+use crate::fits::{self, HeaderCard, HeaderValue};
+use crate::frame::{FrameRequest, RawFrame};
+
+/// Seconds to wait past the longest exposure for every camera's frame.
+const GROUP_TIMEOUT: f64 = 30.0;
+
+/// A camera taking part in a capture group.
+#[derive(Debug, Clone)]
+pub struct GroupMember {
+    /// The camera.
+    pub key: CameraKey,
+    /// Name used in the report and the file name.
+    pub label: String,
+    /// Exposure the camera is set to, in seconds.
+    pub exposure: f64,
+    /// Sensor temperature when the group was triggered, if known.
+    pub temperature: Option<f32>,
+    /// The image request sent for the group, or `None` if it could not be sent.
+    pub request: Option<FrameRequest>,
+    /// The frame the camera delivered for this group.
+    pub frame: Option<RawFrame>,
+}
+
+/// One synchronized exposure in progress or completed.
+pub struct GroupRun {
+    /// Id shared by every frame of the group.
+    pub id: u32,
+    members: Vec<GroupMember>,
+    /// GUI time after which missing frames are given up on, in seconds.
+    deadline: f64,
+    finished: bool,
+}
+
+impl GroupRun {
+    /// Starts group `id` at GUI time `now`, waiting for a frame from each of `members`.
+    pub fn new(id: u32, members: Vec<GroupMember>, now: f64) -> GroupRun {
+        let longest = members.iter().map(|m| m.exposure).fold(0.0, f64::max);
+        GroupRun {
+            id,
+            members,
+            deadline: now + longest + GROUP_TIMEOUT,
+            finished: false,
+        }
+    }
+
+    /// Whether every frame has arrived or the group timed out.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Records `frame` from camera `key` if it answers the camera's request for the group.
+    /// Frames that were in flight or free-running when the group was triggered are skipped.
+    pub fn offer(&mut self, key: &CameraKey, frame: &RawFrame) {
+        if self.finished {
+            return;
+        }
+        if let Some(m) = self
+            .members
+            .iter_mut()
+            .find(|m| &m.key == key && m.frame.is_none())
+        {
+            if m.request.as_mut().is_some_and(|r| r.accepts(frame)) {
+                m.frame = Some(frame.clone());
+            }
+        }
+    }
+
+    /// Receive time of the earliest frame in the group.
+    fn first_timestamp(&self) -> Option<f64> {
+        self.members
+            .iter()
+            .filter_map(|m| m.frame.as_ref().map(|f| f.timestamp))
+            .reduce(f64::min)
+    }
+
+    /// Finishes the group once every frame is in or the deadline at GUI time `now` has passed,
+    /// saving the frames into `output_dir`. Returns a summary, or an error if a frame could not
+    /// be saved.
+    pub fn poll(&mut self, now: f64, output_dir: &Path) -> Option<Result<String, String>> {
+        if self.finished {
+            return None;
+        }
+        let complete = self.members.iter().all(|m| m.frame.is_some());
+        if !complete && now <= self.deadline {
+            return None;
+        }
+        self.finished = true;
+        if let Err(e) = self.save(output_dir) {
+            return Some(Err(e));
+        }
+
+        let missed = self.missed();
+        let received = self.members.len() - missed.len();
+        Some(Ok(if missed.is_empty() {
+            format!(
+                "Group {}: all {} cameras captured, receive skew {:.0} ms",
+                self.id,
+                received,
+                self.skew() * 1000.0
+            )
+        } else {
+            format!(
+                "Group {}: {}/{} cameras captured, missed by {}",
+                self.id,
+                received,
+                self.members.len(),
+                missed.join(", ")
+            )
+        }))
+    }
+
+    /// Labels of the cameras whose frame has not arrived.
+    pub fn missed(&self) -> Vec<&str> {
+        self.members
+            .iter()
+            .filter(|m| m.frame.is_none())
+            .map(|m| m.label.as_str())
+            .collect()
+    }
+
+    /// Spread between the earliest and latest frame receive times, in seconds.
+    ///
+    /// Frames carry the time the GUI received them, so this includes transfer delays and is
+    /// an upper bound on the spread of the exposures themselves.
+    fn skew(&self) -> f64 {
+        let last = self
+            .members
+            .iter()
+            .filter_map(|m| m.frame.as_ref().map(|f| f.timestamp))
+            .reduce(f64::max);
+        match (self.first_timestamp(), last) {
+            (Some(first), Some(last)) => last - first,
+            _ => 0.0,
+        }
+    }
+
+    /// Writes each received frame as a FITS file tagged with the group id and its receive skew.
+    /// Files that already exist are not overwritten.
+    fn save(&self, output_dir: &Path) -> Result<(), String> {
+        let Some(first) = self.first_timestamp() else {
+            return Ok(());
+        };
+        for m in &self.members {
+            let Some(frame) = &m.frame else {
+                continue;
+            };
+            // The server and camera id keep cameras of the same model apart.
+            let server = m.key.server.rsplit("://").next().unwrap_or_default();
+            let file = format!(
+                "group_{:04}_{}_{}.fits",
+                self.id,
+                fits::file_name_part(&m.label),
+                fits::file_name_part(&format!("{}_{}", server, m.key.id))
+            );
+
+            let mut cards = fits::frame_cards(
+                "Light Frame",
+                m.exposure,
+                frame.timestamp,
+                frame.bit_depth,
+                m.temperature,
+            );
+            cards.push(HeaderCard::new(
+                "GROUPID",
+                HeaderValue::Int(self.id as i64),
+                "synchronized capture group",
+            ));
+            cards.push(HeaderCard::new(
+                "GRPSIZE",
+                HeaderValue::Int(self.members.len() as i64),
+                "cameras triggered in the group",
+            ));
+            cards.push(HeaderCard::new(
+                "GRPSKEW",
+                HeaderValue::Float(frame.timestamp - first),
+                "receive delay after first frame of group [s]",
+            ));
+            cards.push(HeaderCard::new(
+                "INSTRUME",
+                HeaderValue::Str(m.label.clone()),
+                "camera",
+            ));
+            fits::write_fits(&output_dir.join(file), frame, &cards).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Lists each camera with whether its frame arrived, is pending or was missed.
+    pub fn ui(&self, ui: &mut Ui) {
+        let first = self.first_timestamp();
+        egui::Grid::new("CaptureGroupMembers")
+            .striped(true)
+            .show(ui, |ui| {
+                for m in &self.members {
+                    match (&m.frame, self.finished) {
+                        (Some(frame), _) => {
+                            ui.colored_label(egui::Color32::GREEN, "✔");
+                            ui.label(&m.label);
+                            let skew = frame.timestamp - first.unwrap_or(frame.timestamp);
+                            ui.label(format!("received +{:.0} ms", skew * 1000.0))
+                                .on_hover_text("Delay after the group's first frame arrived.");
+                        }
+                        (None, false) => {
+                            ui.label("…");
+                            ui.label(&m.label);
+                            ui.label("waiting");
+                        }
+                        (None, true) => {
+                            ui.colored_label(ui.visuals().error_fg_color, "✖");
+                            ui.label(&m.label);
+                            ui.colored_label(ui.visuals().error_fg_color, "missed");
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+}
+
+/// Which cameras to capture together and where to save the groups.
+pub struct SyncCapture {
+    /// Cameras included in the next group.
+    pub members: BTreeSet<CameraKey>,
+    /// Directory the group's FITS files are written to.
+    pub output_dir: String,
+    /// Id given to the next group.
+    pub next_id: u32,
+    /// The group in progress, or the last one taken.
+    pub run: Option<GroupRun>,
+}
+
+impl Default for SyncCapture {
+    fn default() -> Self {
+        Self {
+            members: BTreeSet::new(),
+            output_dir: ".".into(),
+            next_id: 1,
+            run: None,
+        }
+    }
+}
+
+impl SyncCapture {
+    /// Whether a group is waiting for frames.
+    pub fn is_running(&self) -> bool {
+        self.run.as_ref().is_some_and(|r| !r.is_finished())
+    }
+
+    /// Shows the camera selection and the state of the current group. `cameras` lists every
+    /// connected camera with its label. Returns whether Capture was clicked.
+    pub fn ui(&mut self, ui: &mut Ui, cameras: &[(CameraKey, String)]) -> bool {
+        let running = self.is_running();
+        let mut capture = false;
+
+        ui.add_enabled_ui(!running, |ui| {
+            ui.label("Cameras");
+            if cameras.is_empty() {
+                ui.label("No cameras connected.");
+            }
+            for (key, label) in cameras {
+                let mut included = self.members.contains(key);
+                if ui.checkbox(&mut included, label).changed() {
+                    if included {
+                        self.members.insert(key.clone());
+                    } else {
+                        self.members.remove(key);
+                    }
+                }
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Output Directory");
+                ui.text_edit_singleline(&mut self.output_dir);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Next Group");
+                ui.add(egui::DragValue::new(&mut self.next_id).range(1..=9999));
+            });
+        });
+
+        let selected = cameras
+            .iter()
+            .filter(|(k, _)| self.members.contains(k))
+            .count();
+        if ui
+            .add_enabled(
+                !running && selected > 0,
+                egui::Button::new(format!("Capture Group ({} cameras)", selected)),
+            )
+            .on_hover_text("Request one frame from every selected camera at once.")
+            .clicked()
+        {
+            capture = true;
+        }
+
+        if let Some(run) = &self.run {
+            ui.separator();
+            ui.label(if run.is_finished() {
+                format!("Group {}", run.id)
+            } else {
+                format!("Group {}: capturing…", run.id)
+            });
+            run.ui(ui);
+        }
+        capture
+    }
+}
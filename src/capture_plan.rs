@@ -0,0 +1,691 @@
+//!
+//! # Capture Plans
+//! Multi-step acquisition recipes mixing light, dark, flat and bias frames.
+//!
+
+use std::path::{Path, PathBuf};
+
+use eframe::egui;
+use egui::Ui;
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::FrameInfo;
+use crate::clock;
+use crate::command::{CameraCommand, ServerRequest};
+use crate::fits::{self, HeaderCard, HeaderValue};
+use crate::frame::{FrameRequest, RawFrame};
+
+/// How close (in °C) the sensor must be to a step's setpoint before exposing.
+pub const TEMPERATURE_TOLERANCE: f32 = 0.5;
+
+/// Seconds to wait past the exposure time for a frame before giving up.
+const FRAME_TIMEOUT: f64 = 30.0;
+
+/// Seconds to wait for the sensor to reach a step's setpoint before giving up.
+const TEMPERATURE_TIMEOUT: f64 = 900.0;
+
+/// The kind of frame a step produces, recorded in the FITS `IMAGETYP` keyword.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FrameType {
+    /// Science frame.
+    Light,
+    /// Shutter closed, same exposure as the lights.
+    Dark,
+    /// Evenly illuminated field.
+    Flat,
+    /// Shortest possible exposure with the shutter closed.
+    Bias,
+}
+
+impl FrameType {
+    /// All frame types, in display order.
+    pub const ALL: [FrameType; 4] = [
+        FrameType::Light,
+        FrameType::Dark,
+        FrameType::Flat,
+        FrameType::Bias,
+    ];
+
+    /// The conventional `IMAGETYP` value for this frame type.
+    pub fn imagetyp(&self) -> &'static str {
+        match self {
+            FrameType::Light => "Light Frame",
+            FrameType::Dark => "Dark Frame",
+            FrameType::Flat => "Flat Field",
+            FrameType::Bias => "Bias Frame",
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            FrameType::Light => "light",
+            FrameType::Dark => "dark",
+            FrameType::Flat => "flat",
+            FrameType::Bias => "bias",
+        }
+    }
+}
+
+/// One step of a capture plan.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureStep {
+    /// Frame type, written to `IMAGETYP`.
+    pub frame_type: FrameType,
+    /// Exposure time in seconds. Ignored for bias frames.
+    pub exposure: f64,
+    /// Number of frames to take.
+    pub count: u32,
+    /// Symmetric binning factor.
+    pub binning: u8,
+    /// Sensor gain.
+    pub gain: i64,
+    /// Region of interest as `[x, y, width, height]`, or `None` for the full sensor.
+    pub roi: Option<[u32; 4]>,
+    /// Required sensor temperature in °C, or `None` to start immediately.
+    pub temperature: Option<f32>,
+}
+
+impl Default for CaptureStep {
+    fn default() -> Self {
+        Self {
+            frame_type: FrameType::Light,
+            exposure: 1.0,
+            count: 1,
+            binning: 1,
+            gain: 0,
+            roi: None,
+            temperature: None,
+        }
+    }
+}
+
+impl CaptureStep {
+    fn exposure_seconds(&self) -> f64 {
+        match self.frame_type {
+            FrameType::Bias => 0.0,
+            _ => self.exposure,
+        }
+    }
+}
+
+/// An ordered list of capture steps, shareable as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CapturePlan {
+    /// Plan name, also used as the file name prefix.
+    pub name: String,
+    /// Steps, executed in order.
+    pub steps: Vec<CaptureStep>,
+}
+
+impl CapturePlan {
+    /// Loads a plan from a JSON file.
+    pub fn load(path: &Path) -> Result<CapturePlan, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&text).map_err(|e| e.to_string())
+    }
+
+    /// Saves the plan as pretty-printed JSON.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| e.to_string())
+    }
+
+    /// Total number of frames the plan will take.
+    pub fn total_frames(&self) -> u32 {
+        self.steps.iter().map(|s| s.count).sum()
+    }
+
+    /// Shows the step editor.
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Plan Name");
+            ui.text_edit_singleline(&mut self.name);
+        });
+
+        let mut remove = None;
+        let mut swap = None;
+        let n_steps = self.steps.len();
+
+        egui::ScrollArea::vertical()
+            .max_height(300.0)
+            .show(ui, |ui| {
+                egui::Grid::new("CapturePlanSteps")
+                    .striped(true)
+                    .num_columns(9)
+                    .show(ui, |ui| {
+                        ui.label("#");
+                        ui.label("Type");
+                        ui.label("Exposure");
+                        ui.label("Count");
+                        ui.label("Bin");
+                        ui.label("Gain");
+                        ui.label("ROI [x, y, w, h]");
+                        ui.label("Temperature");
+                        ui.label("");
+                        ui.end_row();
+
+                        for (i, step) in self.steps.iter_mut().enumerate() {
+                            ui.label(format!("{}", i + 1));
+
+                            egui::ComboBox::from_id_source(("PlanStepType", i))
+                                .selected_text(format!("{:?}", step.frame_type))
+                                .show_ui(ui, |ui| {
+                                    for t in FrameType::ALL {
+                                        ui.selectable_value(
+                                            &mut step.frame_type,
+                                            t,
+                                            format!("{:?}", t),
+                                        );
+                                    }
+                                });
+
+                            ui.add_enabled(
+                                step.frame_type != FrameType::Bias,
+                                egui::DragValue::new(&mut step.exposure)
+                                    .range(0.0..=3600.0)
+                                    .speed(0.1)
+                                    .suffix(" s"),
+                            );
+                            ui.add(egui::DragValue::new(&mut step.count).range(1..=9999));
+                            ui.add(egui::DragValue::new(&mut step.binning).range(1..=8));
+                            ui.add(egui::DragValue::new(&mut step.gain));
+
+                            ui.horizontal(|ui| {
+                                let mut use_roi = step.roi.is_some();
+                                ui.checkbox(&mut use_roi, "");
+                                match (use_roi, &mut step.roi) {
+                                    (true, Some(roi)) => {
+                                        for v in roi.iter_mut() {
+                                            ui.add(egui::DragValue::new(v));
+                                        }
+                                    }
+                                    (true, None) => step.roi = Some([0, 0, 0, 0]),
+                                    (false, _) => step.roi = None,
+                                }
+                            });
+
+                            ui.horizontal(|ui| {
+                                let mut use_temp = step.temperature.is_some();
+                                ui.checkbox(&mut use_temp, "");
+                                match (use_temp, &mut step.temperature) {
+                                    (true, Some(t)) => {
+                                        ui.add(
+                                            egui::DragValue::new(t)
+                                                .range(-80.0..=30.0)
+                                                .suffix(" °C"),
+                                        );
+                                    }
+                                    (true, None) => step.temperature = Some(0.0),
+                                    (false, _) => step.temperature = None,
+                                }
+                            });
+
+                            ui.horizontal(|ui| {
+                                if ui.add_enabled(i > 0, egui::Button::new("⬆")).clicked() {
+                                    swap = Some((i - 1, i));
+                                }
+                                if ui
+                                    .add_enabled(i + 1 < n_steps, egui::Button::new("⬇"))
+                                    .clicked()
+                                {
+                                    swap = Some((i, i + 1));
+                                }
+                                if ui.button("✖").clicked() {
+                                    remove = Some(i);
+                                }
+                            });
+                            ui.end_row();
+                        }
+                    });
+            });
+
+        if let Some((a, b)) = swap {
+            self.steps.swap(a, b);
+        }
+        if let Some(i) = remove {
+            self.steps.remove(i);
+        }
+
+        if ui.button("Add Step").clicked() {
+            let step = self.steps.last().cloned().unwrap_or_default();
+            self.steps.push(step);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum RunnerState {
+    Configure,
+    WaitTemperature {
+        deadline: f64,
+    },
+    Expose,
+    WaitFrame {
+        deadline: f64,
+        request: Option<FrameRequest>,
+    },
+    Done,
+    Failed(String),
+}
+
+/// Executes a [`CapturePlan`] step by step, saving each frame as FITS.
+pub struct PlanRunner {
+    plan: CapturePlan,
+    output_dir: PathBuf,
+    /// UTC start time in file-name form, so runs of the same plan do not collide.
+    session: String,
+    step: usize,
+    taken: u32,
+    completed: u32,
+    state: RunnerState,
+}
+
+impl PlanRunner {
+    /// Starts executing `plan`, writing frames into `output_dir`.
+    pub fn new(plan: CapturePlan, output_dir: PathBuf) -> PlanRunner {
+        PlanRunner {
+            plan,
+            output_dir,
+            session: clock::file_stamp(clock::unix_time()),
+            step: 0,
+            taken: 0,
+            completed: 0,
+            state: RunnerState::Configure,
+        }
+    }
+
+    /// Whether the plan has completed or failed.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, RunnerState::Done | RunnerState::Failed(_))
+    }
+
+    /// Abandons the plan, recording `reason` as the failure.
+    pub fn stop(&mut self, reason: &str) {
+        self.state = RunnerState::Failed(reason.to_owned());
+    }
+
+    /// The frame type of the step being executed.
+    pub fn current_frame_type(&self) -> Option<FrameType> {
+        self.plan.steps.get(self.step).map(|s| s.frame_type)
+    }
+
+    /// The settings the running step takes its frames with, for calibrating them.
+    pub fn frame_info(&self, temperature: Option<f32>) -> Option<FrameInfo> {
+        self.plan.steps.get(self.step).map(|s| FrameInfo {
+            exposure: s.exposure_seconds(),
+            temperature,
+            binning: s.binning,
+        })
+    }
+
+    /// Fraction of the plan's frames taken so far.
+    pub fn progress(&self) -> f32 {
+        let total = self.plan.total_frames();
+        if total == 0 {
+            1.0
+        } else {
+            self.completed as f32 / total as f32
+        }
+    }
+
+    /// A human-readable description of what the runner is doing.
+    pub fn status(&self) -> String {
+        let step = self.plan.steps.get(self.step);
+        let where_ = match step {
+            Some(s) => format!(
+                "Step {}/{} ({:?}), frame {}/{}",
+                self.step + 1,
+                self.plan.steps.len(),
+                s.frame_type,
+                self.taken + 1,
+                s.count
+            ),
+            None => String::new(),
+        };
+        match &self.state {
+            RunnerState::Configure => format!("{}: configuring", where_),
+            RunnerState::WaitTemperature { .. } => format!(
+                "{}: waiting for {:.1} °C",
+                where_,
+                step.and_then(|s| s.temperature).unwrap_or_default()
+            ),
+            RunnerState::Expose | RunnerState::WaitFrame { .. } => format!("{}: exposing", where_),
+            RunnerState::Done => format!("Plan complete, {} frames saved.", self.completed),
+            RunnerState::Failed(e) => format!("Plan failed: {}", e),
+        }
+    }
+
+    /// Records the image request sent for the frame being waited on, so that frames from
+    /// earlier exposures are not saved in its place.
+    pub fn requested(&mut self, sent: FrameRequest) {
+        if let RunnerState::WaitFrame { request, .. } = &mut self.state {
+            *request = Some(sent);
+        }
+    }
+
+    /// Advances the plan.
+    ///
+    /// `temperature` is the latest reported sensor temperature and `frames` are the frames that
+    /// arrived since the last call, oldest first. Returns the requests to send to the server.
+    pub fn poll(
+        &mut self,
+        now: f64,
+        temperature: Option<f32>,
+        frames: &[RawFrame],
+    ) -> Vec<ServerRequest> {
+        let mut out = Vec::new();
+
+        // Skip over empty steps.
+        while self.plan.steps.get(self.step).is_some_and(|s| s.count == 0) {
+            self.step += 1;
+        }
+        let Some(step) = self.plan.steps.get(self.step).cloned() else {
+            if !self.is_finished() {
+                self.state = RunnerState::Done;
+            }
+            return out;
+        };
+
+        match self.state.clone() {
+            RunnerState::Configure => {
+                out.push(ServerRequest::Command(CameraCommand::SetBinning(
+                    step.binning,
+                )));
+                out.push(ServerRequest::Command(CameraCommand::SetGain(step.gain)));
+                out.push(ServerRequest::Command(CameraCommand::SetRoi(step.roi)));
+                out.push(ServerRequest::Command(CameraCommand::SetExposure(
+                    step.exposure_seconds(),
+                )));
+                if step.temperature.is_some() {
+                    out.push(ServerRequest::Command(CameraCommand::SetCoolerTarget(
+                        step.temperature,
+                    )));
+                    self.state = RunnerState::WaitTemperature {
+                        deadline: now + TEMPERATURE_TIMEOUT,
+                    };
+                } else {
+                    self.state = RunnerState::Expose;
+                }
+            }
+            RunnerState::WaitTemperature { deadline } => {
+                let target = step.temperature.unwrap_or_default();
+                if temperature.is_some_and(|t| (target - t).abs() <= TEMPERATURE_TOLERANCE) {
+                    self.state = RunnerState::Expose;
+                } else if now > deadline {
+                    self.state = RunnerState::Failed(format!(
+                        "sensor did not reach {:.1} °C within {:.0} minutes",
+                        target,
+                        TEMPERATURE_TIMEOUT / 60.0
+                    ));
+                }
+            }
+            RunnerState::Expose => {
+                out.push(ServerRequest::Image);
+                self.state = RunnerState::WaitFrame {
+                    deadline: now + step.exposure_seconds() + FRAME_TIMEOUT,
+                    request: None,
+                };
+            }
+            RunnerState::WaitFrame {
+                deadline,
+                mut request,
+            } => {
+                // Frames from exposures started before the request was sent are skipped.
+                let frame = frames
+                    .iter()
+                    .find(|f| request.as_mut().map_or(true, |r| r.accepts(f)));
+                if let Some(frame) = frame {
+                    if let Err(e) = self.save(&step, frame, temperature) {
+                        self.state = RunnerState::Failed(e);
+                        return out;
+                    }
+                    self.taken += 1;
+                    self.completed += 1;
+                    if self.taken >= step.count {
+                        self.step += 1;
+                        self.taken = 0;
+                        self.state = RunnerState::Configure;
+                    } else {
+                        self.state = RunnerState::Expose;
+                    }
+                } else if now > deadline {
+                    self.state = RunnerState::Failed("timed out waiting for a frame".into());
+                } else {
+                    self.state = RunnerState::WaitFrame { deadline, request };
+                }
+            }
+            RunnerState::Done | RunnerState::Failed(_) => {}
+        }
+
+        out
+    }
+
+    fn save(
+        &self,
+        step: &CaptureStep,
+        frame: &RawFrame,
+        temperature: Option<f32>,
+    ) -> Result<(), String> {
+        let name = fits::file_name_part(&self.plan.name);
+        let file = format!(
+            "{}_{}_{:02}_{}_{}s_{:03}.fits",
+            if name.is_empty() { "plan" } else { &name },
+            self.session,
+            self.step + 1,
+            step.frame_type.as_str(),
+            step.exposure_seconds(),
+            self.taken + 1
+        );
+
+        let mut cards = fits::frame_cards(
+            step.frame_type.imagetyp(),
+            step.exposure_seconds(),
+            frame.timestamp,
+            frame.bit_depth,
+            temperature,
+        );
+        cards.push(HeaderCard::new(
+            "XBINNING",
+            HeaderValue::Int(step.binning as i64),
+            "binning factor in x",
+        ));
+        cards.push(HeaderCard::new(
+            "YBINNING",
+            HeaderValue::Int(step.binning as i64),
+            "binning factor in y",
+        ));
+        cards.push(HeaderCard::new(
+            "GAIN",
+            HeaderValue::Int(step.gain),
+            "sensor gain",
+        ));
+        if let Some(t) = step.temperature {
+            cards.push(HeaderCard::new(
+                "SET-TEMP",
+                HeaderValue::Float(t as f64),
+                "cooler setpoint [C]",
+            ));
+        }
+
+        fits::write_fits(&self.output_dir.join(file), frame, &cards).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test's output.
+    fn output_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gencam_plan_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn frame(request: Option<u64>) -> RawFrame {
+        RawFrame {
+            width: 4,
+            height: 2,
+            channels: 1,
+            bit_depth: 8,
+            data: vec![10; 8],
+            timestamp: 1_700_000_000.0,
+            request,
+            sequence: None,
+        }
+    }
+
+    fn saved(dir: &Path) -> usize {
+        std::fs::read_dir(dir).unwrap().count()
+    }
+
+    #[test]
+    fn runs_steps_in_order() {
+        let dir = output_dir("steps");
+        let plan = CapturePlan {
+            name: "test".into(),
+            steps: vec![
+                CaptureStep {
+                    count: 2,
+                    ..Default::default()
+                },
+                CaptureStep {
+                    frame_type: FrameType::Bias,
+                    count: 0,
+                    ..Default::default()
+                },
+                CaptureStep {
+                    frame_type: FrameType::Dark,
+                    exposure: 2.0,
+                    binning: 2,
+                    ..Default::default()
+                },
+            ],
+        };
+        let mut runner = PlanRunner::new(plan, dir.clone());
+
+        let configure = runner.poll(0.0, None, &[]);
+        assert_eq!(
+            configure,
+            [
+                ServerRequest::Command(CameraCommand::SetBinning(1)),
+                ServerRequest::Command(CameraCommand::SetGain(0)),
+                ServerRequest::Command(CameraCommand::SetRoi(None)),
+                ServerRequest::Command(CameraCommand::SetExposure(1.0)),
+            ]
+        );
+        for taken in 1..=2 {
+            assert_eq!(runner.poll(1.0, None, &[]), [ServerRequest::Image]);
+            assert!(runner.poll(2.0, None, &[]).is_empty());
+            runner.poll(3.0, None, &[frame(None)]);
+            assert_eq!(saved(&dir), taken);
+        }
+        assert_eq!(runner.current_frame_type(), Some(FrameType::Bias));
+
+        // The empty bias step is skipped.
+        let configure = runner.poll(4.0, None, &[]);
+        assert_eq!(runner.current_frame_type(), Some(FrameType::Dark));
+        assert!(configure.contains(&ServerRequest::Command(CameraCommand::SetBinning(2))));
+        assert!(configure.contains(&ServerRequest::Command(CameraCommand::SetExposure(2.0))));
+        assert_eq!(runner.poll(5.0, None, &[]), [ServerRequest::Image]);
+        runner.poll(6.0, None, &[frame(None)]);
+        runner.poll(7.0, None, &[]);
+
+        assert!(runner.is_finished());
+        assert_eq!(runner.progress(), 1.0);
+        assert_eq!(runner.status(), "Plan complete, 3 frames saved.");
+        assert_eq!(saved(&dir), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skips_frames_answering_other_requests() {
+        let dir = output_dir("requests");
+        let plan = CapturePlan {
+            name: "test".into(),
+            steps: vec![CaptureStep::default()],
+        };
+        let mut runner = PlanRunner::new(plan, dir.clone());
+        runner.poll(0.0, None, &[]);
+        runner.poll(0.0, None, &[]);
+        runner.requested(FrameRequest::new(7, true, false));
+
+        runner.poll(1.0, None, &[frame(Some(6)), frame(None)]);
+        assert_eq!(saved(&dir), 0);
+        runner.poll(2.0, None, &[frame(Some(7))]);
+        assert_eq!(saved(&dir), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn times_out_waiting_for_a_frame() {
+        let mut runner = PlanRunner::new(
+            CapturePlan {
+                name: "test".into(),
+                steps: vec![CaptureStep::default()],
+            },
+            output_dir("frame_timeout"),
+        );
+        runner.poll(0.0, None, &[]);
+        runner.poll(0.0, None, &[]);
+        runner.poll(1.0 + FRAME_TIMEOUT - 0.5, None, &[]);
+        assert!(!runner.is_finished());
+        runner.poll(1.0 + FRAME_TIMEOUT + 0.5, None, &[]);
+        assert_eq!(
+            runner.status(),
+            "Plan failed: timed out waiting for a frame"
+        );
+    }
+
+    #[test]
+    fn waits_for_the_setpoint() {
+        let steps = vec![CaptureStep {
+            temperature: Some(-10.0),
+            ..Default::default()
+        }];
+        let mut runner = PlanRunner::new(
+            CapturePlan {
+                name: "test".into(),
+                steps,
+            },
+            output_dir("setpoint"),
+        );
+        let configure = runner.poll(0.0, Some(5.0), &[]);
+        assert!(
+            configure.contains(&ServerRequest::Command(CameraCommand::SetCoolerTarget(
+                Some(-10.0)
+            )))
+        );
+        assert!(runner.poll(60.0, Some(-9.0), &[]).is_empty());
+        assert!(runner.status().ends_with("waiting for -10.0 °C"));
+        runner.poll(120.0, Some(-10.4), &[]);
+        assert_eq!(runner.poll(121.0, Some(-10.4), &[]), [ServerRequest::Image]);
+    }
+
+    #[test]
+    fn gives_up_on_the_setpoint() {
+        let steps = vec![CaptureStep {
+            temperature: Some(-10.0),
+            ..Default::default()
+        }];
+        let mut runner = PlanRunner::new(
+            CapturePlan {
+                name: "test".into(),
+                steps,
+            },
+            output_dir("setpoint_timeout"),
+        );
+        runner.poll(0.0, Some(5.0), &[]);
+        runner.poll(TEMPERATURE_TIMEOUT - 1.0, Some(-5.0), &[]);
+        assert!(!runner.is_finished());
+        runner.poll(TEMPERATURE_TIMEOUT + 1.0, Some(-5.0), &[]);
+        assert!(runner.is_finished());
+        assert_eq!(
+            runner.status(),
+            "Plan failed: sensor did not reach -10.0 °C within 15 minutes"
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//!
+//! # Wall Clock
+//! Wall-clock helpers that work both natively and in the browser.
+//!
+
+/// Seconds since the Unix epoch.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn unix_time() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// Seconds since the Unix epoch.
+///
+/// `SystemTime::now()` panics on `wasm32-unknown-unknown`, so ask the browser instead.
+#[cfg(target_arch = "wasm32")]
+pub fn unix_time() -> f64 {
+    web_sys::js_sys::Date::now() / 1000.0
+}
+
+/// Offset of the local time zone from UTC at Unix time `t`, in seconds.
+pub fn local_offset(t: f64) -> f64 {
+    use chrono::{Local, TimeZone};
+    Local
+        .timestamp_opt(t.floor() as i64, 0)
+        .earliest()
+        .map_or(0, |d| d.offset().local_minus_utc()) as f64
+}
+
+/// Splits a Unix timestamp into (year, month, day, hour, minute, second) in UTC.
+pub fn to_utc(t: f64) -> (i64, u32, u32, u32, u32, f64) {
+    let days = (t / 86400.0).floor() as i64;
+    let secs = t - (days as f64) * 86400.0;
+
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let hour = (secs / 3600.0) as u32;
+    let minute = ((secs % 3600.0) / 60.0) as u32;
+    let second = secs % 60.0;
+    (year, month, day, hour, minute, second)
+}
+
+/// Formats a Unix timestamp as an ISO-8601 UTC string, as used by FITS `DATE-OBS`.
+pub fn format_utc(t: f64) -> String {
+    // Round to whole milliseconds before splitting, so 59.9996 s carries into the next minute
+    // rather than printing as 60.000.
+    let ms = (t * 1000.0).round() as i64;
+    let (year, month, day, hour, minute, second) = to_utc(ms.div_euclid(1000) as f64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second as u32,
+        ms.rem_euclid(1000)
+    )
+}
+
+/// Formats a Unix timestamp as a compact UTC string suitable for file names.
+pub fn file_stamp(t: f64) -> String {
+    let (year, month, day, hour, minute, second) = to_utc(t);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}",
+        year, month, day, hour, minute, second as u32
+    )
+}
+
+/// Formats a duration in seconds as `m:ss`, or `h:mm:ss` from an hour up.
+pub fn format_duration(seconds: f64) -> String {
+    let total = seconds.ceil() as u64;
+    let (h, m, s) = (total / 3600, total / 60 % 60, total % 60);
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", m, s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_timestamps() {
+        assert_eq!(to_utc(0.0), (1970, 1, 1, 0, 0, 0.0));
+        assert_eq!(to_utc(951_782_400.0), (2000, 2, 29, 0, 0, 0.0));
+        assert_eq!(to_utc(-1.0), (1969, 12, 31, 23, 59, 59.0));
+        assert_eq!(to_utc(1_700_000_000.5), (2023, 11, 14, 22, 13, 20.5));
+    }
+
+    #[test]
+    fn rounds_to_milliseconds() {
+        assert_eq!(format_utc(59.9996), "1970-01-01T00:01:00.000");
+        assert_eq!(format_utc(1.2344), "1970-01-01T00:00:01.234");
+        assert_eq!(file_stamp(951_782_400.0), "20000229T000000");
+    }
+}
@@ -0,0 +1,216 @@
+//!
+//! # Camera Control Messages
+//! Image traffic uses binary `GenCamPacket` frames. Camera settings and status are exchanged as
+//! JSON text frames using the types in this module.
+//!
+
+use serde::{Deserialize, Serialize};
+
+/// A setting change or action requested of the camera.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CameraCommand {
+    /// Address this and all later commands and image requests to the camera with this id.
+    Select(String),
+    /// Exposure time in seconds.
+    SetExposure(f64),
+    /// Sensor gain, in the camera's native units.
+    SetGain(i64),
+    /// Symmetric binning factor.
+    SetBinning(u8),
+    /// Region of interest as `[x, y, width, height]`, or `None` for the full sensor.
+    SetRoi(Option<[u32; 4]>),
+    /// Cooler setpoint in °C, or `None` to switch the cooler off.
+    SetCoolerTarget(Option<f32>),
+    /// Pixel format to capture frames in.
+    SetFormat(CaptureFormat),
+    /// Offset (black level), in the camera's native units.
+    SetOffset(i64),
+    /// USB bandwidth limit, in the camera's native units.
+    SetUsbBandwidth(i64),
+    /// Index into the camera's advertised readout modes.
+    SetReadoutMode(usize),
+    /// Switch the sensor's high-conversion-gain mode on or off.
+    SetHighConversionGain(bool),
+    /// Switch amplifier-glow suppression on or off.
+    SetAntiAmpGlow(bool),
+    /// How exposures are started.
+    SetTriggerMode(TriggerMode),
+    /// Start an exposure now, in software trigger mode.
+    SoftwareTrigger,
+    /// Open the shutter for a bulb exposure lasting at most the given number of seconds.
+    StartBulb(f64),
+    /// End the current exposure now and read out what has been collected.
+    StopExposure,
+    /// End the current exposure now and throw its frame away.
+    AbortExposure,
+    /// Tag the frame answering the next image request with this id, echoed back in
+    /// [`CameraReply::Request`].
+    Tag(u64),
+}
+
+/// Which edge or level of the external trigger input is active.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TriggerPolarity {
+    /// Rising edge, or high level.
+    Positive,
+    /// Falling edge, or low level.
+    Negative,
+}
+
+/// How the camera starts exposures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TriggerMode {
+    /// Expose continuously, one frame after another.
+    FreeRun,
+    /// Expose when sent [`CameraCommand::SoftwareTrigger`].
+    Software,
+    /// Expose on an edge of the external trigger input.
+    ExternalEdge(TriggerPolarity),
+    /// Expose while the external trigger input is at a level.
+    ExternalLevel(TriggerPolarity),
+}
+
+/// Limits of an integer camera control.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ControlRange {
+    /// Smallest accepted value.
+    pub min: i64,
+    /// Largest accepted value.
+    pub max: i64,
+    /// Increment between accepted values.
+    pub step: i64,
+    /// Value the camera starts with.
+    pub default: i64,
+}
+
+impl ControlRange {
+    /// Clamps `value` into the range and rounds it to a multiple of `step` from `min`.
+    pub fn clamp(&self, value: i64) -> i64 {
+        let step = self.step.max(1);
+        let value = value.clamp(self.min, self.max);
+        self.min + (value - self.min) / step * step
+    }
+}
+
+/// The sensor controls a camera supports, sent by the server when a camera is opened.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraCapabilities {
+    /// Camera model, used to key per-model presets.
+    pub model: String,
+    /// Gain limits, if gain is adjustable.
+    pub gain: Option<ControlRange>,
+    /// Offset limits, if offset is adjustable.
+    pub offset: Option<ControlRange>,
+    /// USB bandwidth limits, if bandwidth is adjustable.
+    pub usb_bandwidth: Option<ControlRange>,
+    /// Names of the selectable readout modes; empty if there is no choice.
+    pub readout_modes: Vec<String>,
+    /// Whether the sensor has a switchable high-conversion-gain mode.
+    pub high_conversion_gain: bool,
+    /// Whether the camera can suppress amplifier glow.
+    pub anti_amp_glow: bool,
+    /// Gain at which one electron reads as one ADU, if known.
+    pub unity_gain: Option<i64>,
+    /// Gain giving the lowest read noise, if known.
+    pub lowest_noise_gain: Option<i64>,
+}
+
+/// What the camera is doing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CameraState {
+    /// Not exposing.
+    #[default]
+    Idle,
+    /// Collecting light.
+    Exposing,
+    /// Reading the sensor out.
+    ReadingOut,
+    /// Transferring the frame to the server.
+    Downloading,
+}
+
+/// Status reported by the camera.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraStatus {
+    /// Sensor temperature in °C, if the camera has a sensor.
+    pub temperature: Option<f32>,
+    /// The camera is armed and waiting for a trigger.
+    pub armed: bool,
+    /// What the camera is doing.
+    pub state: CameraState,
+    /// Seconds the current exposure has been running.
+    pub exposure_elapsed: f64,
+    /// Length of the current exposure in seconds.
+    pub exposure_length: f64,
+}
+
+/// A camera available on the server.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraInfo {
+    /// Identifier used to address the camera.
+    pub id: String,
+    /// Camera model.
+    pub model: String,
+    /// Serial number.
+    pub serial: String,
+    /// What the camera is doing.
+    pub state: CameraState,
+    /// Sensor temperature in °C, if the camera has a sensor.
+    pub temperature: Option<f32>,
+}
+
+/// Pixel format frames are captured in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaptureFormat {
+    /// Single-channel intensity.
+    #[default]
+    Gray,
+    /// Raw colour-filter-array data.
+    Bayer,
+    /// Three-channel colour.
+    Rgb,
+}
+
+/// Describes the image that follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageInfo {
+    /// How the samples are arranged.
+    pub format: CaptureFormat,
+    /// Significant bits per sample. Samples deeper than 8 bits are sent as little-endian 16-bit
+    /// words.
+    pub bit_depth: u8,
+    /// The camera's running frame number, if it counts frames.
+    #[serde(default)]
+    pub sequence: Option<u64>,
+}
+
+/// A text frame received from the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CameraReply {
+    /// Periodic or on-change status update.
+    Status(CameraStatus),
+    /// The controls the camera supports.
+    Capabilities(CameraCapabilities),
+    /// The exposure was aborted; any image sent before this belongs to it.
+    Aborted,
+    /// The cameras the server exposes, sent on connection and whenever the list changes.
+    Cameras(Vec<CameraInfo>),
+    /// The replies and images that follow come from the camera with this id.
+    Source(String),
+    /// The next image answers the image request tagged with this id.
+    Request(u64),
+    /// The next image is laid out as described.
+    Image(ImageInfo),
+}
+
+/// Everything the GUI may send to the server.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerRequest {
+    /// A camera control command.
+    Command(CameraCommand),
+    /// Ask for the next image.
+    Image,
+}
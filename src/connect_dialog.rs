@@ -0,0 +1,237 @@
+//!
+//! # Connect Dialog
+//! Lists favorite, recently used and discovered camera servers, with a box for entering a
+//! server URI by hand.
+//!
+
+use eframe::egui;
+use egui::Ui;
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::discovery::Discovery;
+
+/// Number of recently used servers remembered.
+const MAX_RECENT: usize = 10;
+
+/// A server saved under a nickname.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FavoriteServer {
+    /// Websocket URI of the server.
+    pub uri: String,
+    /// Name shown instead of the URI.
+    pub nickname: String,
+}
+
+/// Favorite and recently used servers.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerHistory {
+    /// URIs connected to, most recent first.
+    pub recent: Vec<String>,
+    /// Saved servers, in the order they were added.
+    pub favorites: Vec<FavoriteServer>,
+}
+
+impl ServerHistory {
+    /// Moves `uri` to the top of the recent servers.
+    pub fn used(&mut self, uri: &str) {
+        self.recent.retain(|u| u != uri);
+        self.recent.insert(0, uri.to_owned());
+        self.recent.truncate(MAX_RECENT);
+    }
+
+    /// The nickname `uri` is saved under, if it is a favorite.
+    pub fn nickname(&self, uri: &str) -> Option<&str> {
+        self.favorites
+            .iter()
+            .find(|f| f.uri == uri)
+            .map(|f| f.nickname.as_str())
+    }
+
+    /// Saves `uri` as a favorite called `nickname`, unless it already is one.
+    fn add_favorite(&mut self, uri: &str, nickname: &str) {
+        if self.nickname(uri).is_none() {
+            self.favorites.push(FavoriteServer {
+                uri: uri.to_owned(),
+                nickname: nickname.to_owned(),
+            });
+        }
+    }
+}
+
+/// State behind the connect dialog.
+#[derive(Default)]
+pub struct ConnectDialog {
+    /// Favorite and recent servers.
+    pub history: ServerHistory,
+    #[cfg(not(target_arch = "wasm32"))]
+    discovery: Option<Discovery>,
+    /// Why the last search failed, if it did.
+    error: Option<String>,
+}
+
+impl ConnectDialog {
+    /// Probes the network for servers at GUI time `now`.
+    pub fn search(&mut self, now: f64) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            if self.discovery.is_none() {
+                match Discovery::new() {
+                    Ok(d) => self.discovery = Some(d),
+                    Err(e) => {
+                        self.error = Some(e);
+                        return;
+                    }
+                }
+            }
+            if let Some(d) = &mut self.discovery {
+                self.error = d.search(now).err();
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = now;
+    }
+
+    /// Shows the saved, recent and discovered servers and the manual entry box, editing `uri`.
+    /// `now` is the GUI time in seconds. Returns the URI to connect to.
+    pub fn ui(&mut self, ui: &mut Ui, uri: &mut String, now: f64) -> Option<String> {
+        let mut connect = None;
+        let history = &mut self.history;
+
+        ui.strong("Favorites");
+        if history.favorites.is_empty() {
+            ui.label("Star a server below to keep it here.");
+        }
+        let mut remove = None;
+        egui::Grid::new("FavoriteServers")
+            .striped(true)
+            .show(ui, |ui| {
+                for (i, fav) in history.favorites.iter_mut().enumerate() {
+                    ui.add(egui::TextEdit::singleline(&mut fav.nickname).desired_width(120.0));
+                    ui.label(&fav.uri);
+                    if ui.button("Connect").clicked() {
+                        connect = Some(fav.uri.clone());
+                    }
+                    if ui
+                        .small_button("✖")
+                        .on_hover_text("Remove from favorites")
+                        .clicked()
+                    {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+        if let Some(i) = remove {
+            history.favorites.remove(i);
+        }
+
+        if !history.recent.is_empty() {
+            ui.separator();
+            ui.strong("Recent");
+            let mut star = None;
+            egui::Grid::new("RecentServers")
+                .striped(true)
+                .show(ui, |ui| {
+                    for recent in &history.recent {
+                        ui.label(recent);
+                        if ui.button("Connect").clicked() {
+                            connect = Some(recent.clone());
+                        }
+                        if history.nickname(recent).is_none()
+                            && ui
+                                .small_button("☆")
+                                .on_hover_text("Add to favorites")
+                                .clicked()
+                        {
+                            star = Some(recent.clone());
+                        }
+                        ui.end_row();
+                    }
+                });
+            if let Some(recent) = star {
+                history.add_favorite(&recent, &recent);
+            }
+        }
+        ui.separator();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            ui.horizontal(|ui| {
+                ui.strong("Servers on this network");
+                let searching = self.discovery.as_ref().is_some_and(|d| d.is_searching(now));
+                if searching {
+                    ui.spinner();
+                } else if ui.button("Refresh").clicked() {
+                    self.search(now);
+                }
+            });
+
+            if let Some(d) = &mut self.discovery {
+                let history = &mut self.history;
+                d.poll();
+                if d.servers.is_empty() && !d.is_searching(now) {
+                    ui.label("No servers found.");
+                }
+                egui::Grid::new("DiscoveredServers")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for server in &d.servers {
+                            let a = &server.announcement;
+                            ui.label(&a.hostname);
+                            ui.label(format!("{}:{}", server.address.ip(), a.port));
+                            ui.label(match a.cameras {
+                                1 => "1 camera".to_owned(),
+                                n => format!("{} cameras", n),
+                            });
+                            if ui.button("Connect").clicked() {
+                                connect = Some(server.uri());
+                            }
+                            if history.nickname(&server.uri()).is_none()
+                                && ui
+                                    .small_button("☆")
+                                    .on_hover_text("Add to favorites")
+                                    .clicked()
+                            {
+                                history.add_favorite(&server.uri(), &a.hostname);
+                            }
+                            ui.end_row();
+                        }
+                    });
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = now;
+            ui.label("Browsers cannot search the network; enter the server's URI below.");
+        }
+
+        if let Some(e) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, format!("Search failed: {}", e));
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Server URI");
+            let response = ui.text_edit_singleline(uri);
+            let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            let trimmed = uri.trim().to_owned();
+            if (ui.button("Connect").clicked() || entered) && !trimmed.is_empty() {
+                connect = Some(trimmed.clone());
+            }
+            if ui
+                .add_enabled(
+                    !trimmed.is_empty() && self.history.nickname(&trimmed).is_none(),
+                    egui::Button::new("☆").small(),
+                )
+                .on_hover_text("Add to favorites")
+                .clicked()
+            {
+                self.history.add_favorite(&trimmed, &trimmed);
+            }
+        });
+        connect
+    }
+}
@@ -0,0 +1,361 @@
+//!
+//! # Server Connections
+//! Websocket connections to camera servers, and the manager that keeps several of them open at
+//! once.
+//!
+
+use std::sync::atomic::AtomicBool;
+
+use eframe::egui;
+use egui::Ui;
+use ewebsock::{WsEvent, WsMessage, WsReceiver, WsSender};
+use gencam_packet::GenCamPacket;
+
+use crate::clock;
+use crate::command::{CameraCommand, CameraReply, CameraState, ImageInfo, ServerRequest};
+
+/// Seconds to drop an aborted camera's images if the server never confirms the abort.
+const DISCARD_TIMEOUT: f64 = 10.0;
+
+/// State of a server connection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionStatus {
+    /// Waiting for the websocket to open.
+    Connecting,
+    /// Open and exchanging messages.
+    Connected,
+    /// Closed by the server.
+    Closed,
+    /// Failed with an error.
+    Error(String),
+}
+
+/// Identifies a camera across all connected servers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CameraKey {
+    /// URI of the server the camera is on.
+    pub server: String,
+    /// The camera's id on that server.
+    pub id: String,
+}
+
+/// An image received from a server.
+pub struct ImageEvent {
+    /// Id of the camera that sent it, if the server named one.
+    pub source: Option<String>,
+    /// Id of the image request it answers, if the server echoed one.
+    pub request: Option<u64>,
+    /// Its layout, if the server described it.
+    pub info: Option<ImageInfo>,
+    /// The websocket event carrying the image.
+    pub event: WsEvent,
+}
+
+/// Images being dropped after an abort, so the partial frame is not shown.
+struct Discard {
+    /// The aborted camera, or `None` if the server does not name cameras.
+    camera: Option<String>,
+    /// Unix time after which images are accepted again.
+    until: f64,
+}
+
+/// One websocket connection to a camera server.
+pub struct WsBackend {
+    /// The URI of the websocket server.
+    pub uri: String,
+    /// State of the connection.
+    pub status: ConnectionStatus,
+    ws_sender: WsSender,
+    ws_receiver: WsReceiver,
+    /// Everything received that is not an image or a control reply, for the communication log.
+    pub events: Vec<WsEvent>,
+    /// Images received since they were last taken.
+    pub image_events: Vec<ImageEvent>,
+    /// Set when images have arrived since it was last cleared.
+    pub new_image_event: AtomicBool,
+    /// Control replies received as text frames, waiting to be handled by the GUI, with the id of
+    /// the camera that sent them if the server named one.
+    pub replies: Vec<(Option<String>, CameraReply)>,
+    /// Images being dropped after an abort.
+    discard: Option<Discard>,
+    /// The camera the server last said it is sending from.
+    source: Option<String>,
+    /// The camera commands are currently addressed to.
+    target: Option<String>,
+    /// Whether the server has echoed image request ids.
+    pub echoes_requests: bool,
+    /// Id of the request the next image answers, as the server last announced.
+    request: Option<u64>,
+    /// Layout of the next image, as the server last announced.
+    info: Option<ImageInfo>,
+    /// Id of the last image request sent.
+    last_request: u64,
+}
+
+impl WsBackend {
+    /// Opens a connection to `uri`, waking `ctx` up whenever a message arrives.
+    pub fn connect(uri: &str, ctx: &Option<egui::Context>) -> Result<WsBackend, String> {
+        let res = if let Some(ctx) = ctx {
+            let ctx = ctx.clone();
+            let wakeup = move || ctx.request_repaint();
+            ewebsock::connect_with_wakeup(uri, Default::default(), wakeup)
+        } else {
+            ewebsock::connect(uri, Default::default())
+        };
+        let (ws_sender, ws_receiver) = res?;
+        Ok(WsBackend {
+            uri: uri.to_owned(),
+            status: ConnectionStatus::Connecting,
+            ws_sender,
+            ws_receiver,
+            events: Vec::new(),
+            image_events: Vec::new(),
+            new_image_event: AtomicBool::new(false),
+            replies: Vec::new(),
+            discard: None,
+            source: None,
+            target: None,
+            echoes_requests: false,
+            request: None,
+            info: None,
+            last_request: 0,
+        })
+    }
+
+    /// Closes the connection.
+    pub fn close(&mut self) {
+        self.ws_sender.close();
+    }
+
+    /// Sends a request to the server. Images are requested with a `GenCamPacket`, everything else
+    /// goes out as a JSON text frame.
+    ///
+    /// Image requests are tagged with a new id first, which is returned.
+    pub fn send(&mut self, request: &ServerRequest) -> Option<u64> {
+        match request {
+            ServerRequest::Image => {
+                self.last_request += 1;
+                self.send(&ServerRequest::Command(CameraCommand::Tag(
+                    self.last_request,
+                )));
+                self.ws_sender.send(WsMessage::Binary(
+                    serde_json::to_vec(&GenCamPacket::image_request()).unwrap(),
+                ));
+                Some(self.last_request)
+            }
+            ServerRequest::Command(cmd) => {
+                self.ws_sender
+                    .send(WsMessage::Text(serde_json::to_string(cmd).unwrap()));
+                None
+            }
+        }
+    }
+
+    /// Aborts the selected camera's exposure and drops its images until the server reports the
+    /// abort, the camera reports it is idle, or [`DISCARD_TIMEOUT`] passes.
+    pub fn abort(&mut self) {
+        self.discard = Some(Discard {
+            camera: self.target.clone(),
+            until: clock::unix_time() + DISCARD_TIMEOUT,
+        });
+        self.send(&ServerRequest::Command(CameraCommand::AbortExposure));
+    }
+
+    /// Whether images and replies from the camera the server last named are those of the
+    /// aborted camera.
+    fn source_discarded(&self) -> bool {
+        self.discard
+            .as_ref()
+            .is_some_and(|d| d.camera.is_none() || d.camera == self.source)
+    }
+
+    /// Addresses later commands and image requests to camera `id`, if they are not already.
+    pub fn select(&mut self, id: &str) {
+        if self.target.as_deref() != Some(id) {
+            self.target = Some(id.to_owned());
+            self.send(&ServerRequest::Command(CameraCommand::Select(
+                id.to_owned(),
+            )));
+        }
+    }
+
+    /// Sorts every received event into images, control replies and the general event log.
+    /// Returns the new status if it changed.
+    pub fn poll(&mut self) -> Option<ConnectionStatus> {
+        let status = self.status.clone();
+        if self
+            .discard
+            .as_ref()
+            .is_some_and(|d| clock::unix_time() > d.until)
+        {
+            self.discard = None;
+        }
+        while let Some(event) = self.ws_receiver.try_recv() {
+            match event.clone() {
+                WsEvent::Message(WsMessage::Binary(data)) => {
+                    // All messages should be binary
+                    let pkt: GenCamPacket = match serde_json::from_slice(&data) {
+                        Ok(pkt) => pkt,
+                        Err(e) => {
+                            log::warn!("Malformed packet from {}: {}", self.uri, e);
+                            self.status =
+                                ConnectionStatus::Error(format!("malformed packet: {}", e));
+                            self.request = None;
+                            self.info = None;
+                            continue;
+                        }
+                    };
+                    match pkt {
+                        GenCamPacket::Image { .. } if self.source_discarded() => {
+                            self.request = None;
+                            self.info = None;
+                        }
+                        GenCamPacket::Image { .. } => {
+                            self.image_events.push(ImageEvent {
+                                source: self.source.clone(),
+                                request: self.request.take(),
+                                info: self.info.take(),
+                                event,
+                            });
+                            self.new_image_event = AtomicBool::new(true);
+                        }
+                        _ => {
+                            self.events.push(event);
+                        }
+                    }
+                }
+                WsEvent::Message(WsMessage::Text(text)) => {
+                    // Text frames carry control replies; anything unrecognized is just logged.
+                    if let Ok(reply) = serde_json::from_str::<CameraReply>(&text) {
+                        match reply {
+                            CameraReply::Source(id) => self.source = Some(id),
+                            CameraReply::Request(id) => {
+                                self.echoes_requests = true;
+                                self.request = Some(id);
+                            }
+                            CameraReply::Image(info) => self.info = Some(info),
+                            reply => {
+                                let finished = match &reply {
+                                    CameraReply::Aborted => true,
+                                    CameraReply::Status(s) => s.state == CameraState::Idle,
+                                    _ => false,
+                                };
+                                if finished && self.source_discarded() {
+                                    self.discard = None;
+                                }
+                                self.replies.push((self.source.clone(), reply));
+                            }
+                        }
+                    }
+                    self.events.push(event);
+                }
+                WsEvent::Opened => {
+                    self.status = ConnectionStatus::Connected;
+                    self.events.push(event);
+                }
+                WsEvent::Closed => {
+                    self.status = ConnectionStatus::Closed;
+                    self.events.push(event);
+                }
+                WsEvent::Error(e) => {
+                    self.status = ConnectionStatus::Error(e);
+                    self.events.push(event);
+                }
+                _ => {
+                    self.events.push(event);
+                }
+            }
+        }
+        (self.status != status).then(|| self.status.clone())
+    }
+
+    /// Shows buttons for sending raw test packets.
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Send Ack").clicked() {
+                let pkt = GenCamPacket::ack();
+                // Set msg to serialized pkt.
+                let msg = serde_json::to_vec(&pkt).unwrap();
+                // Send
+                self.ws_sender.send(WsMessage::Binary(msg));
+            }
+
+            if ui.button("Send NAck").clicked() {
+                let pkt = GenCamPacket::nack();
+                // Set msg to serialized pkt.
+                let msg = serde_json::to_vec(&pkt).unwrap();
+                // Send
+                self.ws_sender.send(WsMessage::Binary(msg));
+            }
+
+            if ui.button("Send ImgReq").clicked() {
+                let pkt = GenCamPacket::image_request();
+                // Set msg to serialized pkt.
+                let msg = serde_json::to_vec(&pkt).unwrap();
+                // Send
+                self.ws_sender.send(WsMessage::Binary(msg));
+            }
+        });
+    }
+}
+
+/// All open server connections.
+#[derive(Default)]
+pub struct ConnectionManager {
+    /// Connections in the order they were opened.
+    pub connections: Vec<WsBackend>,
+}
+
+impl ConnectionManager {
+    /// Opens a connection to `uri`, unless one is already open.
+    pub fn connect(&mut self, uri: &str, ctx: &Option<egui::Context>) -> Result<(), String> {
+        if self.get_mut(uri).is_some() {
+            return Err(format!("Already connected to {}", uri));
+        }
+        self.connections.push(WsBackend::connect(uri, ctx)?);
+        Ok(())
+    }
+
+    /// Closes and forgets the connection to `uri`.
+    pub fn disconnect(&mut self, uri: &str) {
+        if let Some(i) = self.connections.iter().position(|c| c.uri == uri) {
+            self.connections.remove(i).close();
+        }
+    }
+
+    /// Whether no connections are open.
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// The connection to `uri`.
+    pub fn get_mut(&mut self, uri: &str) -> Option<&mut WsBackend> {
+        self.connections.iter_mut().find(|c| c.uri == uri)
+    }
+
+    /// Whether [`ConnectionManager::for_camera`] would find a connection.
+    pub fn serves(&self, key: Option<&CameraKey>) -> bool {
+        match key {
+            Some(key) => self.connections.iter().any(|c| c.uri == key.server),
+            None => self.connections.len() == 1,
+        }
+    }
+
+    /// The connection serving camera `key`. With no camera given, the only connection if there
+    /// is exactly one, so servers that do not list their cameras still work.
+    pub fn for_camera(&mut self, key: Option<&CameraKey>) -> Option<&mut WsBackend> {
+        match key {
+            Some(key) => self.get_mut(&key.server),
+            None if self.connections.len() == 1 => self.connections.first_mut(),
+            None => None,
+        }
+    }
+
+    /// Polls every connection. Returns the URI and new status of each one that changed.
+    pub fn poll(&mut self) -> Vec<(String, ConnectionStatus)> {
+        self.connections
+            .iter_mut()
+            .filter_map(|c| c.poll().map(|s| (c.uri.clone(), s)))
+            .collect()
+    }
+}
@@ -0,0 +1,204 @@
+//!
+//! # Server Discovery
+//! Finds camera servers on the local network. The GUI broadcasts a probe over UDP and each
+//! server's beacon answers with its hostname, websocket port and number of cameras.
+//!
+
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+use serde::{Deserialize, Serialize};
+
+/// UDP port beacons listen on for probes.
+pub const DISCOVERY_PORT: u16 = 9091;
+
+/// The datagram a client broadcasts to ask servers to announce themselves.
+const PROBE: &[u8] = b"GENCAM_DISCOVER";
+
+/// Seconds to collect answers after a probe.
+const SEARCH_TIME: f64 = 2.0;
+
+/// What a server's beacon reports about it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerAnnouncement {
+    /// Host name of the machine the server runs on.
+    pub hostname: String,
+    /// Port the websocket server listens on.
+    pub port: u16,
+    /// Number of cameras the server has.
+    pub cameras: usize,
+}
+
+/// A server that answered a probe.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredServer {
+    /// The address the answer came from.
+    pub address: SocketAddr,
+    /// What the server reported.
+    pub announcement: ServerAnnouncement,
+}
+
+impl DiscoveredServer {
+    /// The websocket URI to connect to the server.
+    pub fn uri(&self) -> String {
+        format!(
+            "ws://{}",
+            SocketAddr::new(self.address.ip(), self.announcement.port)
+        )
+    }
+}
+
+/// Sends probes and collects the servers that answer.
+pub struct Discovery {
+    socket: UdpSocket,
+    target: SocketAddr,
+    /// Servers that answered the latest probe, in the order they answered.
+    pub servers: Vec<DiscoveredServer>,
+    /// GUI time at which the current search ends, in seconds.
+    searching_until: f64,
+}
+
+impl Discovery {
+    /// Prepares to broadcast probes to [`DISCOVERY_PORT`] on the local network.
+    pub fn new() -> Result<Discovery, String> {
+        Discovery::with_target(SocketAddr::from((Ipv4Addr::BROADCAST, DISCOVERY_PORT)))
+    }
+
+    /// Prepares to send probes to `target` instead of broadcasting.
+    pub fn with_target(target: SocketAddr) -> Result<Discovery, String> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).map_err(|e| e.to_string())?;
+        socket.set_broadcast(true).map_err(|e| e.to_string())?;
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(Discovery {
+            socket,
+            target,
+            servers: Vec::new(),
+            searching_until: 0.0,
+        })
+    }
+
+    /// Forgets earlier answers and sends a new probe at GUI time `now`.
+    pub fn search(&mut self, now: f64) -> Result<(), String> {
+        self.servers.clear();
+        self.socket
+            .send_to(PROBE, self.target)
+            .map_err(|e| e.to_string())?;
+        self.searching_until = now + SEARCH_TIME;
+        Ok(())
+    }
+
+    /// Whether answers to the latest probe are still expected at GUI time `now`.
+    pub fn is_searching(&self, now: f64) -> bool {
+        now < self.searching_until
+    }
+
+    /// Reads every answer that has arrived. Malformed answers are ignored.
+    pub fn poll(&mut self) {
+        let mut buf = [0; 1024];
+        while let Ok((len, address)) = self.socket.recv_from(&mut buf) {
+            let Ok(announcement) = serde_json::from_slice::<ServerAnnouncement>(&buf[..len]) else {
+                continue;
+            };
+            let server = DiscoveredServer {
+                address,
+                announcement,
+            };
+            match self.servers.iter_mut().find(|s| s.uri() == server.uri()) {
+                Some(existing) => *existing = server,
+                None => self.servers.push(server),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Answers discovery probes from a background thread, standing in for a server's beacon.
+    struct Beacon {
+        address: SocketAddr,
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl Beacon {
+        /// Starts answering probes received on `bind` with `announcement`, until dropped.
+        fn spawn(bind: SocketAddr, announcement: ServerAnnouncement) -> Result<Beacon, String> {
+            let socket = UdpSocket::bind(bind).map_err(|e| e.to_string())?;
+            // Wake up regularly to notice when the beacon is dropped.
+            socket
+                .set_read_timeout(Some(Duration::from_millis(100)))
+                .map_err(|e| e.to_string())?;
+            let address = socket.local_addr().map_err(|e| e.to_string())?;
+            let reply = serde_json::to_vec(&announcement).map_err(|e| e.to_string())?;
+
+            let stop = Arc::new(AtomicBool::new(false));
+            let stopped = stop.clone();
+            let thread = std::thread::spawn(move || {
+                let mut buf = [0; 64];
+                while !stopped.load(Ordering::Relaxed) {
+                    if let Ok((len, from)) = socket.recv_from(&mut buf) {
+                        if &buf[..len] == PROBE {
+                            let _ = socket.send_to(&reply, from);
+                        }
+                    }
+                }
+            });
+            Ok(Beacon {
+                address,
+                stop,
+                thread: Some(thread),
+            })
+        }
+
+        /// The address the beacon listens on.
+        fn address(&self) -> SocketAddr {
+            self.address
+        }
+    }
+
+    impl Drop for Beacon {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    #[test]
+    fn finds_beacon() {
+        let announcement = ServerAnnouncement {
+            hostname: "observatory".into(),
+            port: 9001,
+            cameras: 2,
+        };
+        let beacon = Beacon::spawn(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            announcement.clone(),
+        )
+        .unwrap();
+        let mut discovery = Discovery::with_target(beacon.address()).unwrap();
+        discovery.search(0.0).unwrap();
+        assert!(discovery.is_searching(0.0));
+
+        for _ in 0..200 {
+            discovery.poll();
+            if !discovery.servers.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(discovery.servers.len(), 1);
+        let server = &discovery.servers[0];
+        assert_eq!(server.announcement.hostname, "observatory");
+        assert_eq!(server.announcement.port, 9001);
+        assert_eq!(server.announcement.cameras, 2);
+        assert_eq!(server.uri(), "ws://127.0.0.1:9001");
+    }
+}
@@ -0,0 +1,300 @@
+//!
+//! # Display Rendering
+//! Stretches raw frames to 8 bits and maps mono frames through a false-color palette.
+//!
+
+use eframe::egui;
+use egui::{Color32, Ui};
+use serde::{Deserialize, Serialize};
+
+use crate::frame::RawFrame;
+use crate::stats;
+
+/// Percentiles the automatic stretch maps to black and white.
+const AUTO_LEVELS: (f32, f32) = (0.5, 99.5);
+
+/// Viridis, sampled at nine even steps.
+const VIRIDIS: [(f32, [u8; 3]); 9] = [
+    (0.0, [68, 1, 84]),
+    (0.125, [71, 45, 123]),
+    (0.25, [59, 82, 139]),
+    (0.375, [44, 114, 142]),
+    (0.5, [33, 145, 140]),
+    (0.625, [40, 174, 128]),
+    (0.75, [94, 201, 98]),
+    (0.875, [173, 220, 48]),
+    (1.0, [253, 231, 37]),
+];
+
+/// Inferno, sampled at nine even steps.
+const INFERNO: [(f32, [u8; 3]); 9] = [
+    (0.0, [0, 0, 4]),
+    (0.125, [31, 12, 72]),
+    (0.25, [85, 15, 109]),
+    (0.375, [136, 34, 106]),
+    (0.5, [186, 54, 85]),
+    (0.625, [227, 89, 51]),
+    (0.75, [249, 142, 9]),
+    (0.875, [249, 203, 53]),
+    (1.0, [252, 255, 164]),
+];
+
+/// The classic MATLAB jet palette.
+const JET: [(f32, [u8; 3]); 6] = [
+    (0.0, [0, 0, 128]),
+    (0.125, [0, 0, 255]),
+    (0.375, [0, 255, 255]),
+    (0.625, [255, 255, 0]),
+    (0.875, [255, 0, 0]),
+    (1.0, [128, 0, 0]),
+];
+
+/// Black body style palette running through red and yellow to white.
+const HOT: [(f32, [u8; 3]); 4] = [
+    (0.0, [0, 0, 0]),
+    (0.375, [255, 0, 0]),
+    (0.75, [255, 255, 0]),
+    (1.0, [255, 255, 255]),
+];
+
+/// How raw values are mapped to display brightness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stretch {
+    /// Zero to the sensor's full scale.
+    Full,
+    /// Low and high percentiles of the frame.
+    Auto,
+    /// User-set black and white points.
+    Manual,
+}
+
+/// The palette mono frames are drawn with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Colormap {
+    /// Grayscale.
+    Gray,
+    /// Perceptually uniform blue-green-yellow.
+    Viridis,
+    /// Perceptually uniform black-purple-yellow.
+    Inferno,
+    /// Blue-cyan-yellow-red rainbow.
+    Jet,
+    /// Black-red-yellow-white.
+    Hot,
+    /// A gradient between two user-chosen colors.
+    Custom,
+}
+
+impl Colormap {
+    /// All colormaps, in display order.
+    pub const ALL: [Colormap; 6] = [
+        Colormap::Gray,
+        Colormap::Viridis,
+        Colormap::Inferno,
+        Colormap::Jet,
+        Colormap::Hot,
+        Colormap::Custom,
+    ];
+}
+
+/// Linearly interpolates the color at `t` (0-1) between palette stops.
+fn interpolate(stops: &[(f32, [u8; 3])], t: f32) -> [u8; 3] {
+    let i = stops
+        .windows(2)
+        .position(|w| t <= w[1].0)
+        .unwrap_or(stops.len() - 2);
+    let ((t0, c0), (t1, c1)) = (stops[i], stops[i + 1]);
+    let f = ((t - t0) / (t1 - t0)).clamp(0.0, 1.0);
+    [0, 1, 2].map(|k| (c0[k] as f32 + (c1[k] as f32 - c0[k] as f32) * f).round() as u8)
+}
+
+/// Stretch and colormap settings for the image viewer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplaySettings {
+    /// How raw values are mapped to brightness.
+    pub stretch: Stretch,
+    /// Black point for the manual stretch, in ADU.
+    pub black: u16,
+    /// White point for the manual stretch, in ADU.
+    pub white: u16,
+    /// Palette for mono frames.
+    pub colormap: Colormap,
+    /// Low end of the custom colormap.
+    pub custom_low: [u8; 3],
+    /// High end of the custom colormap.
+    pub custom_high: [u8; 3],
+    /// Black and white points used for the last rendered frame.
+    #[serde(skip)]
+    levels: (u16, u16),
+    /// Whether the last rendered frame was mono.
+    #[serde(skip)]
+    mono: bool,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            stretch: Stretch::Full,
+            black: 0,
+            white: 255,
+            colormap: Colormap::Gray,
+            custom_low: [0, 0, 64],
+            custom_high: [255, 224, 128],
+            levels: (0, 255),
+            mono: false,
+        }
+    }
+}
+
+impl DisplaySettings {
+    /// The palette as a 256-entry lookup table.
+    fn lut(&self) -> Vec<[u8; 3]> {
+        (0..256)
+            .map(|i| {
+                let t = i as f32 / 255.0;
+                match self.colormap {
+                    Colormap::Gray => [i as u8; 3],
+                    Colormap::Viridis => interpolate(&VIRIDIS, t),
+                    Colormap::Inferno => interpolate(&INFERNO, t),
+                    Colormap::Jet => interpolate(&JET, t),
+                    Colormap::Hot => interpolate(&HOT, t),
+                    Colormap::Custom => {
+                        interpolate(&[(0.0, self.custom_low), (1.0, self.custom_high)], t)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Renders `frame` to 8-bit samples. Returns the samples and whether they are RGB rather than
+    /// one gray sample per pixel, or an error for frames that are neither mono nor RGB.
+    pub fn render(&mut self, frame: &RawFrame) -> Result<(Vec<u8>, bool), String> {
+        if frame.channels != 1 && frame.channels != 3 {
+            return Err(format!(
+                "cannot display a frame with {} channels",
+                frame.channels
+            ));
+        }
+        let (black, white) = match self.stretch {
+            Stretch::Full => (0, frame.max_value()),
+            Stretch::Auto => (
+                stats::percentile(frame, AUTO_LEVELS.0),
+                stats::percentile(frame, AUTO_LEVELS.1),
+            ),
+            Stretch::Manual => (self.black, self.white),
+        };
+        let white = white.max(black.saturating_add(1));
+        self.levels = (black, white);
+        self.mono = frame.channels == 1;
+
+        let range = (white - black).max(1) as f32;
+        let stretch = |v: u16| ((v.saturating_sub(black) as f32 / range).min(1.0) * 255.0) as u8;
+        if self.mono && self.colormap != Colormap::Gray {
+            let lut = self.lut();
+            let data = frame
+                .data
+                .iter()
+                .flat_map(|&v| lut[stretch(v) as usize])
+                .collect();
+            Ok((data, true))
+        } else {
+            Ok((frame.data.iter().map(|&v| stretch(v)).collect(), !self.mono))
+        }
+    }
+
+    /// Shows the stretch and colormap settings. Returns whether anything changed.
+    pub fn ui(&mut self, ui: &mut Ui) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Stretch");
+            for (s, name) in [
+                (Stretch::Full, "Full Range"),
+                (Stretch::Auto, "Auto"),
+                (Stretch::Manual, "Manual"),
+            ] {
+                changed |= ui.selectable_value(&mut self.stretch, s, name).changed();
+            }
+        });
+        if self.stretch == Stretch::Manual {
+            ui.horizontal(|ui| {
+                ui.label("Black");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut self.black).range(0..=u16::MAX))
+                    .changed();
+                ui.label("White");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut self.white).range(0..=u16::MAX))
+                    .changed();
+            });
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Colormap");
+            egui::ComboBox::from_id_source("Colormap")
+                .selected_text(format!("{:?}", self.colormap))
+                .show_ui(ui, |ui| {
+                    for c in Colormap::ALL {
+                        changed |= ui
+                            .selectable_value(&mut self.colormap, c, format!("{:?}", c))
+                            .changed();
+                    }
+                });
+            if self.colormap == Colormap::Custom {
+                changed |= ui.color_edit_button_srgb(&mut self.custom_low).changed();
+                ui.label("to");
+                changed |= ui.color_edit_button_srgb(&mut self.custom_high).changed();
+            }
+        });
+        if !self.mono && self.colormap != Colormap::Gray {
+            ui.label("Colormaps apply to mono frames only.");
+        }
+
+        changed
+    }
+
+    /// Draws a horizontal legend mapping the palette back to raw ADU values.
+    pub fn colorbar(&self, ui: &mut Ui) {
+        if !self.mono {
+            return;
+        }
+        let width = ui.available_width().min(512.0);
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(width, 28.0), egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        let bar = egui::Rect::from_min_size(rect.min, egui::vec2(width, 12.0));
+        let lut = self.lut();
+        let step = bar.width() / lut.len() as f32;
+        for (i, c) in lut.iter().enumerate() {
+            let x = bar.min.x + i as f32 * step;
+            painter.rect_filled(
+                egui::Rect::from_min_max(
+                    egui::pos2(x, bar.min.y),
+                    egui::pos2(x + step + 0.5, bar.max.y),
+                ),
+                0.0,
+                Color32::from_rgb(c[0], c[1], c[2]),
+            );
+        }
+
+        let (black, white) = self.levels;
+        let font = egui::TextStyle::Small.resolve(ui.style());
+        let color = ui.visuals().text_color();
+        for (t, align, value) in [
+            (0.0, egui::Align2::LEFT_TOP, black as u32),
+            (
+                0.5,
+                egui::Align2::CENTER_TOP,
+                (black as u32 + white as u32) / 2,
+            ),
+            (1.0, egui::Align2::RIGHT_TOP, white as u32),
+        ] {
+            painter.text(
+                egui::pos2(bar.min.x + t * bar.width(), bar.max.y + 2.0),
+                align,
+                format!("{} ADU", value),
+                font.clone(),
+                color,
+            );
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//!
+//! # Exposure Progress
+//! Follows the camera through each exposure and offers to abort it.
+//!
+
+use eframe::egui;
+use egui::Ui;
+
+use crate::clock::format_duration;
+use crate::command::{CameraState, CameraStatus};
+
+/// The camera's exposure progress, interpolated between status updates.
+#[derive(Default)]
+pub struct ExposureProgress {
+    state: CameraState,
+    elapsed: f64,
+    length: f64,
+    /// GUI time the last status arrived, in seconds.
+    received: f64,
+    /// An abort has been sent and not yet confirmed.
+    aborting: bool,
+}
+
+impl ExposureProgress {
+    /// Takes in a status update received at GUI time `now`.
+    pub fn update(&mut self, status: &CameraStatus, now: f64) {
+        self.state = status.state;
+        self.elapsed = status.exposure_elapsed;
+        self.length = status.exposure_length;
+        self.received = now;
+    }
+
+    /// Notes that an abort was requested.
+    pub fn abort(&mut self) {
+        self.aborting = true;
+    }
+
+    /// Notes that the server confirmed the abort.
+    pub fn aborted(&mut self) {
+        self.aborting = false;
+        self.state = CameraState::Idle;
+    }
+
+    /// Seconds the current exposure has been running at GUI time `now`.
+    fn elapsed(&self, now: f64) -> f64 {
+        match self.state {
+            CameraState::Exposing => (self.elapsed + now - self.received).min(self.length),
+            _ => self.length,
+        }
+    }
+
+    /// Shows the camera state and progress. Returns whether Abort was clicked.
+    pub fn ui(&mut self, ui: &mut Ui, now: f64) -> bool {
+        let mut abort = false;
+        ui.horizontal(|ui| {
+            let (text, color) = match (self.aborting, self.state) {
+                (true, _) => ("Aborting…", ui.visuals().warn_fg_color),
+                (false, CameraState::Idle) => ("Idle", ui.visuals().weak_text_color()),
+                (false, CameraState::Exposing) => ("Exposing", egui::Color32::GREEN),
+                (false, CameraState::ReadingOut) => ("Reading out", ui.visuals().text_color()),
+                (false, CameraState::Downloading) => ("Downloading", ui.visuals().text_color()),
+            };
+            ui.colored_label(color, text);
+            if ui
+                .add_enabled(
+                    self.state != CameraState::Idle && !self.aborting,
+                    egui::Button::new("Abort"),
+                )
+                .on_hover_text("Stop the exposure and discard its frame.")
+                .clicked()
+            {
+                abort = true;
+            }
+        });
+
+        match self.state {
+            CameraState::Exposing if !self.aborting => {
+                let elapsed = self.elapsed(now);
+                let remaining = (self.length - elapsed).max(0.0);
+                let progress = (elapsed / self.length.max(1e-3)) as f32;
+                ui.add(egui::ProgressBar::new(progress).text(format!(
+                    "{} / {}, {} left",
+                    format_duration(elapsed),
+                    format_duration(self.length),
+                    format_duration(remaining)
+                )));
+            }
+            CameraState::ReadingOut | CameraState::Downloading if !self.aborting => {
+                ui.add(egui::ProgressBar::new(1.0).animate(true));
+            }
+            _ => {}
+        }
+        abort
+    }
+}
@@ -0,0 +1,552 @@
+//!
+//! # FITS Input and Output
+//! A minimal FITS reader and writer for raw frames and their acquisition metadata.
+//!
+
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::clock;
+use crate::frame::{FloatFrame, RawFrame};
+
+const BLOCK: usize = 2880;
+const CARD: usize = 80;
+/// Largest image width or height read, in pixels.
+const MAX_AXIS: i64 = 65536;
+
+/// A value stored in a FITS header card.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderValue {
+    /// Logical value, written as `T` or `F`.
+    Bool(bool),
+    /// Integer value.
+    Int(i64),
+    /// Floating point value.
+    Float(f64),
+    /// Character string value.
+    Str(String),
+}
+
+impl HeaderValue {
+    fn format(&self) -> String {
+        match self {
+            HeaderValue::Bool(b) => format!("{:>20}", if *b { "T" } else { "F" }),
+            HeaderValue::Int(i) => format!("{:>20}", i),
+            HeaderValue::Float(f) => format!("{:>20}", format!("{:E}", f)),
+            HeaderValue::Str(s) => format!("'{:<8}'", s.replace('\'', "''")),
+        }
+    }
+
+    /// Parses the value field of a card, dropping any trailing comment.
+    fn parse(field: &str) -> Option<HeaderValue> {
+        let field = field.trim_start();
+        if let Some(rest) = field.strip_prefix('\'') {
+            // Quotes inside strings are escaped by doubling them.
+            let mut out = String::new();
+            let mut chars = rest.chars().peekable();
+            while let Some(c) = chars.next() {
+                if c == '\'' {
+                    if chars.peek() == Some(&'\'') {
+                        chars.next();
+                    } else {
+                        return Some(HeaderValue::Str(out.trim_end().to_owned()));
+                    }
+                }
+                out.push(c);
+            }
+            return None;
+        }
+
+        let token = field.split('/').next()?.trim();
+        match token {
+            "T" => Some(HeaderValue::Bool(true)),
+            "F" => Some(HeaderValue::Bool(false)),
+            _ => token
+                .parse::<i64>()
+                .map(HeaderValue::Int)
+                .or_else(|_| {
+                    token
+                        .replace('D', "E")
+                        .parse::<f64>()
+                        .map(HeaderValue::Float)
+                })
+                .ok(),
+        }
+    }
+
+    /// The value as a number, if it is one.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            HeaderValue::Int(i) => Some(*i as f64),
+            HeaderValue::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+}
+
+/// Looks up the value of `key` in a list of cards.
+pub fn find<'a>(cards: &'a [HeaderCard], key: &str) -> Option<&'a HeaderValue> {
+    cards.iter().find(|c| c.key == key).map(|c| &c.value)
+}
+
+/// An extra header card written after the mandatory ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderCard {
+    /// Keyword, at most 8 characters.
+    pub key: String,
+    /// Card value.
+    pub value: HeaderValue,
+    /// Optional comment.
+    pub comment: String,
+}
+
+impl HeaderCard {
+    /// Creates a new header card.
+    pub fn new(key: &str, value: HeaderValue, comment: &str) -> HeaderCard {
+        HeaderCard {
+            key: key.to_uppercase(),
+            value,
+            comment: comment.to_owned(),
+        }
+    }
+}
+
+/// Replaces every character of `name` other than letters, digits and `-` with `_`, so that it can
+/// be used as part of a file name.
+pub fn file_name_part(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// The acquisition keywords written for every frame the GUI saves, for a frame of `bit_depth`
+/// bits received at Unix time `received`.
+///
+/// Servers do not report when an exposure started, so `DATE-OBS` is estimated as the time the
+/// frame was received less the exposure time.
+pub fn frame_cards(
+    imagetyp: &str,
+    exposure: f64,
+    received: f64,
+    bit_depth: u8,
+    temperature: Option<f32>,
+) -> Vec<HeaderCard> {
+    let mut cards = vec![
+        HeaderCard::new(
+            "IMAGETYP",
+            HeaderValue::Str(imagetyp.into()),
+            "type of image",
+        ),
+        HeaderCard::new("EXPTIME", HeaderValue::Float(exposure), "exposure time [s]"),
+        HeaderCard::new(
+            "DATE-OBS",
+            HeaderValue::Str(clock::format_utc(received - exposure)),
+            "UTC start: receive time less EXPTIME",
+        ),
+        HeaderCard::new(
+            "DATAMAX",
+            HeaderValue::Int((1i64 << bit_depth.min(16)) - 1),
+            "full scale of the sensor data",
+        ),
+    ];
+    if let Some(t) = temperature {
+        cards.push(HeaderCard::new(
+            "CCD-TEMP",
+            HeaderValue::Float(t as f64),
+            "sensor temperature [C]",
+        ));
+    }
+    cards
+}
+
+fn push_card(header: &mut Vec<u8>, key: &str, value: Option<&HeaderValue>, comment: &str) {
+    let mut card = match value {
+        Some(value) => format!("{:<8}= {}", key, value.format()),
+        None => format!("{:<8}", key),
+    };
+    if !comment.is_empty() && value.is_some() {
+        card.push_str(" / ");
+        card.push_str(comment);
+    }
+    let mut card: Vec<u8> = card
+        .bytes()
+        .filter(|b| b.is_ascii() && !b.is_ascii_control())
+        .collect();
+    card.resize(CARD, b' ');
+    header.extend_from_slice(&card);
+}
+
+/// Writes `frame` to `path` as a single-HDU FITS file.
+///
+/// Frames of 8 bits or less are written with `BITPIX = 8`; deeper frames are written as unsigned
+/// 16-bit data using the usual `BZERO = 32768` offset. Color frames become a 3-axis cube with one
+/// plane per channel. Existing files are never overwritten; writing to a path that exists fails
+/// with [`std::io::ErrorKind::AlreadyExists`].
+pub fn write_fits(path: &Path, frame: &RawFrame, cards: &[HeaderCard]) -> std::io::Result<()> {
+    let bitpix = if frame.bit_depth <= 8 { 8 } else { 16 };
+    write_image(path, frame.geometry(), bitpix, cards, |i, out| {
+        let v = frame.data[i];
+        if bitpix == 8 {
+            out.push(v.min(255) as u8);
+        } else {
+            out.extend_from_slice(&((v as i32 - 32768) as i16).to_be_bytes());
+        }
+    })
+}
+
+/// Writes `frame` to `path` as 32-bit floating point data (`BITPIX = -32`), keeping the fractional
+/// part that stacking gives the samples. Otherwise behaves like [`write_fits`].
+pub fn write_fits_f32(
+    path: &Path,
+    frame: &FloatFrame,
+    cards: &[HeaderCard],
+) -> std::io::Result<()> {
+    write_image(path, frame.geometry(), -32, cards, |i, out| {
+        out.extend_from_slice(&frame.data[i].to_be_bytes());
+    })
+}
+
+/// Writes the header and data of an image with the given width, height and channels.
+/// `sample` appends the big-endian encoding of interleaved sample `i` to its buffer.
+fn write_image(
+    path: &Path,
+    (width, height, channels): (usize, usize, usize),
+    bitpix: i64,
+    cards: &[HeaderCard],
+    sample: impl Fn(usize, &mut Vec<u8>),
+) -> std::io::Result<()> {
+    let mut header = Vec::with_capacity(BLOCK);
+    push_card(
+        &mut header,
+        "SIMPLE",
+        Some(&HeaderValue::Bool(true)),
+        "conforms to FITS standard",
+    );
+    push_card(
+        &mut header,
+        "BITPIX",
+        Some(&HeaderValue::Int(bitpix)),
+        "bits per data value",
+    );
+    let naxis = if channels > 1 { 3 } else { 2 };
+    push_card(
+        &mut header,
+        "NAXIS",
+        Some(&HeaderValue::Int(naxis)),
+        "number of data axes",
+    );
+    push_card(
+        &mut header,
+        "NAXIS1",
+        Some(&HeaderValue::Int(width as i64)),
+        "image width",
+    );
+    push_card(
+        &mut header,
+        "NAXIS2",
+        Some(&HeaderValue::Int(height as i64)),
+        "image height",
+    );
+    if naxis == 3 {
+        push_card(
+            &mut header,
+            "NAXIS3",
+            Some(&HeaderValue::Int(channels as i64)),
+            "color planes",
+        );
+    }
+    if bitpix == 16 {
+        push_card(
+            &mut header,
+            "BZERO",
+            Some(&HeaderValue::Int(32768)),
+            "offset for unsigned data",
+        );
+        push_card(&mut header, "BSCALE", Some(&HeaderValue::Int(1)), "");
+    }
+    push_card(
+        &mut header,
+        "ROWORDER",
+        Some(&HeaderValue::Str("TOP-DOWN".into())),
+        "first row is the top of the image",
+    );
+    for card in cards {
+        push_card(&mut header, &card.key, Some(&card.value), &card.comment);
+    }
+    push_card(&mut header, "END", None, "");
+    header.resize(header.len().div_ceil(BLOCK) * BLOCK, b' ');
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => {
+                std::io::Error::new(e.kind(), format!("{} already exists", path.display()))
+            }
+            _ => e,
+        })?;
+    let mut out = BufWriter::new(file);
+    out.write_all(&header)?;
+
+    // FITS stores color data plane by plane.
+    let mut written: usize = 0;
+    let mut buf = Vec::with_capacity(8);
+    for c in 0..channels {
+        for y in 0..height {
+            for x in 0..width {
+                sample((y * width + x) * channels + c, &mut buf);
+                out.write_all(&buf)?;
+                written += buf.len();
+                buf.clear();
+            }
+        }
+    }
+    let padding = written.div_ceil(BLOCK) * BLOCK - written;
+    out.write_all(&vec![0u8; padding])?;
+    out.flush()
+}
+
+/// Reads the primary image of a FITS file, returning the frame and its header cards.
+///
+/// Integer and floating point data are supported; values are scaled with `BZERO`/`BSCALE` and kept
+/// as floats. The bit depth is taken from `DATAMAX` when present, and is otherwise 8 for
+/// `BITPIX = 8` and 16 for everything else. Floating point data without `DATAMAX` that lies within
+/// 0 to 1 is taken to be normalized and scaled to the 16-bit range. Rows are assumed bottom-up
+/// unless `ROWORDER = 'TOP-DOWN'`.
+pub fn read_fits(path: &Path) -> Result<(FloatFrame, Vec<HeaderCard>), String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+
+    let mut cards = Vec::new();
+    let mut offset = 0;
+    'header: loop {
+        let block = bytes
+            .get(offset..offset + BLOCK)
+            .ok_or("file ends before the header does")?;
+        offset += BLOCK;
+        for card in block.chunks(CARD) {
+            let key = String::from_utf8_lossy(&card[..8]).trim().to_owned();
+            if key == "END" {
+                break 'header;
+            }
+            if &card[8..10] == b"= " {
+                let field = String::from_utf8_lossy(&card[10..]);
+                if let Some(value) = HeaderValue::parse(&field) {
+                    cards.push(HeaderCard {
+                        key,
+                        value,
+                        comment: String::new(),
+                    });
+                }
+            }
+        }
+    }
+
+    let int = |key: &str| find(&cards, key).and_then(|v| v.as_f64()).map(|v| v as i64);
+    let bitpix = int("BITPIX").ok_or("missing BITPIX")?;
+    let naxis = int("NAXIS").ok_or("missing NAXIS")?;
+    if !(2..=3).contains(&naxis) {
+        return Err(format!(
+            "expected a 2D image or 3D color cube, found NAXIS = {}",
+            naxis
+        ));
+    }
+    let axis = |key: &str, max: i64| -> Result<usize, String> {
+        let n = int(key).ok_or(format!("missing {}", key))?;
+        if !(1..=max).contains(&n) {
+            return Err(format!("{} = {} is out of range 1 to {}", key, n, max));
+        }
+        Ok(n as usize)
+    };
+    let width = axis("NAXIS1", MAX_AXIS)?;
+    let height = axis("NAXIS2", MAX_AXIS)?;
+    let channels = if naxis == 3 {
+        match axis("NAXIS3", 3)? {
+            c @ (1 | 3) => c,
+            c => return Err(format!("expected 1 or 3 colour planes, found {}", c)),
+        }
+    } else {
+        1
+    };
+    let bzero = find(&cards, "BZERO")
+        .and_then(|v| v.as_f64())
+        .unwrap_or(0.0);
+    let bscale = find(&cards, "BSCALE")
+        .and_then(|v| v.as_f64())
+        .unwrap_or(1.0);
+    let top_down = find(&cards, "ROWORDER") == Some(&HeaderValue::Str("TOP-DOWN".into()));
+    let datamax = find(&cards, "DATAMAX")
+        .and_then(|v| v.as_f64())
+        .filter(|&v| v >= 1.0);
+
+    let sample_size = (bitpix.unsigned_abs() / 8) as usize;
+    let count = width * height * channels;
+    let data = bytes
+        .get(offset..offset + count * sample_size)
+        .ok_or("file ends before the image data does")?;
+
+    let raw: Vec<f64> = match bitpix {
+        8 => data.iter().map(|&b| b as f64).collect(),
+        16 => data
+            .chunks(2)
+            .map(|b| i16::from_be_bytes([b[0], b[1]]) as f64)
+            .collect(),
+        32 => data
+            .chunks(4)
+            .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect(),
+        -32 => data
+            .chunks(4)
+            .map(|b| f32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect(),
+        -64 => data
+            .chunks(8)
+            .map(|b| f64::from_be_bytes(b.try_into().unwrap()))
+            .collect(),
+        _ => return Err(format!("unsupported BITPIX = {}", bitpix)),
+    };
+
+    // Planar, possibly bottom-up, to interleaved top-down.
+    let mut samples = vec![0f32; count];
+    for c in 0..channels {
+        for y in 0..height {
+            let src_y = if top_down { y } else { height - 1 - y };
+            for x in 0..width {
+                let v = bzero + bscale * raw[(c * height + src_y) * width + x];
+                samples[(y * width + x) * channels + c] =
+                    if v.is_finite() { v as f32 } else { 0.0 };
+            }
+        }
+    }
+
+    let bit_depth = match datamax {
+        Some(max) => (max + 1.0).log2().ceil().clamp(1.0, 16.0) as u8,
+        None if bitpix == 8 => 8,
+        None => 16,
+    };
+    if bitpix < 0 && datamax.is_none() && samples.iter().all(|&v| v <= 1.0) {
+        let full = u16::MAX as f32;
+        samples.iter_mut().for_each(|v| *v *= full);
+    }
+
+    let frame = FloatFrame {
+        width,
+        height,
+        channels,
+        bit_depth,
+        data: samples,
+    };
+    Ok((frame, cards))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("gencam_fits_{}_{}.fits", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn raw(width: usize, height: usize, channels: usize, bit_depth: u8) -> RawFrame {
+        let max = (1u32 << bit_depth) - 1;
+        RawFrame {
+            width,
+            height,
+            channels,
+            bit_depth,
+            data: (0..width * height * channels)
+                .map(|i| (i as u32 * 37 % (max + 1)) as u16)
+                .collect(),
+            timestamp: 0.0,
+            request: None,
+            sequence: None,
+        }
+    }
+
+    fn roundtrip(frame: &RawFrame, name: &str) {
+        let path = temp_path(name);
+        let cards = frame_cards("Light", 2.0, 1_000_002.0, frame.bit_depth, Some(-10.0));
+        write_fits(&path, frame, &cards).unwrap();
+        let (read, read_cards) = read_fits(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.geometry(), frame.geometry());
+        assert_eq!(read.bit_depth, frame.bit_depth);
+        let expected: Vec<f32> = frame.data.iter().map(|&v| v as f32).collect();
+        assert_eq!(read.data, expected);
+        assert_eq!(
+            find(&read_cards, "DATE-OBS"),
+            Some(&HeaderValue::Str("1970-01-12T13:46:40.000".into()))
+        );
+        assert_eq!(
+            find(&read_cards, "IMAGETYP"),
+            Some(&HeaderValue::Str("Light".into()))
+        );
+    }
+
+    #[test]
+    fn roundtrips_mono_12_bit() {
+        roundtrip(&raw(7, 5, 1, 12), "mono12");
+    }
+
+    #[test]
+    fn roundtrips_rgb_8_bit() {
+        roundtrip(&raw(6, 4, 3, 8), "rgb8");
+    }
+
+    #[test]
+    fn roundtrips_float() {
+        let path = temp_path("float");
+        let frame = FloatFrame {
+            width: 3,
+            height: 2,
+            channels: 1,
+            bit_depth: 16,
+            data: vec![0.25, 1.5, 100.75, 4095.5, 65535.0, 0.0],
+        };
+        let cards = frame_cards("Master Dark", 10.0, 0.0, 16, None);
+        write_fits_f32(&path, &frame, &cards).unwrap();
+        let (read, _) = read_fits(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.geometry(), frame.geometry());
+        assert_eq!(read.bit_depth, 16);
+        assert_eq!(read.data, frame.data);
+    }
+
+    #[test]
+    fn scales_normalised_float_data() {
+        let path = temp_path("normalised");
+        let frame = FloatFrame {
+            width: 2,
+            height: 1,
+            channels: 1,
+            bit_depth: 16,
+            data: vec![0.0, 1.0],
+        };
+        write_fits_f32(&path, &frame, &[]).unwrap();
+        let (read, _) = read_fits(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.bit_depth, 16);
+        assert_eq!(read.data, [0.0, 65535.0]);
+    }
+
+    #[test]
+    fn refuses_to_overwrite() {
+        let path = temp_path("overwrite");
+        let frame = raw(2, 2, 1, 8);
+        write_fits(&path, &frame, &[]).unwrap();
+        let err = write_fits(&path, &frame, &[]).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    }
+}
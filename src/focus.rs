@@ -0,0 +1,337 @@
+//!
+//! # Focus Assistant
+//! Tracks a sharpness metric across live frames and analyzes Bahtinov-mask diffraction patterns.
+//!
+
+use eframe::egui;
+use egui::Ui;
+use egui_plot::{HLine, Line, Plot, PlotPoints, Points};
+
+use crate::frame::RawFrame;
+use crate::stars;
+use crate::stats;
+
+/// Detection threshold, in noise standard deviations, for stars used by the HFR metric.
+const FOCUS_SIGMA: f32 = 5.0;
+
+/// Half-width of the window the Bahtinov analysis searches for spikes in.
+const BAHTINOV_RADIUS: usize = 60;
+
+/// Angular resolution of the Bahtinov spike search, in degrees.
+const BAHTINOV_STEP: f32 = 0.5;
+
+/// Smallest angle, in degrees, between two distinct Bahtinov spikes.
+const BAHTINOV_SEPARATION: f32 = 5.0;
+
+/// The sharpness measure plotted by the focus assistant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FocusMetric {
+    /// Median half-flux radius of detected stars; lower is sharper.
+    Hfr,
+    /// Variance of the Laplacian; higher is sharper. Works on extended targets.
+    Contrast,
+}
+
+impl FocusMetric {
+    /// Whether a lower value of this metric means better focus.
+    fn lower_is_better(&self) -> bool {
+        matches!(self, FocusMetric::Hfr)
+    }
+
+    /// Evaluates the metric on `frame`, or `None` if it cannot be measured.
+    fn measure(&self, frame: &RawFrame) -> Option<f64> {
+        match self {
+            FocusMetric::Hfr => {
+                let mut hfr: Vec<f32> = stars::detect(frame, FOCUS_SIGMA)
+                    .iter()
+                    .map(|s| s.hfr)
+                    .collect();
+                if hfr.is_empty() {
+                    None
+                } else {
+                    Some(stats::median_f32(&mut hfr) as f64)
+                }
+            }
+            FocusMetric::Contrast => laplacian_variance(frame),
+        }
+    }
+}
+
+/// Variance of the 4-neighbour Laplacian of the frame's luminance.
+fn laplacian_variance(frame: &RawFrame) -> Option<f64> {
+    let (w, h) = (frame.width, frame.height);
+    if w < 3 || h < 3 {
+        return None;
+    }
+    let plane = stars::luminance(frame);
+    let (mut sum, mut sum_sq) = (0.0f64, 0.0f64);
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            let i = y * w + x;
+            let l =
+                (plane[i - 1] + plane[i + 1] + plane[i - w] + plane[i + w] - 4.0 * plane[i]) as f64;
+            sum += l;
+            sum_sq += l * l;
+        }
+    }
+    let n = ((w - 2) * (h - 2)) as f64;
+    let mean = sum / n;
+    Some(sum_sq / n - mean * mean)
+}
+
+/// A straight line in normal form: the points at distance `rho` from the origin along `theta`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpikeLine {
+    /// Angle of the line's normal in radians.
+    pub theta: f32,
+    /// Signed distance from the analysis centre in pixels.
+    pub rho: f32,
+}
+
+impl SpikeLine {
+    /// The point on the line closest to the origin and the line's unit direction.
+    pub fn point_and_direction(&self) -> ((f32, f32), (f32, f32)) {
+        let (s, c) = self.theta.sin_cos();
+        ((self.rho * c, self.rho * s), (-s, c))
+    }
+}
+
+/// The three diffraction spikes of a Bahtinov mask and how far the pattern is from focus.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BahtinovResult {
+    /// Centre of the analysis window in frame pixels.
+    pub center: (f32, f32),
+    /// The two outer spikes and the central spike, in that order, relative to `center`.
+    pub spikes: [SpikeLine; 3],
+    /// Where the outer spikes cross, relative to `center`.
+    pub crossing: (f32, f32),
+    /// Signed distance in pixels from the crossing to the central spike; zero at best focus.
+    pub error: f32,
+}
+
+/// Finds the Bahtinov spikes around the brightest pixel of `frame` using a Hough transform.
+fn bahtinov(frame: &RawFrame) -> Option<BahtinovResult> {
+    let (w, h) = (frame.width, frame.height);
+    if w <= 2 * BAHTINOV_RADIUS || h <= 2 * BAHTINOV_RADIUS {
+        return None;
+    }
+    let plane = stars::luminance(frame);
+    let (bg, noise) = stars::background(&plane);
+
+    let peak = plane
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))?
+        .0;
+    let cx = (peak % w).clamp(BAHTINOV_RADIUS, w - BAHTINOV_RADIUS - 1);
+    let cy = (peak / w).clamp(BAHTINOV_RADIUS, h - BAHTINOV_RADIUS - 1);
+
+    let angles = (180.0 / BAHTINOV_STEP) as usize;
+    let bins = 4 * BAHTINOV_RADIUS + 1;
+    let trig: Vec<(f32, f32)> = (0..angles)
+        .map(|a| (a as f32 * BAHTINOV_STEP).to_radians().sin_cos())
+        .collect();
+    let mut votes = vec![0f32; angles * bins];
+    let r = BAHTINOV_RADIUS as isize;
+    for dy in -r..=r {
+        for dx in -r..=r {
+            let v = plane[(cy as isize + dy) as usize * w + (cx as isize + dx) as usize] - bg;
+            if v < 3.0 * noise {
+                continue;
+            }
+            for (a, (s, c)) in trig.iter().enumerate() {
+                let rho = dx as f32 * c + dy as f32 * s;
+                let bin = (rho.round() as isize + 2 * r) as usize;
+                votes[a * bins + bin] += v;
+            }
+        }
+    }
+
+    // The strongest line at each angle, then the three strongest well-separated angles.
+    let mut best: Vec<(usize, usize, f32)> = (0..angles)
+        .map(|a| {
+            let row = &votes[a * bins..(a + 1) * bins];
+            let (bin, v) = row
+                .iter()
+                .enumerate()
+                .max_by(|x, y| x.1.total_cmp(y.1))
+                .unwrap(); // bins is never zero
+            (a, bin, *v)
+        })
+        .collect();
+    best.sort_by(|x, y| y.2.total_cmp(&x.2));
+    let separation = (BAHTINOV_SEPARATION / BAHTINOV_STEP) as usize;
+    let mut picked: Vec<(usize, usize)> = Vec::new();
+    for (a, bin, _) in best {
+        let far = picked.iter().all(|(p, _)| {
+            let d = a.abs_diff(*p);
+            d.min(angles - d) >= separation
+        });
+        if far {
+            picked.push((a, bin));
+            if picked.len() == 3 {
+                break;
+            }
+        }
+    }
+    if picked.len() < 3 {
+        return None;
+    }
+
+    // The central spike is the one whose angle lies between the other two.
+    picked.sort_by_key(|(a, _)| *a);
+    let gaps = [
+        picked[1].0 - picked[0].0,
+        picked[2].0 - picked[1].0,
+        angles - (picked[2].0 - picked[0].0),
+    ];
+    // The widest gap separates the outer spikes; the middle one is opposite it.
+    let widest = (0..3).max_by_key(|&i| gaps[i]).unwrap(); // three gaps
+    let middle = (widest + 2) % 3;
+    let outer = [(middle + 1) % 3, (middle + 2) % 3];
+
+    let line = |(a, bin): (usize, usize)| SpikeLine {
+        theta: (a as f32 * BAHTINOV_STEP).to_radians(),
+        rho: bin as f32 - 2.0 * BAHTINOV_RADIUS as f32,
+    };
+    let spikes = [
+        line(picked[outer[0]]),
+        line(picked[outer[1]]),
+        line(picked[middle]),
+    ];
+
+    // Solve x cos θ + y sin θ = ρ for the two outer lines.
+    let (s0, c0) = spikes[0].theta.sin_cos();
+    let (s1, c1) = spikes[1].theta.sin_cos();
+    let det = c0 * s1 - s0 * c1;
+    if det.abs() < 1e-6 {
+        return None;
+    }
+    let crossing = (
+        (spikes[0].rho * s1 - spikes[1].rho * s0) / det,
+        (c0 * spikes[1].rho - c1 * spikes[0].rho) / det,
+    );
+    let (s2, c2) = spikes[2].theta.sin_cos();
+    let error = crossing.0 * c2 + crossing.1 * s2 - spikes[2].rho;
+
+    Some(BahtinovResult {
+        center: (cx as f32, cy as f32),
+        spikes,
+        crossing,
+        error,
+    })
+}
+
+/// Focus mode settings and the metric history.
+pub struct FocusAssistant {
+    /// Measure every incoming frame.
+    pub enabled: bool,
+    /// The metric being tracked.
+    pub metric: FocusMetric,
+    /// Also look for a Bahtinov-mask pattern and draw its spikes.
+    pub bahtinov: bool,
+    /// The latest Bahtinov analysis, in full-frame coordinates.
+    pub bahtinov_result: Option<BahtinovResult>,
+    history: Vec<[f64; 2]>,
+    start: Option<f64>,
+}
+
+impl Default for FocusAssistant {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            metric: FocusMetric::Hfr,
+            bahtinov: false,
+            bahtinov_result: None,
+            history: Vec::new(),
+            start: None,
+        }
+    }
+}
+
+impl FocusAssistant {
+    /// Clears the metric history.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.start = None;
+        self.bahtinov_result = None;
+    }
+
+    /// Measures `frame`, which covers the region starting at `offset` in the full frame.
+    pub fn push(&mut self, frame: &RawFrame, offset: (usize, usize)) {
+        if let Some(value) = self.metric.measure(frame) {
+            let start = *self.start.get_or_insert(frame.timestamp);
+            self.history.push([frame.timestamp - start, value]);
+        }
+        self.bahtinov_result = if self.bahtinov {
+            bahtinov(frame).map(|mut b| {
+                b.center.0 += offset.0 as f32;
+                b.center.1 += offset.1 as f32;
+                b
+            })
+        } else {
+            None
+        };
+    }
+
+    /// The best value seen so far and when it was recorded.
+    fn best(&self) -> Option<[f64; 2]> {
+        let lower = self.metric.lower_is_better();
+        self.history.iter().copied().reduce(
+            |best, p| {
+                if (p[1] < best[1]) == lower {
+                    p
+                } else {
+                    best
+                }
+            },
+        )
+    }
+
+    /// Shows the metric selector and history plot.
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.checkbox(&mut self.enabled, "Measure live frames");
+        ui.horizontal(|ui| {
+            ui.label("Metric");
+            let metric = self.metric;
+            ui.selectable_value(&mut self.metric, FocusMetric::Hfr, "HFR");
+            ui.selectable_value(&mut self.metric, FocusMetric::Contrast, "Contrast");
+            if metric != self.metric {
+                self.reset();
+            }
+            if ui.button("Reset").clicked() {
+                self.reset();
+            }
+        });
+        ui.checkbox(&mut self.bahtinov, "Bahtinov mask analysis");
+
+        let best = self.best();
+        match (self.history.last(), best) {
+            (Some(last), Some(best)) => {
+                ui.label(format!("Current {:.3} · Best {:.3}", last[1], best[1]));
+            }
+            _ => {
+                ui.label("No measurements yet.");
+            }
+        }
+        if let Some(b) = &self.bahtinov_result {
+            ui.label(format!("Bahtinov focus error {:+.2} px", b.error));
+        }
+
+        Plot::new("FocusHistory")
+            .height(150.0)
+            .allow_scroll(false)
+            .x_axis_label("s")
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(PlotPoints::from(self.history.clone())));
+                if let Some(best) = best {
+                    plot_ui.hline(HLine::new(best[1]).color(egui::Color32::GREEN));
+                    plot_ui.points(
+                        Points::new(vec![best])
+                            .radius(4.0)
+                            .color(egui::Color32::GREEN),
+                    );
+                }
+            });
+    }
+}
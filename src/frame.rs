@@ -0,0 +1,257 @@
+//!
+//! # Raw Frames
+//! Camera frames as received from the server, before any display conversion.
+//!
+
+use crate::command::{CaptureFormat, ImageInfo};
+
+/// A rectangle of pixels within a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    /// Left edge in pixels.
+    pub x: usize,
+    /// Top edge in pixels.
+    pub y: usize,
+    /// Width in pixels.
+    pub width: usize,
+    /// Height in pixels.
+    pub height: usize,
+}
+
+/// Picks out the frame that answers one image request.
+///
+/// From servers that echo request ids only the frame tagged with the request's id is accepted.
+/// From other servers, frames from exposures already running when the request was sent are
+/// skipped and the next one is accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRequest {
+    id: u64,
+    tagged: bool,
+    in_flight: u32,
+}
+
+impl FrameRequest {
+    /// A request sent with `id`. `tagged` is whether the server echoes ids, and `in_flight`
+    /// whether the camera was already exposing when the request was sent.
+    pub fn new(id: u64, tagged: bool, in_flight: bool) -> FrameRequest {
+        FrameRequest {
+            id,
+            tagged,
+            in_flight: in_flight as u32,
+        }
+    }
+
+    /// Whether `frame` answers the request. Call once for each frame, in arrival order.
+    pub fn accepts(&mut self, frame: &RawFrame) -> bool {
+        match frame.request {
+            Some(id) => id == self.id,
+            None if self.tagged => false,
+            None if self.in_flight > 0 => {
+                self.in_flight -= 1;
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+/// A single frame of raw pixel data.
+///
+/// Samples are stored interleaved (`[r, g, b, r, g, b, ...]` for color frames) and widened to
+/// `u16` regardless of the sensor bit depth, so 8- and 16-bit cameras share one code path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawFrame {
+    /// Width in pixels.
+    pub width: usize,
+    /// Height in pixels.
+    pub height: usize,
+    /// Samples per pixel (1 for mono/Bayer, 3 for RGB).
+    pub channels: usize,
+    /// Significant bits per sample.
+    pub bit_depth: u8,
+    /// Interleaved sample data, `width * height * channels` long.
+    pub data: Vec<u16>,
+    /// Unix time at which the frame was received.
+    pub timestamp: f64,
+    /// Id of the image request the frame answers, if the server echoed one.
+    pub request: Option<u64>,
+    /// The camera's running frame number, if the server reported one.
+    pub sequence: Option<u64>,
+}
+
+impl RawFrame {
+    /// Builds a frame from 8-bit samples, inferring mono or RGB from the buffer length.
+    pub fn from_u8(data: &[u8], width: usize, height: usize, timestamp: f64) -> Option<RawFrame> {
+        let pixels = width * height;
+        if pixels == 0 || (data.len() != pixels && data.len() != pixels * 3) {
+            return None;
+        }
+        Some(RawFrame {
+            width,
+            height,
+            channels: data.len() / pixels,
+            bit_depth: 8,
+            data: data.iter().map(|&x| x as u16).collect(),
+            timestamp,
+            request: None,
+            sequence: None,
+        })
+    }
+
+    /// Builds a frame from an image packet's bytes laid out as `info` describes, or as 8-bit
+    /// samples if the server did not describe them.
+    pub fn from_packet(
+        data: &[u8],
+        width: usize,
+        height: usize,
+        info: Option<&ImageInfo>,
+        timestamp: f64,
+    ) -> Option<RawFrame> {
+        let Some(info) = info else {
+            return RawFrame::from_u8(data, width, height, timestamp);
+        };
+        let channels = match info.format {
+            CaptureFormat::Gray | CaptureFormat::Bayer => 1,
+            CaptureFormat::Rgb => 3,
+        };
+        let wide = info.bit_depth > 8;
+        let sample_size = if wide { 2 } else { 1 };
+        if width * height == 0 || data.len() != width * height * channels * sample_size {
+            return None;
+        }
+        let data = if wide {
+            data.chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect()
+        } else {
+            data.iter().map(|&x| x as u16).collect()
+        };
+        Some(RawFrame {
+            width,
+            height,
+            channels,
+            bit_depth: info.bit_depth.clamp(1, 16),
+            data,
+            timestamp,
+            request: None,
+            sequence: info.sequence,
+        })
+    }
+
+    /// The largest value a sample can take at this frame's bit depth.
+    pub fn max_value(&self) -> u16 {
+        ((1u32 << self.bit_depth.min(16)) - 1) as u16
+    }
+
+    /// Width, height and samples per pixel.
+    pub fn geometry(&self) -> (usize, usize, usize) {
+        (self.width, self.height, self.channels)
+    }
+
+    /// Whether `other` has the same geometry, sample layout and bit depth as this frame.
+    pub fn same_shape(&self, other: &RawFrame) -> bool {
+        self.width == other.width
+            && self.height == other.height
+            && self.channels == other.channels
+            && self.bit_depth == other.bit_depth
+    }
+
+    /// The frame's shape as compared by [`RawFrame::same_shape`], for messages.
+    pub fn shape(&self) -> String {
+        format!(
+            "{}x{}x{} {}-bit",
+            self.width, self.height, self.channels, self.bit_depth
+        )
+    }
+
+    /// Copies out the part of the frame inside `region`, clipped to the frame's bounds.
+    pub fn crop(&self, region: &Region) -> RawFrame {
+        let x0 = region.x.min(self.width);
+        let y0 = region.y.min(self.height);
+        let x1 = (region.x + region.width).min(self.width);
+        let y1 = (region.y + region.height).min(self.height);
+        let mut data = Vec::with_capacity((x1 - x0) * (y1 - y0) * self.channels);
+        for y in y0..y1 {
+            let row = y * self.width * self.channels;
+            data.extend_from_slice(&self.data[row + x0 * self.channels..row + x1 * self.channels]);
+        }
+        RawFrame {
+            width: x1 - x0,
+            height: y1 - y0,
+            data,
+            ..*self
+        }
+    }
+}
+
+/// A frame of floating point samples, used for masters so they keep the precision that
+/// averaging gives them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloatFrame {
+    /// Width in pixels.
+    pub width: usize,
+    /// Height in pixels.
+    pub height: usize,
+    /// Samples per pixel (1 for mono/Bayer, 3 for RGB).
+    pub channels: usize,
+    /// Bit depth of the frames whose units the samples are in.
+    pub bit_depth: u8,
+    /// Interleaved sample data, `width * height * channels` long.
+    pub data: Vec<f32>,
+}
+
+impl FloatFrame {
+    /// The largest value a sample of a `bit_depth`-bit frame can take.
+    pub fn max_value(&self) -> f32 {
+        ((1u32 << self.bit_depth.min(16)) - 1) as f32
+    }
+
+    /// Width, height and samples per pixel.
+    pub fn geometry(&self) -> (usize, usize, usize) {
+        (self.width, self.height, self.channels)
+    }
+
+    /// The factor that converts samples to the units of a `bit_depth`-bit frame.
+    pub fn scale_to(&self, bit_depth: u8) -> f32 {
+        ((1u32 << bit_depth.min(16)) - 1) as f32 / self.max_value()
+    }
+
+    /// The frame's geometry and bit depth, for messages.
+    pub fn shape(&self) -> String {
+        format!(
+            "{}x{}x{} {}-bit",
+            self.width, self.height, self.channels, self.bit_depth
+        )
+    }
+
+    /// Rounds the samples to a raw frame of the same bit depth.
+    pub fn to_raw(&self) -> RawFrame {
+        let max = self.max_value();
+        RawFrame {
+            width: self.width,
+            height: self.height,
+            channels: self.channels,
+            bit_depth: self.bit_depth,
+            data: self
+                .data
+                .iter()
+                .map(|v| v.round().clamp(0.0, max) as u16)
+                .collect(),
+            timestamp: 0.0,
+            request: None,
+            sequence: None,
+        }
+    }
+}
+
+impl From<&RawFrame> for FloatFrame {
+    fn from(frame: &RawFrame) -> FloatFrame {
+        FloatFrame {
+            width: frame.width,
+            height: frame.height,
+            channels: frame.channels,
+            bit_depth: frame.bit_depth,
+            data: frame.data.iter().map(|&v| v as f32).collect(),
+        }
+    }
+}
@@ -7,5 +7,34 @@
 mod app;
 pub use app::GenCamGUI;
 
+mod auto_exposure;
+mod calibration;
+mod camera_profile;
+mod capture_group;
+mod capture_plan;
+mod clock;
+mod command;
+mod connect_dialog;
+mod connection;
+#[cfg(not(target_arch = "wasm32"))]
+mod discovery;
+mod display;
+mod exposure;
+mod fits;
+mod focus;
+mod frame;
+mod library;
+mod live_stack;
+mod overlay;
+mod profile;
+mod sensor;
+mod ser;
+mod settings;
+mod stacking;
+mod stars;
+mod stats;
+mod timelapse;
+mod trigger;
+
 #[cfg(target_arch = "wasm32")]
 mod web;
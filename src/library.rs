@@ -0,0 +1,44 @@
+//!
+//! # Model Libraries
+//! Named entries kept per camera model and stored as one JSON file, shared by the sensor presets
+//! and the camera profiles.
+//!
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Entries for every camera model seen, stored as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, bound(deserialize = "T: Deserialize<'de>"))]
+pub struct ModelLibrary<T> {
+    /// Entries keyed by camera model.
+    pub models: BTreeMap<String, Vec<T>>,
+}
+
+impl<T> Default for ModelLibrary<T> {
+    fn default() -> Self {
+        Self {
+            models: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> ModelLibrary<T> {
+    /// Loads the library from a JSON file. A missing file gives an empty library.
+    pub fn load(path: &Path) -> Result<ModelLibrary<T>, String> {
+        if !path.exists() {
+            return Ok(ModelLibrary::default());
+        }
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&text).map_err(|e| e.to_string())
+    }
+
+    /// Saves the library as pretty-printed JSON.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,581 @@
+//!
+//! # Live Stacking
+//! Registers incoming frames against a reference using star positions and accumulates them into
+//! a running mean. Native builds detect and align stars on a background thread.
+//!
+
+use std::collections::VecDeque;
+
+use eframe::egui;
+use egui::Ui;
+
+use crate::frame::{FloatFrame, RawFrame};
+use crate::stars::{self, Star};
+
+/// Number of brightest stars used to match frames.
+const MATCH_STARS: usize = 20;
+
+/// Fewest matched stars an alignment needs to be trusted.
+const MIN_MATCHES: usize = 4;
+
+/// Most frames waiting for the aligner; further frames are skipped.
+const MAX_QUEUED: usize = 4;
+
+/// A reference star position and the frame star position it matched.
+type PointPair = ((f32, f32), (f32, f32));
+
+/// A rotation about the origin followed by a translation, mapping reference to frame coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    /// Rotation in radians.
+    pub angle: f32,
+    /// Translation in x, pixels.
+    pub dx: f32,
+    /// Translation in y, pixels.
+    pub dy: f32,
+}
+
+impl Transform {
+    fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        let (s, c) = self.angle.sin_cos();
+        (c * x - s * y + self.dx, s * x + c * y + self.dy)
+    }
+
+    /// The least-squares rigid transform taking each `from` point to its `to` point.
+    fn fit(pairs: &[PointPair]) -> Transform {
+        let n = pairs.len() as f32;
+        let (mut fx, mut fy, mut tx, mut ty) = (0.0, 0.0, 0.0, 0.0);
+        for ((ax, ay), (bx, by)) in pairs {
+            fx += ax;
+            fy += ay;
+            tx += bx;
+            ty += by;
+        }
+        let (fx, fy, tx, ty) = (fx / n, fy / n, tx / n, ty / n);
+
+        let (mut cross, mut dot) = (0.0, 0.0);
+        for ((ax, ay), (bx, by)) in pairs {
+            let (ax, ay, bx, by) = (ax - fx, ay - fy, bx - tx, by - ty);
+            cross += ax * by - ay * bx;
+            dot += ax * bx + ay * by;
+        }
+        let angle = f32::atan2(cross, dot);
+        let (s, c) = angle.sin_cos();
+        Transform {
+            angle,
+            dx: tx - (c * fx - s * fy),
+            dy: ty - (s * fx + c * fy),
+        }
+    }
+}
+
+/// Finds the transform mapping `reference` stars onto `stars`, with the number of stars matched.
+///
+/// Every pair of reference stars is tried against every pair of frame stars separated by the
+/// same distance; the hypothesis that lines up the most stars is refined by least squares.
+pub fn align(reference: &[Star], stars: &[Star], tolerance: f32) -> Option<(Transform, usize)> {
+    let reference = &reference[..reference.len().min(MATCH_STARS)];
+    let stars = &stars[..stars.len().min(MATCH_STARS)];
+
+    let mut best: Option<(Transform, usize)> = None;
+    for (i, a) in reference.iter().enumerate() {
+        for b in &reference[i + 1..] {
+            let d_ref = (b.x - a.x).hypot(b.y - a.y);
+            if d_ref < 4.0 * tolerance {
+                continue;
+            }
+            for c in stars {
+                for d in stars {
+                    if std::ptr::eq(c, d) {
+                        continue;
+                    }
+                    let d_new = (d.x - c.x).hypot(d.y - c.y);
+                    if (d_new - d_ref).abs() > tolerance {
+                        continue;
+                    }
+                    let t = Transform::fit(&[((a.x, a.y), (c.x, c.y)), ((b.x, b.y), (d.x, d.y))]);
+                    let n = matches(reference, stars, &t, tolerance).len();
+                    if best.map_or(true, |(_, m)| n > m) {
+                        best = Some((t, n));
+                    }
+                }
+            }
+        }
+    }
+
+    let (t, _) = best?;
+    let pairs = matches(reference, stars, &t, tolerance);
+    if pairs.len() < MIN_MATCHES {
+        return None;
+    }
+    Some((Transform::fit(&pairs), pairs.len()))
+}
+
+/// Pairs each reference star with the frame star it lands within `tolerance` of under `t`.
+fn matches(reference: &[Star], stars: &[Star], t: &Transform, tolerance: f32) -> Vec<PointPair> {
+    reference
+        .iter()
+        .filter_map(|r| {
+            let (x, y) = t.apply(r.x, r.y);
+            stars
+                .iter()
+                .find(|s| (s.x - x).hypot(s.y - y) <= tolerance)
+                .map(|s| ((r.x, r.y), (s.x, s.y)))
+        })
+        .collect()
+}
+
+/// How the background worker registered a frame.
+enum Registered {
+    /// The first frame of the stack, which becomes the reference, with its stars.
+    Reference(RawFrame, Vec<Star>),
+    /// A frame resampled onto the reference grid, with whether each pixel was covered.
+    Aligned(Vec<f32>, Vec<bool>),
+}
+
+/// Detects the stars in `frame` and aligns it to `reference`, or makes it the reference if
+/// there is none yet.
+fn register(
+    frame: RawFrame,
+    reference: Option<Vec<Star>>,
+    sigma: f32,
+    tolerance: f32,
+    min_star_fraction: f32,
+) -> Result<Registered, String> {
+    let found = stars::detect(&frame, sigma);
+
+    let Some(reference) = reference else {
+        if found.len() < MIN_MATCHES {
+            return Err(format!(
+                "only {} stars found for the reference, need {}",
+                found.len(),
+                MIN_MATCHES
+            ));
+        }
+        return Ok(Registered::Reference(frame, found));
+    };
+
+    let needed = (reference.len() as f32 * min_star_fraction).ceil() as usize;
+    if found.len() < needed {
+        return Err(format!(
+            "{} stars found, reference has {}",
+            found.len(),
+            reference.len()
+        ));
+    }
+    let (t, _) =
+        align(&reference, &found, tolerance).ok_or_else(|| "alignment failed".to_owned())?;
+    let (samples, covered) = resample(&frame, &t);
+    Ok(Registered::Aligned(samples, covered))
+}
+
+/// Resamples `frame` onto the reference grid through `t`. Returns the samples and whether each
+/// pixel fell inside the frame.
+fn resample(frame: &RawFrame, t: &Transform) -> (Vec<f32>, Vec<bool>) {
+    let (w, h, ch) = (frame.width, frame.height, frame.channels);
+    let mut samples = vec![0.0; w * h * ch];
+    let mut covered = vec![false; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            let (sx, sy) = t.apply(x as f32, y as f32);
+            if sx < 0.0 || sy < 0.0 || sx >= (w - 1) as f32 || sy >= (h - 1) as f32 {
+                continue;
+            }
+            let (x0, y0) = (sx as usize, sy as usize);
+            let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+            let at = |xx: usize, yy: usize, c: usize| frame.data[(yy * w + xx) * ch + c] as f32;
+            for c in 0..ch {
+                let top = at(x0, y0, c) * (1.0 - fx) + at(x0 + 1, y0, c) * fx;
+                let bottom = at(x0, y0 + 1, c) * (1.0 - fx) + at(x0 + 1, y0 + 1, c) * fx;
+                samples[(y * w + x) * ch + c] = top * (1.0 - fy) + bottom * fy;
+            }
+            covered[y * w + x] = true;
+        }
+    }
+    (samples, covered)
+}
+
+/// A frame being registered in the background.
+struct AlignJob {
+    #[cfg(not(target_arch = "wasm32"))]
+    result: std::sync::mpsc::Receiver<Result<Registered, String>>,
+    #[cfg(target_arch = "wasm32")]
+    result: Option<Result<Registered, String>>,
+}
+
+impl AlignJob {
+    /// Starts registering `frame`. On the web there are no threads, so this blocks until done.
+    fn spawn(
+        frame: RawFrame,
+        reference: Option<Vec<Star>>,
+        sigma: f32,
+        tolerance: f32,
+        min_star_fraction: f32,
+    ) -> AlignJob {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (tx, rx) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                let _ = tx.send(register(
+                    frame,
+                    reference,
+                    sigma,
+                    tolerance,
+                    min_star_fraction,
+                ));
+            });
+            AlignJob { result: rx }
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            let result = Some(register(
+                frame,
+                reference,
+                sigma,
+                tolerance,
+                min_star_fraction,
+            ));
+            AlignJob { result }
+        }
+    }
+
+    /// Returns the result once the frame has been registered.
+    fn try_take(&mut self) -> Option<Result<Registered, String>> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            match self.result.try_recv() {
+                Ok(result) => Some(result),
+                Err(std::sync::mpsc::TryRecvError::Empty) => None,
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                    Some(Err("alignment thread exited unexpectedly".into()))
+                }
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.result.take()
+        }
+    }
+}
+
+/// Something the live-stack panel asks the GUI to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LiveStackAction {
+    /// Save the current stack to the given path.
+    Save(String),
+}
+
+/// A running mean of aligned frames.
+pub struct LiveStack {
+    /// Stack incoming frames and display the result.
+    pub enabled: bool,
+    /// Detection threshold in noise standard deviations.
+    pub sigma: f32,
+    /// How far, in pixels, a star may land from its predicted position and still match.
+    pub tolerance: f32,
+    /// Reject frames with fewer stars than this fraction of the reference's.
+    pub min_star_fraction: f32,
+    /// Where the stack is saved.
+    pub output_path: String,
+    reference: Vec<Star>,
+    sum: Vec<f32>,
+    weight: Vec<u32>,
+    template: Option<RawFrame>,
+    stacked: usize,
+    rejected: usize,
+    last: String,
+    /// The frame being registered.
+    job: Option<AlignJob>,
+    /// Frames waiting for the aligner.
+    queue: VecDeque<RawFrame>,
+}
+
+impl Default for LiveStack {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sigma: 5.0,
+            tolerance: 2.0,
+            min_star_fraction: 0.5,
+            output_path: "stack.fits".into(),
+            reference: Vec::new(),
+            sum: Vec::new(),
+            weight: Vec::new(),
+            template: None,
+            stacked: 0,
+            rejected: 0,
+            last: String::new(),
+            job: None,
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl LiveStack {
+    /// Discards the stack; the next frame becomes the new reference.
+    pub fn reset(&mut self) {
+        self.reference.clear();
+        self.sum.clear();
+        self.weight.clear();
+        self.template = None;
+        self.stacked = 0;
+        self.rejected = 0;
+        self.last.clear();
+        self.job = None;
+        self.queue.clear();
+    }
+
+    /// Number of frames in the stack.
+    pub fn stacked(&self) -> usize {
+        self.stacked
+    }
+
+    /// Queues `frame` to be aligned to the reference and added to the stack in the background.
+    /// Frames arriving while the queue is full are counted as rejected.
+    pub fn push(&mut self, frame: &RawFrame) {
+        if self.queue.len() >= MAX_QUEUED {
+            self.reject("still aligning earlier frames".into());
+            return;
+        }
+        self.queue.push_back(frame.clone());
+        self.start_next();
+    }
+
+    fn reject(&mut self, reason: String) {
+        self.rejected += 1;
+        self.last = format!("Frame rejected: {}", reason);
+    }
+
+    /// Hands the next queued frame to the aligner, unless it is busy.
+    fn start_next(&mut self) {
+        if self.job.is_some() {
+            return;
+        }
+        while let Some(frame) = self.queue.pop_front() {
+            if let Some(template) = &self.template {
+                if !frame.same_shape(template) {
+                    let reason = format!(
+                        "frame is {} but the stack is {}",
+                        frame.shape(),
+                        template.shape()
+                    );
+                    self.reject(reason);
+                    continue;
+                }
+            }
+            let reference = self.template.is_some().then(|| self.reference.clone());
+            self.job = Some(AlignJob::spawn(
+                frame,
+                reference,
+                self.sigma,
+                self.tolerance,
+                self.min_star_fraction,
+            ));
+            break;
+        }
+    }
+
+    /// Adds the frame the aligner finished, if any, and starts on the next one. Returns whether
+    /// the stack changed.
+    pub fn poll(&mut self) -> bool {
+        let Some(result) = self.job.as_mut().and_then(|job| job.try_take()) else {
+            return false;
+        };
+        self.job = None;
+        let changed = match result {
+            Ok(Registered::Reference(frame, found)) => {
+                self.reference = found;
+                self.sum = frame.data.iter().map(|&v| v as f32).collect();
+                self.weight = vec![1; frame.width * frame.height];
+                self.template = Some(frame);
+                true
+            }
+            Ok(Registered::Aligned(samples, covered)) => {
+                let ch = self.template.as_ref().map_or(1, |t| t.channels);
+                for (i, _) in covered.iter().enumerate().filter(|(_, covered)| **covered) {
+                    for c in 0..ch {
+                        self.sum[i * ch + c] += samples[i * ch + c];
+                    }
+                    self.weight[i] += 1;
+                }
+                true
+            }
+            Err(e) => {
+                self.reject(e);
+                false
+            }
+        };
+        if changed {
+            self.stacked += 1;
+            self.last = "Frame stacked.".into();
+        }
+        self.start_next();
+        changed
+    }
+
+    /// The current mean of all stacked frames.
+    pub fn result(&self) -> Option<FloatFrame> {
+        let template = self.template.as_ref()?;
+        let ch = template.channels;
+        let data = self
+            .sum
+            .iter()
+            .enumerate()
+            .map(|(i, &s)| s / self.weight[i / ch] as f32)
+            .collect();
+        Some(FloatFrame {
+            width: template.width,
+            height: template.height,
+            channels: ch,
+            bit_depth: template.bit_depth,
+            data,
+        })
+    }
+
+    /// Unix time at which the reference frame was received.
+    pub fn reference_time(&self) -> Option<f64> {
+        self.template.as_ref().map(|t| t.timestamp)
+    }
+
+    /// Shows the stacking options and status. Returns an action for the GUI to carry out, if any.
+    pub fn ui(&mut self, ui: &mut Ui) -> Option<LiveStackAction> {
+        let mut action = None;
+
+        ui.checkbox(&mut self.enabled, "Stack incoming frames");
+        ui.horizontal(|ui| {
+            ui.label("Detection");
+            ui.add(
+                egui::DragValue::new(&mut self.sigma)
+                    .range(2.0..=50.0)
+                    .speed(0.1)
+                    .suffix(" σ"),
+            );
+            ui.label("Tolerance");
+            ui.add(
+                egui::DragValue::new(&mut self.tolerance)
+                    .range(0.5..=10.0)
+                    .speed(0.1)
+                    .suffix(" px"),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Min. Stars");
+            ui.add(
+                egui::Slider::new(&mut self.min_star_fraction, 0.0..=1.0)
+                    .custom_formatter(|v, _| format!("{:.0}% of reference", v * 100.0)),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button("Reset").clicked() {
+                self.reset();
+            }
+            ui.text_edit_singleline(&mut self.output_path);
+            if ui
+                .add_enabled(self.template.is_some(), egui::Button::new("Save"))
+                .clicked()
+            {
+                action = Some(LiveStackAction::Save(self.output_path.clone()));
+            }
+        });
+
+        ui.label(format!(
+            "{} stacked · {} rejected · {} reference stars",
+            self.stacked,
+            self.rejected,
+            self.reference.len()
+        ));
+        if self.job.is_some() {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(format!("Aligning… {} queued", self.queue.len()));
+            });
+        }
+        if !self.last.is_empty() {
+            ui.label(&self.last);
+        }
+
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn star(x: f32, y: f32) -> Star {
+        Star {
+            x,
+            y,
+            flux: 1000.0,
+            hfr: 1.5,
+            fwhm: 3.0,
+        }
+    }
+
+    /// Irregularly spaced positions, so that no two star pairs share a separation.
+    fn field() -> Vec<Star> {
+        [
+            (31.0, 42.0),
+            (210.5, 64.0),
+            (120.0, 180.25),
+            (75.0, 260.0),
+            (300.0, 210.0),
+            (260.0, 330.5),
+            (18.0, 350.0),
+            (160.0, 95.0),
+        ]
+        .iter()
+        .map(|&(x, y)| star(x, y))
+        .collect()
+    }
+
+    fn assert_close(a: Transform, b: Transform) {
+        assert!((a.angle - b.angle).abs() < 1e-4, "{:?} != {:?}", a, b);
+        assert!((a.dx - b.dx).abs() < 1e-2, "{:?} != {:?}", a, b);
+        assert!((a.dy - b.dy).abs() < 1e-2, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn fits_a_known_transform() {
+        let t = Transform {
+            angle: 0.3,
+            dx: 12.5,
+            dy: -7.0,
+        };
+        let pairs: Vec<PointPair> = field()
+            .iter()
+            .map(|s| ((s.x, s.y), t.apply(s.x, s.y)))
+            .collect();
+        assert_close(Transform::fit(&pairs), t);
+    }
+
+    #[test]
+    fn aligns_rotated_and_shifted_stars() {
+        let t = Transform {
+            angle: -0.05,
+            dx: 20.0,
+            dy: 8.5,
+        };
+        let reference = field();
+        // The frame lists its stars in a different order than the reference.
+        let mut stars: Vec<Star> = reference
+            .iter()
+            .map(|s| {
+                let (x, y) = t.apply(s.x, s.y);
+                star(x, y)
+            })
+            .collect();
+        stars.reverse();
+
+        let (found, matched) = align(&reference, &stars, 2.0).unwrap();
+        assert_eq!(matched, reference.len());
+        assert_close(found, t);
+    }
+
+    #[test]
+    fn rejects_too_few_stars() {
+        let reference = field();
+        assert!(align(&reference[..3], &reference[..3], 2.0).is_none());
+    }
+}
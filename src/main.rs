@@ -33,7 +33,7 @@ fn main() -> eframe::Result {
         Box::new(|cc| {
             // This gives us image support:
             egui_extras::install_image_loaders(&cc.egui_ctx);
-            Ok(Box::<GenCamGUI>::default())
+            Ok(Box::new(GenCamGUI::new(cc)))
         }),
     )
 }
\ No newline at end of file
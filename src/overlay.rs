@@ -0,0 +1,157 @@
+//!
+//! # Framing Overlays
+//! Crosshair, grid, circles and a user-placed reticle drawn over the image for framing,
+//! polar alignment and collimation.
+//!
+
+use eframe::egui;
+use egui::{Color32, Painter, Pos2, Stroke, Ui};
+use serde::{Deserialize, Serialize};
+
+/// Units the overlay circle radii are given in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RadiusUnit {
+    /// Image pixels.
+    Pixels,
+    /// Arcseconds, converted with the plate scale.
+    Arcsec,
+}
+
+/// Which framing overlays are drawn and where.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Overlays {
+    /// Lines through the centre of the frame.
+    pub crosshair: bool,
+    /// Lines dividing the frame into thirds.
+    pub thirds: bool,
+    /// Concentric circles around the reticle, or the frame centre if none is placed.
+    pub circles: bool,
+    /// Circle radii in `unit`s.
+    pub radii: Vec<f32>,
+    /// Units of `radii`.
+    pub unit: RadiusUnit,
+    /// A marked position in frame pixels.
+    pub reticle: Option<(f32, f32)>,
+    /// Colour of all overlays.
+    pub color: [u8; 3],
+}
+
+impl Default for Overlays {
+    fn default() -> Self {
+        Self {
+            crosshair: false,
+            thirds: false,
+            circles: false,
+            radii: vec![50.0, 100.0, 200.0],
+            unit: RadiusUnit::Pixels,
+            reticle: None,
+            color: [255, 64, 64],
+        }
+    }
+}
+
+impl Overlays {
+    /// Draws the enabled overlays for a `width` x `height` frame.
+    ///
+    /// `to_screen` maps frame pixels to screen positions, `scale` is screen points per frame
+    /// pixel, and `plate_scale` is arcseconds per pixel.
+    pub fn paint(
+        &self,
+        painter: &Painter,
+        to_screen: impl Fn(f32, f32) -> Pos2,
+        scale: f32,
+        (width, height): (f32, f32),
+        plate_scale: f32,
+    ) {
+        let stroke = Stroke::new(
+            1.0,
+            Color32::from_rgb(self.color[0], self.color[1], self.color[2]),
+        );
+
+        if self.crosshair {
+            let (cx, cy) = (width / 2.0, height / 2.0);
+            painter.line_segment([to_screen(cx, 0.0), to_screen(cx, height)], stroke);
+            painter.line_segment([to_screen(0.0, cy), to_screen(width, cy)], stroke);
+        }
+
+        if self.thirds {
+            for i in 1..3 {
+                let (x, y) = (width * i as f32 / 3.0, height * i as f32 / 3.0);
+                painter.line_segment([to_screen(x, 0.0), to_screen(x, height)], stroke);
+                painter.line_segment([to_screen(0.0, y), to_screen(width, y)], stroke);
+            }
+        }
+
+        let (cx, cy) = self.reticle.unwrap_or((width / 2.0, height / 2.0));
+        if self.circles {
+            let px_per_unit = match self.unit {
+                RadiusUnit::Pixels => 1.0,
+                RadiusUnit::Arcsec => 1.0 / plate_scale.max(1e-6),
+            };
+            for r in &self.radii {
+                painter.circle_stroke(to_screen(cx, cy), r * px_per_unit * scale, stroke);
+            }
+        }
+
+        if let Some((x, y)) = self.reticle {
+            let c = to_screen(x, y);
+            let (gap, arm) = (4.0, 12.0);
+            for (dx, dy) in [(1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0)] {
+                painter.line_segment(
+                    [
+                        c + egui::vec2(dx * gap, dy * gap),
+                        c + egui::vec2(dx * arm, dy * arm),
+                    ],
+                    stroke,
+                );
+            }
+        }
+    }
+
+    /// Shows the overlay toggles and circle settings.
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.crosshair, "Crosshair");
+            ui.checkbox(&mut self.thirds, "Thirds Grid");
+            ui.checkbox(&mut self.circles, "Circles");
+            ui.color_edit_button_srgb(&mut self.color);
+        });
+
+        ui.add_enabled_ui(self.circles, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Radii");
+                ui.selectable_value(&mut self.unit, RadiusUnit::Pixels, "px");
+                ui.selectable_value(&mut self.unit, RadiusUnit::Arcsec, "arcsec");
+            });
+            let mut remove = None;
+            ui.horizontal_wrapped(|ui| {
+                for (i, r) in self.radii.iter_mut().enumerate() {
+                    ui.add(egui::DragValue::new(r).range(1.0..=100_000.0));
+                    if ui.small_button("✖").clicked() {
+                        remove = Some(i);
+                    }
+                }
+                if ui.small_button("+").clicked() {
+                    let next = self.radii.last().map_or(50.0, |r| r * 2.0);
+                    self.radii.push(next);
+                }
+            });
+            if let Some(i) = remove {
+                self.radii.remove(i);
+            }
+        });
+
+        ui.horizontal(|ui| match self.reticle {
+            Some((x, y)) => {
+                ui.label(format!("Reticle at ({:.1}, {:.1})", x, y));
+                if ui.button("Clear").clicked() {
+                    self.reticle = None;
+                }
+            }
+            None => {
+                ui.label("Select the reticle tool and click the image to place one.");
+            }
+        });
+    }
+}
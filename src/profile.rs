@@ -0,0 +1,146 @@
+//!
+//! # Line Profile
+//! Raw intensity sampled along a segment drawn on the image.
+//!
+
+use eframe::egui;
+use egui::{Color32, Ui};
+use egui_plot::{Legend, Line, Plot, PlotPoints};
+
+use crate::frame::RawFrame;
+
+/// A segment on the image and the intensity measured along it.
+pub struct LineProfile {
+    /// Start and end of the segment in frame pixels.
+    pub line: Option<((f32, f32), (f32, f32))>,
+    /// Width in pixels of the band averaged across the segment.
+    pub band: u32,
+    samples: Vec<Vec<[f64; 2]>>,
+}
+
+impl Default for LineProfile {
+    fn default() -> Self {
+        Self {
+            line: None,
+            band: 1,
+            samples: Vec::new(),
+        }
+    }
+}
+
+/// Bilinearly interpolates channel `c` of `frame` at (`x`, `y`), clamping to the frame's edges.
+fn sample(frame: &RawFrame, x: f32, y: f32, c: usize) -> f32 {
+    let x = x.clamp(0.0, (frame.width - 1) as f32);
+    let y = y.clamp(0.0, (frame.height - 1) as f32);
+    let (x0, y0) = (x as usize, y as usize);
+    let (x1, y1) = (
+        (x0 + 1).min(frame.width - 1),
+        (y0 + 1).min(frame.height - 1),
+    );
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let at = |xx: usize, yy: usize| frame.data[(yy * frame.width + xx) * frame.channels + c] as f32;
+    let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+    let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+impl LineProfile {
+    /// Samples `frame` along the segment once per pixel of length.
+    pub fn update(&mut self, frame: &RawFrame) {
+        self.samples.clear();
+        let Some(((x0, y0), (x1, y1))) = self.line else {
+            return;
+        };
+        if frame.width == 0 || frame.height == 0 {
+            return;
+        }
+        let length = (x1 - x0).hypot(y1 - y0);
+        let steps = length.ceil().max(1.0) as usize;
+        let (dx, dy) = ((x1 - x0) / steps as f32, (y1 - y0) / steps as f32);
+        // Unit normal to the segment, for averaging across the band.
+        let (nx, ny) = if length > 0.0 {
+            (-(y1 - y0) / length, (x1 - x0) / length)
+        } else {
+            (0.0, 0.0)
+        };
+        let half = (self.band.max(1) - 1) as f32 / 2.0;
+        let offsets: Vec<f32> = (0..self.band.max(1)).map(|i| i as f32 - half).collect();
+
+        for c in 0..frame.channels {
+            let points = (0..=steps)
+                .map(|i| {
+                    let (x, y) = (x0 + dx * i as f32, y0 + dy * i as f32);
+                    let sum: f32 = offsets
+                        .iter()
+                        .map(|o| sample(frame, x + nx * o, y + ny * o, c))
+                        .sum();
+                    [
+                        i as f64 * length as f64 / steps as f64,
+                        (sum / offsets.len() as f32) as f64,
+                    ]
+                })
+                .collect();
+            self.samples.push(points);
+        }
+    }
+
+    /// Shows the band width setting and the profile plot. Returns whether the band width changed.
+    pub fn ui(&mut self, ui: &mut Ui) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Band Width");
+            changed = ui
+                .add(
+                    egui::DragValue::new(&mut self.band)
+                        .range(1..=101)
+                        .suffix(" px"),
+                )
+                .changed();
+            if let Some(((x0, y0), (x1, y1))) = self.line {
+                ui.label(format!(
+                    "({:.0}, {:.0}) to ({:.0}, {:.0}), {:.1} px",
+                    x0,
+                    y0,
+                    x1,
+                    y1,
+                    (x1 - x0).hypot(y1 - y0)
+                ));
+                if ui.button("Clear").clicked() {
+                    self.line = None;
+                    self.samples.clear();
+                }
+            }
+        });
+
+        if self.samples.is_empty() {
+            ui.label("Select the line tool and drag across the image.");
+            return changed;
+        }
+
+        let channels: &[(&str, Color32)] = if self.samples.len() == 3 {
+            &[
+                ("R", Color32::RED),
+                ("G", Color32::GREEN),
+                ("B", Color32::LIGHT_BLUE),
+            ]
+        } else {
+            &[("Mono", Color32::GRAY)]
+        };
+        Plot::new("LineProfile")
+            .height(150.0)
+            .legend(Legend::default())
+            .x_axis_label("px")
+            .y_axis_label("ADU")
+            .show(ui, |plot_ui| {
+                for (points, (name, color)) in self.samples.iter().zip(channels) {
+                    plot_ui.line(
+                        Line::new(PlotPoints::from(points.clone()))
+                            .color(*color)
+                            .name(*name),
+                    );
+                }
+            });
+
+        changed
+    }
+}
@@ -0,0 +1,278 @@
+//!
+//! # Sensor Controls
+//! Gain, offset and the other sensor settings a camera advertises, with named presets kept per
+//! camera model.
+//!
+
+use eframe::egui;
+use egui::Ui;
+use serde::{Deserialize, Serialize};
+
+use crate::command::{CameraCapabilities, CameraCommand, ControlRange};
+use crate::library::ModelLibrary;
+
+/// Values of the adjustable sensor controls.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SensorSettings {
+    /// Sensor gain.
+    pub gain: i64,
+    /// Offset (black level).
+    pub offset: i64,
+    /// USB bandwidth limit.
+    pub usb_bandwidth: i64,
+    /// Index of the selected readout mode.
+    pub readout_mode: usize,
+    /// High-conversion-gain mode.
+    pub high_conversion_gain: bool,
+    /// Amplifier-glow suppression.
+    pub anti_amp_glow: bool,
+}
+
+/// A named set of sensor settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SensorPreset {
+    /// Name shown in the preset list.
+    pub name: String,
+    /// The settings the preset applies.
+    pub settings: SensorSettings,
+}
+
+/// Presets for every camera model seen, stored as JSON.
+pub type PresetLibrary = ModelLibrary<SensorPreset>;
+
+/// Requests from the sensor controls panel.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SensorAction {
+    /// Send these commands to the camera.
+    Send(Vec<CameraCommand>),
+    /// The preset library was edited and should be saved.
+    PresetsChanged,
+}
+
+/// Sensor control state for one camera.
+#[derive(Default)]
+pub struct SensorControls {
+    /// What the camera supports, once it has reported it.
+    pub capabilities: Option<CameraCapabilities>,
+    /// Current control values.
+    pub settings: SensorSettings,
+    preset_name: String,
+}
+
+/// A slider over `range`, if the control exists. Returns whether the value changed.
+fn range_slider(ui: &mut Ui, value: &mut i64, range: Option<ControlRange>) -> bool {
+    let Some(range) = range else {
+        ui.label("Not supported");
+        return false;
+    };
+    let response =
+        ui.add(egui::Slider::new(value, range.min..=range.max).step_by(range.step.max(1) as f64));
+    let changed = response.changed();
+    if response.double_clicked() {
+        *value = range.default;
+        return true;
+    }
+    changed
+}
+
+impl SensorControls {
+    /// Takes on a newly reported set of capabilities.
+    ///
+    /// Settings are reset to the camera's defaults when the model or its control ranges changed,
+    /// and kept when the camera merely reported them again. Models seen for the first time get
+    /// the presets their capabilities allow in `presets`.
+    pub fn set_capabilities(&mut self, caps: CameraCapabilities, presets: &mut PresetLibrary) {
+        let unchanged = self.capabilities.as_ref().is_some_and(|old| {
+            old.model == caps.model
+                && old.gain == caps.gain
+                && old.offset == caps.offset
+                && old.usb_bandwidth == caps.usb_bandwidth
+                && old.readout_modes == caps.readout_modes
+        });
+        if !unchanged {
+            self.settings = SensorSettings {
+                gain: caps.gain.map_or(0, |r| r.default),
+                offset: caps.offset.map_or(0, |r| r.default),
+                usb_bandwidth: caps.usb_bandwidth.map_or(0, |r| r.default),
+                readout_mode: 0,
+                high_conversion_gain: false,
+                anti_amp_glow: false,
+            };
+        }
+
+        if !presets.models.contains_key(&caps.model) {
+            let mut seeded = Vec::new();
+            if let Some(gain) = caps.unity_gain {
+                seeded.push(SensorPreset {
+                    name: "Unity Gain".into(),
+                    settings: SensorSettings {
+                        gain,
+                        ..self.settings.clone()
+                    },
+                });
+            }
+            if let Some(gain) = caps.lowest_noise_gain {
+                seeded.push(SensorPreset {
+                    name: "Lowest Read Noise".into(),
+                    settings: SensorSettings {
+                        gain,
+                        high_conversion_gain: caps.high_conversion_gain,
+                        ..self.settings.clone()
+                    },
+                });
+            }
+            presets.models.insert(caps.model.clone(), seeded);
+        }
+        self.capabilities = Some(caps);
+    }
+
+    /// Commands that set every supported control to `settings`, clamped to the camera's limits.
+    fn commands(&self, settings: &SensorSettings) -> Vec<CameraCommand> {
+        let Some(caps) = &self.capabilities else {
+            return Vec::new();
+        };
+        let mut out = Vec::new();
+        if let Some(r) = caps.gain {
+            out.push(CameraCommand::SetGain(r.clamp(settings.gain)));
+        }
+        if let Some(r) = caps.offset {
+            out.push(CameraCommand::SetOffset(r.clamp(settings.offset)));
+        }
+        if let Some(r) = caps.usb_bandwidth {
+            out.push(CameraCommand::SetUsbBandwidth(
+                r.clamp(settings.usb_bandwidth),
+            ));
+        }
+        if settings.readout_mode < caps.readout_modes.len() {
+            out.push(CameraCommand::SetReadoutMode(settings.readout_mode));
+        }
+        if caps.high_conversion_gain {
+            out.push(CameraCommand::SetHighConversionGain(
+                settings.high_conversion_gain,
+            ));
+        }
+        if caps.anti_amp_glow {
+            out.push(CameraCommand::SetAntiAmpGlow(settings.anti_amp_glow));
+        }
+        out
+    }
+
+    /// Shows the controls the camera supports and its model's entries in `presets`.
+    pub fn ui(&mut self, ui: &mut Ui, presets: &mut PresetLibrary) -> Option<SensorAction> {
+        let Some(caps) = self.capabilities.clone() else {
+            ui.label("Waiting for the camera to report its controls.");
+            return None;
+        };
+        let mut action = None;
+        let s = &mut self.settings;
+
+        egui::Grid::new("SensorControls").show(ui, |ui| {
+            ui.label("Gain");
+            if range_slider(ui, &mut s.gain, caps.gain) {
+                action = Some(SensorAction::Send(vec![CameraCommand::SetGain(s.gain)]));
+            }
+            ui.end_row();
+
+            ui.label("Offset");
+            if range_slider(ui, &mut s.offset, caps.offset) {
+                action = Some(SensorAction::Send(vec![CameraCommand::SetOffset(s.offset)]));
+            }
+            ui.end_row();
+
+            ui.label("USB Bandwidth");
+            if range_slider(ui, &mut s.usb_bandwidth, caps.usb_bandwidth) {
+                action = Some(SensorAction::Send(vec![CameraCommand::SetUsbBandwidth(
+                    s.usb_bandwidth,
+                )]));
+            }
+            ui.end_row();
+
+            if !caps.readout_modes.is_empty() {
+                ui.label("Readout Mode");
+                let selected = caps
+                    .readout_modes
+                    .get(s.readout_mode)
+                    .cloned()
+                    .unwrap_or_default();
+                egui::ComboBox::from_id_source("ReadoutMode")
+                    .selected_text(selected)
+                    .show_ui(ui, |ui| {
+                        for (i, name) in caps.readout_modes.iter().enumerate() {
+                            if ui.selectable_value(&mut s.readout_mode, i, name).changed() {
+                                action =
+                                    Some(SensorAction::Send(vec![CameraCommand::SetReadoutMode(
+                                        i,
+                                    )]));
+                            }
+                        }
+                    });
+                ui.end_row();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if caps.high_conversion_gain
+                && ui
+                    .checkbox(&mut s.high_conversion_gain, "High Conversion Gain")
+                    .changed()
+            {
+                action = Some(SensorAction::Send(vec![
+                    CameraCommand::SetHighConversionGain(s.high_conversion_gain),
+                ]));
+            }
+            if caps.anti_amp_glow && ui.checkbox(&mut s.anti_amp_glow, "Anti-Amp-Glow").changed() {
+                action = Some(SensorAction::Send(vec![CameraCommand::SetAntiAmpGlow(
+                    s.anti_amp_glow,
+                )]));
+            }
+        });
+
+        ui.separator();
+        ui.label(format!("Presets for {}", caps.model));
+        let presets = presets.models.entry(caps.model.clone()).or_default();
+        let mut apply = None;
+        let mut remove = None;
+        for (i, preset) in presets.iter().enumerate() {
+            ui.horizontal(|ui| {
+                if ui.button("Apply").clicked() {
+                    apply = Some(preset.settings.clone());
+                }
+                ui.label(&preset.name);
+                if ui.small_button("✖").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.preset_name);
+            let name = self.preset_name.trim().to_owned();
+            if ui
+                .add_enabled(!name.is_empty(), egui::Button::new("Save Current"))
+                .clicked()
+            {
+                let preset = SensorPreset {
+                    name: name.clone(),
+                    settings: self.settings.clone(),
+                };
+                match presets.iter_mut().find(|p| p.name == name) {
+                    Some(existing) => *existing = preset,
+                    None => presets.push(preset),
+                }
+                self.preset_name.clear();
+                action = Some(SensorAction::PresetsChanged);
+            }
+        });
+
+        if let Some(i) = remove {
+            presets.remove(i);
+            action = Some(SensorAction::PresetsChanged);
+        }
+        if let Some(settings) = apply {
+            let commands = self.commands(&settings);
+            self.settings = settings;
+            action = Some(SensorAction::Send(commands));
+        }
+        action
+    }
+}
@@ -0,0 +1,456 @@
+//!
+//! # SER Video Recording
+//! Writes live-view frames to SER files, the de facto standard format for planetary video.
+//!
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use eframe::egui;
+use egui::Ui;
+
+use crate::clock;
+use crate::frame::RawFrame;
+
+/// Byte offset of the `FrameCount` field in the SER header.
+const FRAME_COUNT_OFFSET: u64 = 38;
+
+/// Seconds between 0001-01-01 (the .NET epoch SER timestamps use) and the Unix epoch.
+const DOTNET_EPOCH_OFFSET: f64 = 62_135_596_800.0;
+
+/// Converts a Unix timestamp to .NET ticks (100 ns units since 0001-01-01).
+fn to_ticks(t: f64) -> i64 {
+    ((t + DOTNET_EPOCH_OFFSET) * 1e7) as i64
+}
+
+/// The `ColorID` field of a SER file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerColor {
+    /// Single-plane grayscale.
+    Mono,
+    /// Raw Bayer mosaic, RGGB.
+    BayerRggb,
+    /// Raw Bayer mosaic, GRBG.
+    BayerGrbg,
+    /// Raw Bayer mosaic, GBRG.
+    BayerGbrg,
+    /// Raw Bayer mosaic, BGGR.
+    BayerBggr,
+    /// Interleaved RGB.
+    Rgb,
+}
+
+impl SerColor {
+    /// All color layouts, in display order.
+    pub const ALL: [SerColor; 6] = [
+        SerColor::Mono,
+        SerColor::BayerRggb,
+        SerColor::BayerGrbg,
+        SerColor::BayerGbrg,
+        SerColor::BayerBggr,
+        SerColor::Rgb,
+    ];
+
+    fn id(&self) -> i32 {
+        match self {
+            SerColor::Mono => 0,
+            SerColor::BayerRggb => 8,
+            SerColor::BayerGrbg => 9,
+            SerColor::BayerGbrg => 10,
+            SerColor::BayerBggr => 11,
+            SerColor::Rgb => 100,
+        }
+    }
+
+    fn planes(&self) -> usize {
+        match self {
+            SerColor::Rgb => 3,
+            _ => 1,
+        }
+    }
+}
+
+/// An open SER file that frames are appended to.
+pub struct SerWriter {
+    out: BufWriter<File>,
+    width: usize,
+    height: usize,
+    planes: usize,
+    bit_depth: u8,
+    timestamps: Vec<i64>,
+    bytes_written: u64,
+}
+
+impl SerWriter {
+    /// Creates `path` and writes a header describing frames shaped like `first`.
+    ///
+    /// Frames deeper than 8 bits are written as 16-bit words, scaled up to the full 16-bit range.
+    /// The frame itself is not written; pass it to [`SerWriter::write_frame`] as usual.
+    pub fn create(path: &Path, first: &RawFrame, color: SerColor) -> std::io::Result<SerWriter> {
+        if color.planes() != first.channels {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{:?} needs {} channel(s) but the camera sends {}",
+                    color,
+                    color.planes(),
+                    first.channels
+                ),
+            ));
+        }
+
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(b"LUCAM-RECORDER")?;
+        out.write_all(&0i32.to_le_bytes())?; // LuID
+        out.write_all(&color.id().to_le_bytes())?;
+        // The spec defines 1 as little-endian 16-bit data, which is what we write.
+        out.write_all(&1i32.to_le_bytes())?;
+        out.write_all(&(first.width as i32).to_le_bytes())?;
+        out.write_all(&(first.height as i32).to_le_bytes())?;
+        let depth: i32 = if first.bit_depth <= 8 { 8 } else { 16 };
+        out.write_all(&depth.to_le_bytes())?;
+        out.write_all(&0i32.to_le_bytes())?; // FrameCount, patched in finish()
+        out.write_all(&[0u8; 40])?; // Observer
+        out.write_all(&pad40("Generic Camera GUI"))?; // Instrument
+        out.write_all(&[0u8; 40])?; // Telescope
+        let ticks = to_ticks(first.timestamp);
+        out.write_all(&ticks.to_le_bytes())?; // DateTime (local, we only know UTC)
+        out.write_all(&ticks.to_le_bytes())?; // DateTime_UTC
+
+        Ok(SerWriter {
+            out,
+            width: first.width,
+            height: first.height,
+            planes: first.channels,
+            bit_depth: first.bit_depth,
+            timestamps: Vec::new(),
+            bytes_written: 178,
+        })
+    }
+
+    /// Appends a frame. Returns `Ok(false)` without writing if its shape differs from the file's.
+    pub fn write_frame(&mut self, frame: &RawFrame) -> std::io::Result<bool> {
+        if frame.width != self.width
+            || frame.height != self.height
+            || frame.channels != self.planes
+            || frame.bit_depth != self.bit_depth
+        {
+            return Ok(false);
+        }
+
+        let bytes: Vec<u8> = if self.bit_depth <= 8 {
+            frame.data.iter().map(|&v| v as u8).collect()
+        } else {
+            let shift = 16 - self.bit_depth as u32;
+            frame
+                .data
+                .iter()
+                .flat_map(|&v| (((v as u32) << shift).min(u16::MAX as u32) as u16).to_le_bytes())
+                .collect()
+        };
+        self.out.write_all(&bytes)?;
+        self.bytes_written += bytes.len() as u64;
+        self.timestamps.push(to_ticks(frame.timestamp));
+        Ok(true)
+    }
+
+    /// Number of frames written so far.
+    pub fn frames(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    /// Total bytes written so far, including the header.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Writes the timestamp trailer and the final frame count, then closes the file.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        for ticks in &self.timestamps {
+            self.out.write_all(&ticks.to_le_bytes())?;
+        }
+        self.out.seek(SeekFrom::Start(FRAME_COUNT_OFFSET))?;
+        self.out
+            .write_all(&(self.timestamps.len() as i32).to_le_bytes())?;
+        self.out.flush()
+    }
+}
+
+fn pad40(s: &str) -> [u8; 40] {
+    let mut buf = [0u8; 40];
+    for (b, c) in buf.iter_mut().zip(s.bytes()) {
+        *b = c;
+    }
+    buf
+}
+
+/// When a recording stops on its own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordLimit {
+    /// Until stopped by the user.
+    None,
+    /// After this many frames.
+    Frames(usize),
+    /// After this many seconds.
+    Seconds(f64),
+}
+
+/// Records incoming frames to SER files and reports throughput.
+pub struct SerRecorder {
+    /// Directory recordings are written to.
+    pub output_dir: String,
+    /// Color layout written to the header.
+    pub color: SerColor,
+    /// When to stop automatically.
+    pub limit: RecordLimit,
+    writer: Option<SerWriter>,
+    path: PathBuf,
+    recording: bool,
+    started: f64,
+    elapsed: f64,
+    recorded: usize,
+    /// Frames the camera numbered but that never arrived.
+    dropped: usize,
+    /// Frames that arrived but did not match the file's shape.
+    skipped: usize,
+    /// The camera's number for the last frame that arrived.
+    last_sequence: Option<u64>,
+    bytes: u64,
+}
+
+impl Default for SerRecorder {
+    fn default() -> Self {
+        Self {
+            output_dir: ".".into(),
+            color: SerColor::Mono,
+            limit: RecordLimit::None,
+            writer: None,
+            path: PathBuf::new(),
+            recording: false,
+            started: 0.0,
+            elapsed: 0.0,
+            recorded: 0,
+            dropped: 0,
+            skipped: 0,
+            last_sequence: None,
+            bytes: 0,
+        }
+    }
+}
+
+impl SerRecorder {
+    /// Whether a recording is in progress.
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Arms the recorder; the file is created when the first frame arrives so its shape is known.
+    pub fn start(&mut self, now: f64) {
+        self.writer = None;
+        self.recording = true;
+        self.started = now;
+        self.elapsed = 0.0;
+        self.recorded = 0;
+        self.dropped = 0;
+        self.skipped = 0;
+        self.last_sequence = None;
+        self.bytes = 0;
+    }
+
+    /// Stops recording and finalizes the file. Returns a summary message.
+    pub fn stop(&mut self) -> std::io::Result<String> {
+        self.recording = false;
+        match self.writer.take() {
+            Some(writer) => {
+                writer.finish()?;
+                Ok(format!(
+                    "Recorded {} frames ({} dropped, {} skipped) to {}",
+                    self.recorded,
+                    self.dropped,
+                    self.skipped,
+                    self.path.display()
+                ))
+            }
+            None => Ok("Recording stopped before any frames arrived.".into()),
+        }
+    }
+
+    /// Records `frame` at egui time `now`. Returns `Ok(true)` once the recording limit is reached.
+    pub fn push(&mut self, frame: &RawFrame, now: f64) -> std::io::Result<bool> {
+        if !self.is_recording() {
+            return Ok(false);
+        }
+
+        if self.writer.is_none() {
+            self.path = PathBuf::from(&self.output_dir).join(format!(
+                "capture_{}.ser",
+                clock::file_stamp(frame.timestamp)
+            ));
+            self.writer = Some(SerWriter::create(&self.path, frame, self.color)?);
+        }
+        let writer = self.writer.as_mut().unwrap(); // created just above
+
+        // Gaps in the camera's frame numbers are frames lost on the way.
+        if let (Some(last), Some(sequence)) = (self.last_sequence, frame.sequence) {
+            self.dropped += sequence.saturating_sub(last + 1) as usize;
+        }
+        if frame.sequence.is_some() {
+            self.last_sequence = frame.sequence;
+        }
+
+        if writer.write_frame(frame)? {
+            self.recorded = writer.frames();
+            self.bytes = writer.bytes_written();
+        } else {
+            self.skipped += 1;
+        }
+        self.elapsed = now - self.started;
+
+        Ok(match self.limit {
+            RecordLimit::None => false,
+            RecordLimit::Frames(n) => self.recorded >= n,
+            RecordLimit::Seconds(s) => self.elapsed >= s,
+        })
+    }
+
+    /// Shows the recording settings and the live readout.
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.add_enabled_ui(!self.is_recording(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Color");
+                egui::ComboBox::from_id_source("SerColor")
+                    .selected_text(format!("{:?}", self.color))
+                    .show_ui(ui, |ui| {
+                        for c in SerColor::ALL {
+                            ui.selectable_value(&mut self.color, c, format!("{:?}", c));
+                        }
+                    });
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Limit");
+                egui::ComboBox::from_id_source("SerLimit")
+                    .selected_text(match self.limit {
+                        RecordLimit::None => "None",
+                        RecordLimit::Frames(_) => "Frames",
+                        RecordLimit::Seconds(_) => "Duration",
+                    })
+                    .show_ui(ui, |ui| {
+                        if ui
+                            .selectable_label(self.limit == RecordLimit::None, "None")
+                            .clicked()
+                        {
+                            self.limit = RecordLimit::None;
+                        }
+                        if ui
+                            .selectable_label(
+                                matches!(self.limit, RecordLimit::Frames(_)),
+                                "Frames",
+                            )
+                            .clicked()
+                        {
+                            self.limit = RecordLimit::Frames(1000);
+                        }
+                        if ui
+                            .selectable_label(
+                                matches!(self.limit, RecordLimit::Seconds(_)),
+                                "Duration",
+                            )
+                            .clicked()
+                        {
+                            self.limit = RecordLimit::Seconds(60.0);
+                        }
+                    });
+                match &mut self.limit {
+                    RecordLimit::None => {}
+                    RecordLimit::Frames(n) => {
+                        ui.add(egui::DragValue::new(n).range(1..=1_000_000));
+                    }
+                    RecordLimit::Seconds(s) => {
+                        ui.add(egui::DragValue::new(s).range(1.0..=36000.0).suffix(" s"));
+                    }
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Output Directory");
+                ui.text_edit_singleline(&mut self.output_dir);
+            });
+        });
+
+        let rate = if self.elapsed > 0.0 {
+            self.bytes as f64 / self.elapsed / 1e6
+        } else {
+            0.0
+        };
+        ui.label(format!(
+            "Recorded {} · Dropped {} · Skipped {} · {:.1} MB/s",
+            self.recorded, self.dropped, self.skipped, rate
+        ))
+        .on_hover_text(
+            "Dropped frames are gaps in the camera's frame numbers. \
+             Skipped frames arrived with a different size or format.",
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn i32_at(bytes: &[u8], offset: usize) -> i32 {
+        i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn writes_header_frames_and_trailer() {
+        let path = std::env::temp_dir().join(format!("gencam_ser_{}.ser", std::process::id()));
+        let frame = RawFrame {
+            width: 3,
+            height: 2,
+            channels: 1,
+            bit_depth: 12,
+            data: vec![0, 1, 2, 4095, 100, 7],
+            timestamp: 0.0,
+            request: None,
+            sequence: None,
+        };
+        let mut writer = SerWriter::create(&path, &frame, SerColor::BayerRggb).unwrap();
+        assert!(writer.write_frame(&frame).unwrap());
+        assert!(writer.write_frame(&frame).unwrap());
+        let other = RawFrame {
+            width: 2,
+            data: vec![0; 4],
+            ..frame.clone()
+        };
+        assert!(!writer.write_frame(&other).unwrap());
+        assert_eq!(writer.frames(), 2);
+        assert_eq!(writer.bytes_written(), 178 + 2 * 12);
+        writer.finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bytes.len(), 178 + 2 * 12 + 2 * 8);
+        assert_eq!(&bytes[..14], b"LUCAM-RECORDER");
+        assert_eq!(i32_at(&bytes, 14), 0);
+        assert_eq!(i32_at(&bytes, 18), 8);
+        assert_eq!(i32_at(&bytes, 22), 1);
+        assert_eq!(i32_at(&bytes, 26), 3);
+        assert_eq!(i32_at(&bytes, 30), 2);
+        assert_eq!(i32_at(&bytes, 34), 16);
+        assert_eq!(i32_at(&bytes, FRAME_COUNT_OFFSET as usize), 2);
+        assert_eq!(&bytes[82..100], b"Generic Camera GUI");
+
+        let ticks = i64::from_le_bytes(bytes[162..170].try_into().unwrap());
+        assert_eq!(ticks, to_ticks(0.0));
+        assert_eq!(ticks, 621_355_968_000_000_000);
+
+        // 12-bit samples are scaled up to the full 16-bit range.
+        let samples: Vec<u16> = bytes[178..190]
+            .chunks(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(samples, [0, 16, 32, 65520, 1600, 112]);
+    }
+}
@@ -0,0 +1,245 @@
+//!
+//! # Saved Settings
+//! The GUI settings kept across restarts in eframe's storage. Settings are stored as versioned
+//! JSON so that state saved by older versions is carried forward rather than discarded.
+//!
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::connect_dialog::ServerHistory;
+use crate::display::DisplaySettings;
+use crate::overlay::Overlays;
+
+/// Version of the settings format written by this build.
+pub const SETTINGS_VERSION: u32 = 1;
+
+/// Key the settings are stored under.
+const STORAGE_KEY: &str = "settings";
+
+/// Key the server history was stored under before settings were versioned.
+const LEGACY_SERVERS_KEY: &str = "servers";
+
+/// Everything the GUI restores on start-up.
+///
+/// Panel sizes and window positions are kept by egui itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuiSettings {
+    /// Format version the settings were saved with.
+    pub version: u32,
+    /// Use the dark theme.
+    pub dark_mode: bool,
+    /// URI in the connect box.
+    pub uri: String,
+    /// Favorite and recent servers.
+    pub servers: ServerHistory,
+    /// Exposure slider position, in ms or s depending on `long_exposure`.
+    pub exposure_slider: f32,
+    /// The exposure slider is in seconds.
+    pub long_exposure: bool,
+    /// Region of interest as `[x, y, width, height]`, or center and size when `roi_centered`.
+    pub roi: [f32; 4],
+    /// The ROI is given by its center rather than its corner.
+    pub roi_centered: bool,
+    /// Apply the region of interest.
+    pub roi_enabled: bool,
+    /// Symmetric binning factor.
+    pub binning: u8,
+    /// Framing overlays and the reticle position.
+    pub overlays: Overlays,
+    /// Display stretch and colormap.
+    pub display: DisplaySettings,
+    /// Show every camera side by side.
+    pub tiled_view: bool,
+    /// Columns in the tiled view, or 0 for automatic.
+    pub tile_columns: usize,
+    /// Mark clipped pixels in the viewer.
+    pub highlight_clipping: bool,
+    /// The Capture Plan window is open.
+    pub show_capture_plan: bool,
+    /// The Master Builder window is open.
+    pub show_master_builder: bool,
+    /// The Synchronized Capture window is open.
+    pub show_sync_capture: bool,
+    /// Capture plan file.
+    pub plan_path: String,
+    /// Directory capture plans write to.
+    pub plan_output_dir: String,
+    /// Directory synchronized captures write to.
+    pub sync_output_dir: String,
+    /// Id given to the next synchronized capture group, so ids do not repeat across restarts.
+    pub sync_next_id: u32,
+    /// Sensor preset file.
+    pub sensor_presets_path: String,
+    /// Camera profile file.
+    pub camera_profiles_path: String,
+}
+
+impl Default for GuiSettings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            dark_mode: false,
+            uri: "ws://localhost:9001".into(),
+            servers: ServerHistory::default(),
+            exposure_slider: 0.0,
+            long_exposure: false,
+            roi: [0.0, 0.0, 0.0, 0.0],
+            roi_centered: true,
+            roi_enabled: false,
+            binning: 1,
+            overlays: Overlays::default(),
+            display: DisplaySettings::default(),
+            tiled_view: false,
+            tile_columns: 0,
+            highlight_clipping: false,
+            show_capture_plan: false,
+            show_master_builder: false,
+            show_sync_capture: false,
+            plan_path: "plan.json".into(),
+            plan_output_dir: ".".into(),
+            sync_output_dir: ".".into(),
+            sync_next_id: 1,
+            sensor_presets_path: "sensor_presets.json".into(),
+            camera_profiles_path: "camera_profiles.json".into(),
+        }
+    }
+}
+
+impl GuiSettings {
+    /// Reads the settings from `storage`, upgrading them if an older version saved them.
+    /// Returns `None` if nothing was saved yet, and an error if the saved state is unreadable.
+    pub fn load(storage: &dyn eframe::Storage) -> Option<Result<GuiSettings, String>> {
+        match storage.get_string(STORAGE_KEY) {
+            Some(text) => Some(GuiSettings::from_json(&text)),
+            None => {
+                let servers: ServerHistory = eframe::get_value(storage, LEGACY_SERVERS_KEY)?;
+                let mut value = serde_json::to_value(servers).unwrap();
+                migrate(&mut value, 0);
+                Some(serde_json::from_value(value).map_err(|e| e.to_string()))
+            }
+        }
+    }
+
+    /// Reads settings saved as JSON by any version.
+    fn from_json(text: &str) -> Result<GuiSettings, String> {
+        let mut value = serde_json::from_str::<Value>(text).map_err(|e| e.to_string())?;
+        let version = match value.get("version").and_then(Value::as_u64) {
+            Some(version) => version,
+            None if is_server_history(&value) => 0,
+            // Settings with their fields but no version were written by the current format.
+            None => SETTINGS_VERSION as u64,
+        };
+        migrate(&mut value, version);
+        serde_json::from_value(value).map_err(|e| e.to_string())
+    }
+
+    /// Writes the settings to `storage`.
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(STORAGE_KEY, serde_json::to_string(self).unwrap());
+    }
+}
+
+/// Whether `value` is a bare server history, the only thing saved before version 1.
+fn is_server_history(value: &Value) -> bool {
+    value
+        .as_object()
+        .is_some_and(|map| map.keys().all(|k| k == "recent" || k == "favorites"))
+}
+
+/// Upgrades `value`, saved in format `version`, to the current format one version at a time.
+///
+/// Fields added without a change of meaning need no step; they take their defaults. Fields a
+/// newer version added are ignored.
+fn migrate(value: &mut Value, version: u64) {
+    // Version 0 is the server history saved on its own before settings were versioned.
+    if version < 1 {
+        *value = serde_json::json!({ "servers": value.take() });
+    }
+    if let Some(map) = value.as_object_mut() {
+        map.insert("version".into(), SETTINGS_VERSION.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MemoryStorage(HashMap<String, String>);
+
+    impl eframe::Storage for MemoryStorage {
+        fn get_string(&self, key: &str) -> Option<String> {
+            self.0.get(key).cloned()
+        }
+
+        fn set_string(&mut self, key: &str, value: String) {
+            self.0.insert(key.to_owned(), value);
+        }
+
+        fn flush(&mut self) {}
+    }
+
+    fn history() -> ServerHistory {
+        let mut servers = ServerHistory::default();
+        servers.used("ws://scope:9001");
+        servers.used("ws://localhost:9001");
+        servers
+    }
+
+    #[test]
+    fn nothing_saved() {
+        assert!(GuiSettings::load(&MemoryStorage::default()).is_none());
+    }
+
+    #[test]
+    fn loads_legacy_servers_key() {
+        let mut storage = MemoryStorage::default();
+        eframe::set_value(&mut storage, LEGACY_SERVERS_KEY, &history());
+        let settings = GuiSettings::load(&storage).unwrap().unwrap();
+        assert_eq!(settings.servers, history());
+        assert_eq!(settings.version, SETTINGS_VERSION);
+        assert_eq!(settings.binning, 1);
+    }
+
+    #[test]
+    fn loads_v0_servers_blob() {
+        let text = serde_json::to_string(&history()).unwrap();
+        let settings = GuiSettings::from_json(&text).unwrap();
+        assert_eq!(
+            settings,
+            GuiSettings {
+                servers: history(),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn loads_v1_blob() {
+        let mut saved = GuiSettings {
+            dark_mode: true,
+            servers: history(),
+            binning: 2,
+            ..Default::default()
+        };
+        saved.overlays.reticle = Some((10.0, 20.0));
+        let mut storage = MemoryStorage::default();
+        saved.save(&mut storage);
+        assert_eq!(GuiSettings::load(&storage).unwrap().unwrap(), saved);
+    }
+
+    #[test]
+    fn missing_version_with_settings_fields_is_current() {
+        let text =
+            r#"{"dark_mode": true, "binning": 3, "servers": {"recent": ["ws://scope:9001"]}}"#;
+        let settings = GuiSettings::from_json(text).unwrap();
+        assert!(settings.dark_mode);
+        assert_eq!(settings.binning, 3);
+        assert_eq!(settings.servers.recent, ["ws://scope:9001"]);
+        assert_eq!(settings.version, SETTINGS_VERSION);
+    }
+}
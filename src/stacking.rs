@@ -0,0 +1,574 @@
+//!
+//! # Master Frame Stacking
+//! Combines a set of calibration frames into a master using mean, median, or kappa-sigma-clipped
+//! mean. Native builds stack rows in parallel on a background thread.
+//!
+
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use eframe::egui;
+use egui::Ui;
+
+use crate::calibration::{FrameInfo, MasterFrame, MasterKind};
+use crate::fits::{self, HeaderCard, HeaderValue};
+use crate::frame::{FloatFrame, RawFrame};
+use crate::stats;
+
+/// How corresponding pixels of the input frames are combined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StackMethod {
+    /// Arithmetic mean.
+    Mean,
+    /// Median, robust against outliers but noisier than a mean.
+    Median,
+    /// Mean after repeatedly rejecting values more than `kappa` standard deviations from the median.
+    KappaSigma {
+        /// Rejection threshold in standard deviations.
+        kappa: f32,
+        /// Maximum number of rejection passes.
+        iterations: u32,
+    },
+}
+
+impl StackMethod {
+    /// Combines `values` into one, reordering them in the process.
+    fn combine(&self, values: &mut [f32]) -> f32 {
+        match *self {
+            StackMethod::Mean => values.iter().sum::<f32>() / values.len() as f32,
+            StackMethod::Median => stats::median_f32(values),
+            StackMethod::KappaSigma { kappa, iterations } => {
+                let mut n = values.len();
+                for _ in 0..iterations {
+                    let kept = &mut values[..n];
+                    let center = stats::median_f32(kept);
+                    let mean = kept.iter().sum::<f32>() / n as f32;
+                    let var = kept.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / n as f32;
+                    let limit = kappa * var.sqrt();
+
+                    // Move the survivors to the front.
+                    let mut m = 0;
+                    for i in 0..n {
+                        if (kept[i] - center).abs() <= limit {
+                            kept.swap(i, m);
+                            m += 1;
+                        }
+                    }
+                    if m == n || m == 0 {
+                        break;
+                    }
+                    n = m;
+                }
+                values[..n].iter().sum::<f32>() / n as f32
+            }
+        }
+    }
+}
+
+/// Stacks one output row. `row` is the row index and `out` its interleaved samples.
+fn stack_row(frames: &[RawFrame], method: StackMethod, row: usize, out: &mut [f32]) {
+    let offset = row * out.len();
+    let mut values = vec![0f32; frames.len()];
+    for (i, o) in out.iter_mut().enumerate() {
+        for (v, f) in values.iter_mut().zip(frames) {
+            *v = f.data[offset + i] as f32;
+        }
+        *o = method.combine(&mut values);
+    }
+}
+
+/// Stacks `frames`, which must all share the first frame's shape, counting finished rows in
+/// `progress`. The result keeps the fractional part of the combined values.
+pub fn stack(
+    frames: &[RawFrame],
+    method: StackMethod,
+    progress: &AtomicUsize,
+) -> Result<FloatFrame, String> {
+    let first = frames.first().ok_or("no frames to stack")?;
+    if let Some(bad) = frames.iter().position(|f| !f.same_shape(first)) {
+        return Err(format!(
+            "frame {} is {} but frame 1 is {}",
+            bad + 1,
+            frames[bad].shape(),
+            first.shape()
+        ));
+    }
+
+    let mut out = FloatFrame {
+        width: first.width,
+        height: first.height,
+        channels: first.channels,
+        bit_depth: first.bit_depth,
+        data: vec![0.0; first.data.len()],
+    };
+    let row_len = first.width * first.channels;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use rayon::prelude::*;
+        out.data
+            .par_chunks_mut(row_len)
+            .enumerate()
+            .for_each(|(row, chunk)| {
+                stack_row(frames, method, row, chunk);
+                progress.fetch_add(1, Ordering::Relaxed);
+            });
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        for (row, chunk) in out.data.chunks_mut(row_len).enumerate() {
+            stack_row(frames, method, row, chunk);
+            progress.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    Ok(out)
+}
+
+/// A stack running in the background.
+pub struct StackJob {
+    progress: Arc<AtomicUsize>,
+    rows: usize,
+    #[cfg(not(target_arch = "wasm32"))]
+    result: std::sync::mpsc::Receiver<Result<FloatFrame, String>>,
+    #[cfg(target_arch = "wasm32")]
+    result: Option<Result<FloatFrame, String>>,
+}
+
+impl StackJob {
+    /// Starts stacking `frames`. On the web there are no threads, so this blocks until done.
+    pub fn spawn(frames: Vec<RawFrame>, method: StackMethod) -> StackJob {
+        let progress = Arc::new(AtomicUsize::new(0));
+        let rows = frames.first().map_or(0, |f| f.height);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (tx, rx) = std::sync::mpsc::channel();
+            let counter = progress.clone();
+            std::thread::spawn(move || {
+                let _ = tx.send(stack(&frames, method, &counter));
+            });
+            StackJob {
+                progress,
+                rows,
+                result: rx,
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            let result = Some(stack(&frames, method, &progress));
+            StackJob {
+                progress,
+                rows,
+                result,
+            }
+        }
+    }
+
+    /// Fraction of rows stacked so far.
+    pub fn progress(&self) -> f32 {
+        if self.rows == 0 {
+            1.0
+        } else {
+            self.progress.load(Ordering::Relaxed) as f32 / self.rows as f32
+        }
+    }
+
+    /// Returns the result once the stack has finished.
+    pub fn try_take(&mut self) -> Option<Result<FloatFrame, String>> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            match self.result.try_recv() {
+                Ok(result) => Some(result),
+                Err(std::sync::mpsc::TryRecvError::Empty) => None,
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                    Some(Err("stacking thread exited unexpectedly".into()))
+                }
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.result.take()
+        }
+    }
+}
+
+/// Something the master builder asks the GUI to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuilderAction {
+    /// Add the most recent frame to the input list.
+    AddCurrent,
+    /// Load a FITS file and add it to the input list.
+    AddFile(String),
+    /// Install the finished master for calibration.
+    UseMaster,
+}
+
+/// Most memory the builder's input frames may take, in bytes. Stacking copies them once more.
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_INPUT_BYTES: usize = 2 << 30;
+
+/// Most memory the builder's input frames may take, in bytes. Stacking copies them once more, and
+/// browsers give a page little more than a few gigabytes in total.
+#[cfg(target_arch = "wasm32")]
+const MAX_INPUT_BYTES: usize = 512 << 20;
+
+/// Input frames and settings for building a master calibration frame.
+pub struct MasterBuilder {
+    /// What kind of master is being built.
+    pub kind: MasterKind,
+    /// How frames are combined.
+    pub method: StackMethod,
+    /// Add every incoming frame to the input list.
+    pub collect: bool,
+    /// Frames to stack with their acquisition settings and a label.
+    frames: Vec<(RawFrame, FrameInfo, String)>,
+    /// Where the finished master is written.
+    pub output_path: String,
+    /// The running stack, with the number of frames in it, the first frame's settings and the
+    /// time it was received.
+    job: Option<(StackJob, usize, FrameInfo, f64)>,
+    /// The most recently built master.
+    pub result: Option<MasterFrame>,
+    input_path: String,
+}
+
+impl Default for MasterBuilder {
+    fn default() -> Self {
+        Self {
+            kind: MasterKind::Dark,
+            method: StackMethod::KappaSigma {
+                kappa: 3.0,
+                iterations: 3,
+            },
+            collect: false,
+            frames: Vec::new(),
+            output_path: "master.fits".into(),
+            job: None,
+            result: None,
+            input_path: String::new(),
+        }
+    }
+}
+
+impl MasterBuilder {
+    /// Memory taken by the input frames, in bytes.
+    fn input_bytes(&self) -> usize {
+        self.frames
+            .iter()
+            .map(|(f, _, _)| f.data.len() * std::mem::size_of::<u16>())
+            .sum()
+    }
+
+    /// Whether another frame the size of the first would exceed the memory limit.
+    fn is_full(&self) -> bool {
+        let next = self
+            .frames
+            .first()
+            .map_or(0, |(f, _, _)| f.data.len() * std::mem::size_of::<u16>());
+        self.input_bytes() + next > MAX_INPUT_BYTES
+    }
+
+    /// Adds a frame to the input list, unless a stack is running or the list would outgrow the
+    /// memory limit. Collecting is switched off once the list is full. Returns whether the frame
+    /// was added.
+    pub fn add(&mut self, frame: RawFrame, info: FrameInfo, label: String) -> bool {
+        if self.job.is_some() {
+            return false;
+        }
+        if self.input_bytes() + frame.data.len() * std::mem::size_of::<u16>() > MAX_INPUT_BYTES {
+            self.collect = false;
+            return false;
+        }
+        self.frames.push((frame, info, label));
+        true
+    }
+
+    /// Starts stacking the input frames in the background.
+    fn build(&mut self) {
+        let frames: Vec<_> = self.frames.iter().map(|(f, _, _)| f.clone()).collect();
+        let (count, info, received) = (frames.len(), self.frames[0].1, self.frames[0].0.timestamp);
+        self.job = Some((StackJob::spawn(frames, self.method), count, info, received));
+    }
+
+    /// Collects a finished stack and writes it to the output path.
+    ///
+    /// Returns `None` while the stack is still running or when none was started, and otherwise a
+    /// message describing the result.
+    pub fn poll(&mut self) -> Option<Result<String, String>> {
+        let (job, count, info, received) = self.job.as_mut()?;
+        let result = job.try_take()?;
+        let (count, info, received) = (*count, *info, *received);
+        self.job = None;
+        let frame = match result {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let imagetyp = match self.kind {
+            MasterKind::Bias => "Master Bias",
+            MasterKind::Dark => "Master Dark",
+            MasterKind::Flat => "Master Flat",
+        };
+        let mut cards = fits::frame_cards(
+            imagetyp,
+            info.exposure,
+            received,
+            frame.bit_depth,
+            info.temperature,
+        );
+        cards.push(HeaderCard::new(
+            "NCOMBINE",
+            HeaderValue::Int(count as i64),
+            "number of frames combined",
+        ));
+        cards.push(HeaderCard::new(
+            "XBINNING",
+            HeaderValue::Int(info.binning as i64),
+            "binning factor in x",
+        ));
+        cards.push(HeaderCard::new(
+            "YBINNING",
+            HeaderValue::Int(info.binning as i64),
+            "binning factor in y",
+        ));
+        let path = Path::new(&self.output_path);
+        let saved = fits::write_fits_f32(path, &frame, &cards);
+
+        self.result = Some(MasterFrame {
+            frame,
+            info,
+            source: path.display().to_string(),
+        });
+        Some(match saved {
+            Ok(()) => Ok(format!(
+                "Built {} from {} frames: {}",
+                imagetyp.to_lowercase(),
+                count,
+                path.display()
+            )),
+            Err(e) => Err(format!(
+                "Built {} but failed to save it: {}",
+                imagetyp.to_lowercase(),
+                e
+            )),
+        })
+    }
+
+    /// Shows the builder. Returns an action for the GUI to carry out, if any.
+    pub fn ui(&mut self, ui: &mut Ui, have_frame: bool) -> Option<BuilderAction> {
+        let mut action = None;
+        let busy = self.job.is_some();
+        let full = self.is_full();
+
+        ui.add_enabled_ui(!busy, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Master");
+                for kind in MasterKind::ALL {
+                    ui.selectable_value(&mut self.kind, kind, format!("{:?}", kind));
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Method");
+                egui::ComboBox::from_id_source("StackMethod")
+                    .selected_text(match self.method {
+                        StackMethod::Mean => "Mean",
+                        StackMethod::Median => "Median",
+                        StackMethod::KappaSigma { .. } => "Kappa-Sigma",
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.method, StackMethod::Mean, "Mean");
+                        ui.selectable_value(&mut self.method, StackMethod::Median, "Median");
+                        if ui
+                            .selectable_label(
+                                matches!(self.method, StackMethod::KappaSigma { .. }),
+                                "Kappa-Sigma",
+                            )
+                            .clicked()
+                        {
+                            self.method = StackMethod::KappaSigma {
+                                kappa: 3.0,
+                                iterations: 3,
+                            };
+                        }
+                    });
+                if let StackMethod::KappaSigma { kappa, iterations } = &mut self.method {
+                    ui.label("κ");
+                    ui.add(egui::DragValue::new(kappa).range(0.5..=10.0).speed(0.1));
+                    ui.label("Passes");
+                    ui.add(egui::DragValue::new(iterations).range(1..=10));
+                }
+            });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "{} input frames, {} of {} MB",
+                    self.frames.len(),
+                    self.input_bytes() >> 20,
+                    MAX_INPUT_BYTES >> 20
+                ));
+                if ui
+                    .add_enabled(have_frame && !full, egui::Button::new("Add Current Frame"))
+                    .clicked()
+                {
+                    action = Some(BuilderAction::AddCurrent);
+                }
+                ui.checkbox(&mut self.collect, "Add incoming frames");
+                if ui.button("Clear").clicked() {
+                    self.frames.clear();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.input_path);
+                if ui
+                    .add_enabled(!full, egui::Button::new("Add FITS"))
+                    .clicked()
+                {
+                    action = Some(BuilderAction::AddFile(self.input_path.clone()));
+                }
+            });
+
+            let mut remove = None;
+            egui::ScrollArea::vertical()
+                .max_height(150.0)
+                .show(ui, |ui| {
+                    for (i, (frame, info, label)) in self.frames.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            if ui.small_button("✖").clicked() {
+                                remove = Some(i);
+                            }
+                            ui.label(format!(
+                                "{}: {}x{}, {:.3} s",
+                                label, frame.width, frame.height, info.exposure
+                            ));
+                        });
+                    }
+                });
+            if let Some(i) = remove {
+                self.frames.remove(i);
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Output");
+                ui.text_edit_singleline(&mut self.output_path);
+            });
+        });
+
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(
+                    !busy && self.frames.len() >= 2,
+                    egui::Button::new("Build Master"),
+                )
+                .on_disabled_hover_text("Add at least two frames.")
+                .clicked()
+            {
+                self.build();
+            }
+            if ui
+                .add_enabled(
+                    !busy && self.result.is_some(),
+                    egui::Button::new("Use for Calibration"),
+                )
+                .clicked()
+            {
+                action = Some(BuilderAction::UseMaster);
+            }
+        });
+
+        if let Some((job, _, _, _)) = &self.job {
+            ui.add(egui::ProgressBar::new(job.progress()).show_percentage());
+            ui.ctx().request_repaint();
+        }
+
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(data: Vec<u16>) -> RawFrame {
+        RawFrame {
+            width: data.len(),
+            height: 1,
+            channels: 1,
+            bit_depth: 12,
+            data,
+            timestamp: 0.0,
+            request: None,
+            sequence: None,
+        }
+    }
+
+    #[test]
+    fn kappa_sigma_rejects_outliers() {
+        let mut values = [100.0, 102.0, 98.0, 101.0, 99.0, 500.0];
+        let method = StackMethod::KappaSigma {
+            kappa: 2.0,
+            iterations: 3,
+        };
+        assert_eq!(method.combine(&mut values), 100.0);
+    }
+
+    #[test]
+    fn kappa_sigma_keeps_uniform_values() {
+        let mut values = [7.0; 5];
+        let method = StackMethod::KappaSigma {
+            kappa: 2.0,
+            iterations: 3,
+        };
+        assert_eq!(method.combine(&mut values), 7.0);
+    }
+
+    #[test]
+    fn stacks_to_fractional_means() {
+        let frames = [
+            frame(vec![1, 10, 4000, 0]),
+            frame(vec![2, 10, 4001, 0]),
+            frame(vec![2, 11, 4001, 3]),
+            frame(vec![2, 10, 4002, 0]),
+        ];
+        let progress = AtomicUsize::new(0);
+        let mean = stack(&frames, StackMethod::Mean, &progress).unwrap();
+        assert_eq!(mean.data, [1.75, 10.25, 4001.0, 0.75]);
+        assert_eq!(mean.bit_depth, 12);
+        assert_eq!(progress.load(Ordering::Relaxed), 1);
+
+        let median = stack(&frames, StackMethod::Median, &AtomicUsize::new(0)).unwrap();
+        assert_eq!(median.data, [2.0, 10.0, 4001.0, 0.0]);
+    }
+
+    #[test]
+    fn counts_rows_in_progress() {
+        let frames = [
+            RawFrame {
+                height: 5,
+                data: vec![1; 15],
+                ..frame(vec![0; 3])
+            },
+            RawFrame {
+                height: 5,
+                data: vec![3; 15],
+                ..frame(vec![0; 3])
+            },
+        ];
+        let progress = AtomicUsize::new(0);
+        let out = stack(&frames, StackMethod::Mean, &progress).unwrap();
+        assert_eq!(out.data, [2.0; 15]);
+        assert_eq!(progress.load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn refuses_mismatched_frames() {
+        let frames = [frame(vec![0; 4]), frame(vec![0; 3])];
+        let err = stack(&frames, StackMethod::Mean, &AtomicUsize::new(0)).unwrap_err();
+        assert!(err.starts_with("frame 2 is "), "{}", err);
+        assert!(stack(&[], StackMethod::Mean, &AtomicUsize::new(0)).is_err());
+    }
+}
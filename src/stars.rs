@@ -0,0 +1,366 @@
+//!
+//! # Star Detection
+//! Finds point sources in a frame and measures their positions and sizes.
+//!
+
+use eframe::egui;
+use egui::Ui;
+
+use crate::frame::RawFrame;
+use crate::stats;
+
+/// Half-width of the box a star's centroid is measured in.
+const STAR_RADIUS: usize = 4;
+
+/// Half-width of the box a star's size is measured in.
+const MEASURE_RADIUS: usize = 12;
+
+/// Ratio of a Gaussian's full width at half maximum to its standard deviation.
+const FWHM_PER_SIGMA: f32 = 2.3548;
+
+/// Fewest pixels above the threshold a source needs, so hot pixels are not taken for stars.
+const MIN_STAR_PIXELS: usize = 3;
+
+/// Largest number of candidate peaks considered, brightest first.
+const MAX_CANDIDATES: usize = 5000;
+
+/// Largest number of stars reported for a frame.
+const MAX_STARS: usize = 500;
+
+/// Largest number of background samples taken from a frame.
+const MAX_BACKGROUND_SAMPLES: usize = 100_000;
+
+/// A detected star.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Star {
+    /// Centroid column in pixels.
+    pub x: f32,
+    /// Centroid row in pixels.
+    pub y: f32,
+    /// Background-subtracted flux summed over the centroid box.
+    pub flux: f32,
+    /// Half-flux radius in pixels.
+    pub hfr: f32,
+    /// Full width at half maximum in pixels, from the star's second moments.
+    pub fwhm: f32,
+}
+
+/// The frame's luminance as one sample per pixel.
+pub fn luminance(frame: &RawFrame) -> Vec<f32> {
+    frame
+        .data
+        .chunks_exact(frame.channels)
+        .map(|px| px.iter().map(|&v| v as f32).sum::<f32>() / frame.channels as f32)
+        .collect()
+}
+
+/// Estimates the background level and noise of a luminance plane from its median and MAD.
+pub fn background(plane: &[f32]) -> (f32, f32) {
+    let step = (plane.len() / MAX_BACKGROUND_SAMPLES).max(1);
+    let mut samples: Vec<u16> = plane.iter().step_by(step).map(|&v| v as u16).collect();
+    let median = stats::percentile_of(&mut samples, 50.0) as f32;
+    let mut deviations: Vec<u16> = samples
+        .iter()
+        .map(|&v| (v as f32 - median).abs() as u16)
+        .collect();
+    let mad = stats::percentile_of(&mut deviations, 50.0) as f32;
+    // 1.4826 converts a MAD to a Gaussian standard deviation.
+    (median, (mad * 1.4826).max(1.0))
+}
+
+/// Finds stars brighter than `sigma` noise levels above the background, brightest first.
+///
+/// At most [`MAX_STARS`] stars are returned, picked from the [`MAX_CANDIDATES`] brightest peaks,
+/// so that crowded or noisy frames stay cheap to analyse.
+pub fn detect(frame: &RawFrame, sigma: f32) -> Vec<Star> {
+    let (w, h) = (frame.width, frame.height);
+    if w <= 2 * STAR_RADIUS || h <= 2 * STAR_RADIUS {
+        return Vec::new();
+    }
+    let plane = luminance(frame);
+    let (bg, noise) = background(&plane);
+    let threshold = bg + sigma * noise;
+
+    let mut stars = Vec::new();
+    for y in STAR_RADIUS..h - STAR_RADIUS {
+        for x in STAR_RADIUS..w - STAR_RADIUS {
+            let v = plane[y * w + x];
+            if v <= threshold || !is_peak(&plane, w, x, y) {
+                continue;
+            }
+
+            let (mut sx, mut sy, mut flux, mut count) = (0.0, 0.0, 0.0, 0);
+            for yy in y - STAR_RADIUS..=y + STAR_RADIUS {
+                for xx in x - STAR_RADIUS..=x + STAR_RADIUS {
+                    let p = plane[yy * w + xx];
+                    if p > threshold {
+                        count += 1;
+                    }
+                    let f = (p - bg).max(0.0);
+                    sx += f * xx as f32;
+                    sy += f * yy as f32;
+                    flux += f;
+                }
+            }
+            if count >= MIN_STAR_PIXELS && flux > 0.0 {
+                stars.push(Star {
+                    x: sx / flux,
+                    y: sy / flux,
+                    flux,
+                    hfr: 0.0,
+                    fwhm: 0.0,
+                });
+            }
+        }
+    }
+
+    if stars.len() > MAX_CANDIDATES {
+        stars.select_nth_unstable_by(MAX_CANDIDATES, |a, b| b.flux.total_cmp(&a.flux));
+        stars.truncate(MAX_CANDIDATES);
+    }
+    stars.sort_by(|a, b| b.flux.total_cmp(&a.flux));
+
+    // Drop secondary peaks inside a brighter star's box. Kept stars are binned into cells as wide
+    // as the minimum distance, so only the neighbouring cells need checking.
+    let min_dist = (2 * STAR_RADIUS) as f32;
+    let cols = w / (2 * STAR_RADIUS) + 1;
+    let cell = |s: &Star| ((s.x / min_dist) as usize, (s.y / min_dist) as usize);
+    let mut grid: Vec<Vec<Star>> = vec![Vec::new(); cols * (h / (2 * STAR_RADIUS) + 1)];
+    let mut kept: Vec<Star> = Vec::new();
+    for s in stars {
+        let (cx, cy) = cell(&s);
+        let crowded = (cy.saturating_sub(1)..=cy + 1)
+            .flat_map(|y| (cx.saturating_sub(1)..=cx + 1).map(move |x| (x, y)))
+            .filter(|&(x, _)| x < cols)
+            .filter_map(|(x, y)| grid.get(y * cols + x))
+            .flatten()
+            .any(|k| (k.x - s.x).hypot(k.y - s.y) < min_dist);
+        if !crowded {
+            grid[cy * cols + cx].push(s);
+            kept.push(s);
+            if kept.len() == MAX_STARS {
+                break;
+            }
+        }
+    }
+    for s in &mut kept {
+        (s.hfr, s.fwhm) = measure(&plane, w, h, bg, s.x, s.y);
+    }
+    kept
+}
+
+/// Measures the half-flux radius and FWHM of the star centred on (`cx`, `cy`).
+fn measure(plane: &[f32], w: usize, h: usize, bg: f32, cx: f32, cy: f32) -> (f32, f32) {
+    let x0 = (cx as usize).saturating_sub(MEASURE_RADIUS);
+    let y0 = (cy as usize).saturating_sub(MEASURE_RADIUS);
+    let x1 = (cx as usize + MEASURE_RADIUS).min(w - 1);
+    let y1 = (cy as usize + MEASURE_RADIUS).min(h - 1);
+
+    let (mut flux, mut radial, mut moment) = (0.0, 0.0, 0.0);
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            let (dx, dy) = (x as f32 - cx, y as f32 - cy);
+            let r2 = dx * dx + dy * dy;
+            if r2 > (MEASURE_RADIUS * MEASURE_RADIUS) as f32 {
+                continue;
+            }
+            let f = (plane[y * w + x] - bg).max(0.0);
+            flux += f;
+            radial += f * r2.sqrt();
+            moment += f * r2;
+        }
+    }
+    if flux <= 0.0 {
+        return (0.0, 0.0);
+    }
+    // The flux-weighted mean radius is the usual HFR estimator; the second moment sums both axes.
+    (radial / flux, FWHM_PER_SIGMA * (moment / flux / 2.0).sqrt())
+}
+
+/// Whether the pixel at (`x`, `y`) is a local maximum, breaking ties towards the top-left.
+fn is_peak(plane: &[f32], w: usize, x: usize, y: usize) -> bool {
+    let v = plane[y * w + x];
+    for dy in -1isize..=1 {
+        for dx in -1isize..=1 {
+            if dx == 0 && dy == 0 {
+                continue;
+            }
+            let n = plane[(y as isize + dy) as usize * w + (x as isize + dx) as usize];
+            let earlier = dy < 0 || (dy == 0 && dx < 0);
+            if n > v || (earlier && n == v) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Star detection settings and the measurements from the most recent frame.
+pub struct StarAnalysis {
+    /// Detect stars on each displayed frame and draw them over the image.
+    pub enabled: bool,
+    /// Detection threshold in noise standard deviations.
+    pub sigma: f32,
+    /// Plate scale in arcseconds per pixel, used to convert sizes.
+    pub plate_scale: f32,
+    /// Stars found in the most recent frame, brightest first.
+    pub stars: Vec<Star>,
+}
+
+impl Default for StarAnalysis {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sigma: 5.0,
+            plate_scale: 1.0,
+            stars: Vec::new(),
+        }
+    }
+}
+
+impl StarAnalysis {
+    /// Detects and measures the stars in `frame`.
+    pub fn analyze(&mut self, frame: &RawFrame) {
+        self.stars = detect(frame, self.sigma);
+    }
+
+    /// Median HFR and FWHM of the detected stars, in pixels.
+    pub fn median_size(&self) -> Option<(f32, f32)> {
+        if self.stars.is_empty() {
+            return None;
+        }
+        let mut hfr: Vec<f32> = self.stars.iter().map(|s| s.hfr).collect();
+        let mut fwhm: Vec<f32> = self.stars.iter().map(|s| s.fwhm).collect();
+        Some((stats::median_f32(&mut hfr), stats::median_f32(&mut fwhm)))
+    }
+
+    /// Shows the detection settings and measurements.
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.checkbox(&mut self.enabled, "Detect stars");
+        ui.horizontal(|ui| {
+            ui.label("Threshold");
+            ui.add(
+                egui::DragValue::new(&mut self.sigma)
+                    .range(2.0..=50.0)
+                    .speed(0.1)
+                    .suffix(" σ"),
+            );
+            ui.label("Plate Scale");
+            ui.add(
+                egui::DragValue::new(&mut self.plate_scale)
+                    .range(0.01..=100.0)
+                    .speed(0.01)
+                    .suffix(" \"/px"),
+            );
+        });
+
+        if !self.enabled {
+            return;
+        }
+        ui.label(format!("{} stars", self.stars.len()));
+        if let Some((hfr, fwhm)) = self.median_size() {
+            egui::Grid::new("StarSizes").show(ui, |ui| {
+                ui.label("");
+                ui.label("px");
+                ui.label("arcsec");
+                ui.end_row();
+                for (name, v) in [("Median HFR", hfr), ("Median FWHM", fwhm)] {
+                    ui.label(name);
+                    ui.label(format!("{:.2}", v));
+                    ui.label(format!("{:.2}", v * self.plate_scale));
+                    ui.end_row();
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKGROUND: f32 = 100.0;
+
+    /// A 16-bit frame with Gaussian stars of standard deviation `sigma` at the given
+    /// (x, y, peak) positions.
+    fn star_field(width: usize, height: usize, sigma: f32, stars: &[(f32, f32, f32)]) -> RawFrame {
+        let mut data = vec![0u16; width * height];
+        for (i, v) in data.iter_mut().enumerate() {
+            let (x, y) = ((i % width) as f32, (i / width) as f32);
+            let signal: f32 = stars
+                .iter()
+                .map(|&(sx, sy, peak)| {
+                    let r2 = (x - sx).powi(2) + (y - sy).powi(2);
+                    peak * (-r2 / (2.0 * sigma * sigma)).exp()
+                })
+                .sum();
+            *v = (BACKGROUND + signal).round() as u16;
+        }
+        RawFrame {
+            width,
+            height,
+            channels: 1,
+            bit_depth: 16,
+            data,
+            timestamp: 0.0,
+            request: None,
+            sequence: None,
+        }
+    }
+
+    #[test]
+    fn measures_a_gaussian_star() {
+        let sigma = 2.0;
+        let frame = star_field(64, 64, sigma, &[(30.3, 33.6, 5000.0)]);
+        let stars = detect(&frame, 5.0);
+        assert_eq!(stars.len(), 1);
+        let star = stars[0];
+        assert!((star.x - 30.3).abs() < 0.1, "x = {}", star.x);
+        assert!((star.y - 33.6).abs() < 0.1, "y = {}", star.y);
+        // The flux-weighted mean radius of a Gaussian is sigma * sqrt(pi / 2).
+        let hfr = sigma * std::f32::consts::FRAC_PI_2.sqrt();
+        assert!((star.hfr - hfr).abs() < 0.05 * hfr, "HFR = {}", star.hfr);
+        let fwhm = FWHM_PER_SIGMA * sigma;
+        assert!(
+            (star.fwhm - fwhm).abs() < 0.05 * fwhm,
+            "FWHM = {}",
+            star.fwhm
+        );
+    }
+
+    #[test]
+    fn finds_separate_stars_brightest_first() {
+        let frame = star_field(
+            96,
+            64,
+            1.5,
+            &[
+                (20.0, 20.0, 2000.0),
+                (70.0, 40.0, 8000.0),
+                (40.0, 50.0, 4000.0),
+            ],
+        );
+        let stars = detect(&frame, 5.0);
+        let positions: Vec<(f32, f32)> = stars.iter().map(|s| (s.x.round(), s.y.round())).collect();
+        assert_eq!(positions, [(70.0, 40.0), (40.0, 50.0), (20.0, 20.0)]);
+    }
+
+    #[test]
+    fn drops_peaks_close_to_a_brighter_star() {
+        let frame = star_field(64, 64, 1.0, &[(30.0, 30.0, 8000.0), (34.0, 31.0, 2000.0)]);
+        let stars = detect(&frame, 5.0);
+        assert_eq!(stars.len(), 1);
+        assert!((stars[0].x - 30.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn limits_the_number_of_stars() {
+        let grid: Vec<(f32, f32, f32)> = (0..30)
+            .flat_map(|y| {
+                (0..30).map(move |x| (8.0 + 10.0 * x as f32, 8.0 + 10.0 * y as f32, 1000.0))
+            })
+            .collect();
+        let frame = star_field(310, 310, 1.0, &grid);
+        assert_eq!(detect(&frame, 5.0).len(), MAX_STARS);
+    }
+}
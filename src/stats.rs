@@ -0,0 +1,213 @@
+//!
+//! # Frame Statistics
+//! Pixel statistics computed on raw frames.
+//!
+
+use eframe::egui;
+use egui::Ui;
+
+use crate::frame::{RawFrame, Region};
+
+/// Returns the `p`-th percentile (0-100) of all samples in `frame`.
+pub fn percentile(frame: &RawFrame, p: f32) -> u16 {
+    let mut values = frame.data.clone();
+    percentile_of(&mut values, p)
+}
+
+/// Returns the `p`-th percentile (0-100) of `values`, reordering them in the process.
+pub fn percentile_of(values: &mut [u16], p: f32) -> u16 {
+    if values.is_empty() {
+        return 0;
+    }
+    let idx = ((p.clamp(0.0, 100.0) / 100.0) * (values.len() - 1) as f32).round() as usize;
+    *values.select_nth_unstable(idx).1
+}
+
+/// Returns the median of `values`, reordering them in the process.
+pub fn median_f32(values: &mut [f32]) -> f32 {
+    let mid = values.len() / 2;
+    *values.select_nth_unstable_by(mid, |a, b| a.total_cmp(b)).1
+}
+
+/// Summary statistics of one channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelStats {
+    /// Arithmetic mean.
+    pub mean: f64,
+    /// Median.
+    pub median: u16,
+    /// Standard deviation.
+    pub std_dev: f64,
+    /// Smallest sample.
+    pub min: u16,
+    /// Largest sample.
+    pub max: u16,
+    /// Noise estimated from the median absolute deviation, robust against stars and hot pixels.
+    pub noise: f64,
+    /// Number of samples at the sensor's full-scale value.
+    pub saturated: usize,
+    /// Number of samples measured.
+    pub count: usize,
+}
+
+/// Computes statistics for each channel of `frame`.
+pub fn channel_stats(frame: &RawFrame) -> Vec<ChannelStats> {
+    let full_scale = frame.max_value();
+    (0..frame.channels)
+        .filter_map(|c| {
+            let mut values: Vec<u16> = frame
+                .data
+                .iter()
+                .skip(c)
+                .step_by(frame.channels)
+                .copied()
+                .collect();
+            if values.is_empty() {
+                return None;
+            }
+            let count = values.len();
+            let (mut sum, mut sum_sq) = (0.0f64, 0.0f64);
+            let (mut min, mut max, mut saturated) = (u16::MAX, 0, 0);
+            for &v in &values {
+                sum += v as f64;
+                sum_sq += v as f64 * v as f64;
+                min = min.min(v);
+                max = max.max(v);
+                if v >= full_scale {
+                    saturated += 1;
+                }
+            }
+            let mean = sum / count as f64;
+            let median = percentile_of(&mut values, 50.0);
+            let mut deviations: Vec<u16> = values.iter().map(|&v| v.abs_diff(median)).collect();
+            let mad = percentile_of(&mut deviations, 50.0);
+            Some(ChannelStats {
+                mean,
+                median,
+                std_dev: (sum_sq / count as f64 - mean * mean).max(0.0).sqrt(),
+                min,
+                max,
+                // 1.4826 converts a MAD to a Gaussian standard deviation.
+                noise: mad as f64 * 1.4826,
+                saturated,
+                count,
+            })
+        })
+        .collect()
+}
+
+/// A row label in the statistics table and how to format it from a channel's statistics.
+type StatRow = (&'static str, fn(&ChannelStats) -> String);
+
+/// Which part of the frame the statistics panel measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsScope {
+    /// Every pixel.
+    Frame,
+    /// The region of interest.
+    Roi,
+    /// A box drawn on the image.
+    Selection,
+}
+
+/// Statistics of the latest raw frame over a chosen region.
+pub struct StatsPanel {
+    /// Which part of the frame is measured.
+    pub scope: StatsScope,
+    /// The box drawn on the image, in frame pixels.
+    pub selection: Option<Region>,
+    results: Vec<ChannelStats>,
+    measured: Option<Region>,
+}
+
+impl Default for StatsPanel {
+    fn default() -> Self {
+        Self {
+            scope: StatsScope::Frame,
+            selection: None,
+            results: Vec::new(),
+            measured: None,
+        }
+    }
+}
+
+impl StatsPanel {
+    /// Recomputes the statistics of `frame`, where `roi` is the region of interest if enabled.
+    pub fn update(&mut self, frame: &RawFrame, roi: Option<Region>) {
+        self.measured = match self.scope {
+            StatsScope::Frame => None,
+            StatsScope::Roi => roi,
+            StatsScope::Selection => self.selection,
+        };
+        self.results = match &self.measured {
+            Some(region) => channel_stats(&frame.crop(region)),
+            None => channel_stats(frame),
+        };
+    }
+
+    /// Shows the scope selector and a table of per-channel statistics. Returns whether the
+    /// scope changed.
+    pub fn ui(&mut self, ui: &mut Ui) -> bool {
+        let scope = self.scope;
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.scope, StatsScope::Frame, "Whole Frame");
+            ui.selectable_value(&mut self.scope, StatsScope::Roi, "ROI");
+            ui.selectable_value(&mut self.scope, StatsScope::Selection, "Selection");
+        });
+        match (self.scope, &self.measured) {
+            (_, Some(r)) => {
+                ui.label(format!("{}x{} at ({}, {})", r.width, r.height, r.x, r.y));
+            }
+            (StatsScope::Roi, None) => {
+                ui.label("ROI disabled; measuring the whole frame.");
+            }
+            (StatsScope::Selection, None) => {
+                ui.label("Drag on the image to select a box; measuring the whole frame.");
+            }
+            (StatsScope::Frame, None) => {}
+        }
+
+        if self.results.is_empty() {
+            ui.label("No image data.");
+            return scope != self.scope;
+        }
+
+        let names: Vec<String> = match self.results.len() {
+            1 => vec!["Mono".into()],
+            3 => vec!["R".into(), "G".into(), "B".into()],
+            n => (1..=n).map(|c| format!("Ch {}", c)).collect(),
+        };
+        egui::Grid::new("FrameStats").striped(true).show(ui, |ui| {
+            ui.label("");
+            for name in &names {
+                ui.label(name);
+            }
+            ui.end_row();
+
+            let rows: [StatRow; 7] = [
+                ("Mean", |s| format!("{:.1}", s.mean)),
+                ("Median", |s| s.median.to_string()),
+                ("Std. Dev.", |s| format!("{:.1}", s.std_dev)),
+                ("Min", |s| s.min.to_string()),
+                ("Max", |s| s.max.to_string()),
+                ("Noise (MAD)", |s| format!("{:.1}", s.noise)),
+                ("Saturated", |s| {
+                    format!(
+                        "{} ({:.2}%)",
+                        s.saturated,
+                        100.0 * s.saturated as f64 / s.count as f64
+                    )
+                }),
+            ];
+            for (label, value) in rows {
+                ui.label(label);
+                for s in &self.results {
+                    ui.label(value(s));
+                }
+                ui.end_row();
+            }
+        });
+
+        scope != self.scope
+    }
+}
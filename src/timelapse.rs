@@ -0,0 +1,403 @@
+//!
+//! # Time-Lapse Capture
+//! Interval capture between scheduled wall-clock start and stop times.
+//!
+
+use std::path::PathBuf;
+
+use eframe::egui;
+use egui::Ui;
+
+use crate::clock;
+use crate::command::{CameraCommand, ServerRequest};
+use crate::fits;
+use crate::frame::{FrameRequest, RawFrame};
+use crate::stats;
+
+/// Seconds to wait past the exposure time for a frame before counting it as missed.
+const FRAME_TIMEOUT: f64 = 30.0;
+
+/// Largest factor auto-exposure may change the exposure by between two frames.
+const MAX_EXPOSURE_STEP: f64 = 4.0;
+
+/// When a time-lapse starts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StartMode {
+    /// As soon as the user presses start.
+    Now,
+    /// At the next occurrence of a local wall-clock time.
+    At {
+        /// Hour, 0-23.
+        hour: u32,
+        /// Minute, 0-59.
+        minute: u32,
+    },
+}
+
+/// When a time-lapse stops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopMode {
+    /// After running for this many minutes.
+    Duration(f64),
+    /// At the next occurrence of a local wall-clock time after the start.
+    At {
+        /// Hour, 0-23.
+        hour: u32,
+        /// Minute, 0-59.
+        minute: u32,
+    },
+    /// After this many frames.
+    Frames(u32),
+}
+
+/// Time-lapse settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeLapse {
+    /// Seconds between frame starts.
+    pub interval: f64,
+    /// When to begin.
+    pub start: StartMode,
+    /// When to end.
+    pub stop: StopMode,
+    /// Adjust exposure between frames to keep the median at `target_level`.
+    pub auto_exposure: bool,
+    /// Target median as a fraction of full scale.
+    pub target_level: f32,
+    /// Shortest exposure auto-exposure may choose, in seconds.
+    pub min_exposure: f64,
+    /// Longest exposure auto-exposure may choose, in seconds.
+    pub max_exposure: f64,
+    /// Directory frames are written to.
+    pub output_dir: String,
+}
+
+impl Default for TimeLapse {
+    fn default() -> Self {
+        Self {
+            interval: 10.0,
+            start: StartMode::Now,
+            stop: StopMode::Duration(60.0),
+            auto_exposure: false,
+            target_level: 0.25,
+            min_exposure: 0.001,
+            max_exposure: 30.0,
+            output_dir: ".".into(),
+        }
+    }
+}
+
+impl TimeLapse {
+    /// The next Unix time after `after` at which local wall-clock time reads `hour:minute`.
+    fn next_occurrence(after: f64, hour: u32, minute: u32) -> f64 {
+        let local = after + clock::local_offset(after);
+        let midnight = (local / 86400.0).floor() * 86400.0;
+        let mut t = midnight + (hour * 3600 + minute * 60) as f64;
+        if t <= local {
+            t += 86400.0;
+        }
+        // Use the offset in effect at the target time, in case daylight saving changes first.
+        t - clock::local_offset(t - clock::local_offset(after))
+    }
+
+    /// Shows the settings editor.
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Interval");
+            ui.add(
+                egui::DragValue::new(&mut self.interval)
+                    .range(0.1..=86400.0)
+                    .speed(0.5)
+                    .suffix(" s"),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Start");
+            let mut at = matches!(self.start, StartMode::At { .. });
+            ui.radio_value(&mut at, false, "Now");
+            ui.radio_value(&mut at, true, "At");
+            match (at, &mut self.start) {
+                (true, StartMode::At { hour, minute }) => {
+                    time_edit(ui, hour, minute);
+                }
+                (true, StartMode::Now) => {
+                    self.start = StartMode::At {
+                        hour: 20,
+                        minute: 0,
+                    }
+                }
+                (false, _) => self.start = StartMode::Now,
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Stop");
+            egui::ComboBox::from_id_source("TimeLapseStop")
+                .selected_text(match self.stop {
+                    StopMode::Duration(_) => "After",
+                    StopMode::At { .. } => "At",
+                    StopMode::Frames(_) => "Frames",
+                })
+                .show_ui(ui, |ui| {
+                    if ui
+                        .selectable_label(matches!(self.stop, StopMode::Duration(_)), "After")
+                        .clicked()
+                    {
+                        self.stop = StopMode::Duration(60.0);
+                    }
+                    if ui
+                        .selectable_label(matches!(self.stop, StopMode::At { .. }), "At")
+                        .clicked()
+                    {
+                        self.stop = StopMode::At { hour: 6, minute: 0 };
+                    }
+                    if ui
+                        .selectable_label(matches!(self.stop, StopMode::Frames(_)), "Frames")
+                        .clicked()
+                    {
+                        self.stop = StopMode::Frames(100);
+                    }
+                });
+            match &mut self.stop {
+                StopMode::Duration(minutes) => {
+                    ui.add(
+                        egui::DragValue::new(minutes)
+                            .range(0.1..=10080.0)
+                            .suffix(" min"),
+                    );
+                }
+                StopMode::At { hour, minute } => time_edit(ui, hour, minute),
+                StopMode::Frames(n) => {
+                    ui.add(egui::DragValue::new(n).range(1..=1_000_000));
+                }
+            }
+        });
+
+        ui.checkbox(&mut self.auto_exposure, "Adjust exposure across the series");
+        ui.add_enabled_ui(self.auto_exposure, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Target Median");
+                ui.add(
+                    egui::Slider::new(&mut self.target_level, 0.01..=0.9)
+                        .custom_formatter(|v, _| format!("{:.0}%", v * 100.0)),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.label("Exposure");
+                ui.add(
+                    egui::DragValue::new(&mut self.min_exposure)
+                        .range(0.0001..=self.max_exposure)
+                        .speed(0.01)
+                        .suffix(" s"),
+                );
+                ui.label("to");
+                ui.add(
+                    egui::DragValue::new(&mut self.max_exposure)
+                        .range(self.min_exposure..=3600.0)
+                        .speed(0.1)
+                        .suffix(" s"),
+                );
+            });
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Output Directory");
+            ui.text_edit_singleline(&mut self.output_dir);
+        });
+    }
+}
+
+fn time_edit(ui: &mut Ui, hour: &mut u32, minute: &mut u32) {
+    ui.add(
+        egui::DragValue::new(hour)
+            .range(0..=23)
+            .custom_formatter(|v, _| format!("{:02}", v)),
+    );
+    ui.label(":");
+    ui.add(
+        egui::DragValue::new(minute)
+            .range(0..=59)
+            .custom_formatter(|v, _| format!("{:02}", v)),
+    );
+}
+
+/// A time-lapse in progress.
+pub struct TimeLapseRun {
+    settings: TimeLapse,
+    start_time: f64,
+    stop_time: Option<f64>,
+    next_capture: f64,
+    /// When the pending frame was requested, and the request once it has been sent.
+    pending: Option<(f64, Option<FrameRequest>)>,
+    exposure: f64,
+    frames: u32,
+    missed: u32,
+    finished: bool,
+}
+
+impl TimeLapseRun {
+    /// Schedules a time-lapse at Unix time `now`, beginning with the given exposure in seconds.
+    pub fn new(settings: TimeLapse, now: f64, exposure: f64) -> TimeLapseRun {
+        let start_time = match settings.start {
+            StartMode::Now => now,
+            StartMode::At { hour, minute } => TimeLapse::next_occurrence(now, hour, minute),
+        };
+        let stop_time = match settings.stop {
+            StopMode::Duration(minutes) => Some(start_time + minutes * 60.0),
+            StopMode::At { hour, minute } => {
+                Some(TimeLapse::next_occurrence(start_time, hour, minute))
+            }
+            StopMode::Frames(_) => None,
+        };
+        TimeLapseRun {
+            settings,
+            start_time,
+            stop_time,
+            next_capture: start_time,
+            pending: None,
+            exposure,
+            frames: 0,
+            missed: 0,
+            finished: false,
+        }
+    }
+
+    /// Whether the time-lapse has ended.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Ends the time-lapse early.
+    pub fn stop(&mut self) {
+        self.finished = true;
+    }
+
+    /// A human-readable description of the time-lapse's progress.
+    pub fn status(&self, now: f64) -> String {
+        if self.finished {
+            format!(
+                "Time-lapse finished: {} frames, {} missed.",
+                self.frames, self.missed
+            )
+        } else if now < self.start_time {
+            format!("Starting in {:.0} s", self.start_time - now)
+        } else {
+            let remaining = match (self.settings.stop, self.stop_time) {
+                (StopMode::Frames(n), _) => {
+                    format!("{} frames left", n.saturating_sub(self.frames))
+                }
+                (_, Some(stop)) => format!("{:.0} s left", (stop - now).max(0.0)),
+                _ => String::new(),
+            };
+            format!(
+                "{} frames, {} missed, exposure {:.3} s, next in {:.0} s, {}",
+                self.frames,
+                self.missed,
+                self.exposure,
+                (self.next_capture - now).max(0.0),
+                remaining
+            )
+        }
+    }
+
+    /// Records the image request sent for the pending frame, so that frames from earlier
+    /// exposures are not saved in its place.
+    pub fn requested(&mut self, sent: FrameRequest) {
+        if let Some((_, request)) = &mut self.pending {
+            *request = Some(sent);
+        }
+    }
+
+    /// Advances the time-lapse at Unix time `now`.
+    ///
+    /// `temperature` is the latest reported sensor temperature and `frames` are the frames that
+    /// arrived since the last call, oldest first. Returns the requests to send to the server, or
+    /// an error if a frame could not be saved.
+    pub fn poll(
+        &mut self,
+        now: f64,
+        temperature: Option<f32>,
+        frames: &[RawFrame],
+    ) -> Result<Vec<ServerRequest>, String> {
+        let mut out = Vec::new();
+        if self.finished {
+            return Ok(out);
+        }
+
+        // Frames from exposures started before the request was sent are skipped.
+        let frame = match &mut self.pending {
+            Some((_, request)) => frames
+                .iter()
+                .find(|f| request.as_mut().map_or(true, |r| r.accepts(f))),
+            None => None,
+        };
+        if let Some(frame) = frame {
+            self.pending = None;
+            self.frames += 1;
+            self.save(frame, temperature)?;
+
+            if self.settings.auto_exposure {
+                let exposure = self.next_exposure(frame);
+                if exposure != self.exposure {
+                    self.exposure = exposure;
+                    out.push(ServerRequest::Command(CameraCommand::SetExposure(exposure)));
+                }
+            }
+        } else if let Some((since, _)) = self.pending {
+            if now - since > self.exposure + FRAME_TIMEOUT {
+                self.pending = None;
+                self.missed += 1;
+            }
+        }
+
+        let done = match (self.settings.stop, self.stop_time) {
+            (StopMode::Frames(n), _) => self.frames >= n,
+            (_, Some(stop)) => now >= stop,
+            _ => false,
+        };
+        if done {
+            self.finished = true;
+            return Ok(out);
+        }
+
+        if now >= self.next_capture && self.pending.is_none() {
+            if self.frames == 0 && self.missed == 0 {
+                out.push(ServerRequest::Command(CameraCommand::SetExposure(
+                    self.exposure,
+                )));
+            }
+            out.push(ServerRequest::Image);
+            self.pending = Some((now, None));
+            // Skip slots we were too slow for rather than bursting to catch up.
+            while self.next_capture <= now {
+                self.next_capture += self.settings.interval;
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Scales the exposure so the next frame's median lands on the target level.
+    fn next_exposure(&self, frame: &RawFrame) -> f64 {
+        let median = stats::percentile(frame, 50.0).max(1) as f64;
+        let target = self.settings.target_level as f64 * frame.max_value() as f64;
+        let ratio = (target / median).clamp(1.0 / MAX_EXPOSURE_STEP, MAX_EXPOSURE_STEP);
+        (self.exposure * ratio).clamp(self.settings.min_exposure, self.settings.max_exposure)
+    }
+
+    fn save(&self, frame: &RawFrame, temperature: Option<f32>) -> Result<(), String> {
+        let path = PathBuf::from(&self.settings.output_dir).join(format!(
+            "timelapse_{}_{:05}.fits",
+            clock::file_stamp(frame.timestamp),
+            self.frames
+        ));
+        let cards = fits::frame_cards(
+            "Light Frame",
+            self.exposure,
+            frame.timestamp,
+            frame.bit_depth,
+            temperature,
+        );
+        fits::write_fits(&path, frame, &cards).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,195 @@
+//!
+//! # Trigger and Bulb Controls
+//! Selects how the camera starts exposures, and runs bulb exposures with a countdown.
+//!
+
+use eframe::egui;
+use egui::Ui;
+
+use crate::clock::format_duration;
+use crate::command::{TriggerMode, TriggerPolarity};
+
+/// Requests from the trigger controls.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriggerAction {
+    /// Switch the camera to this trigger mode.
+    SetMode(TriggerMode),
+    /// Fire a software trigger.
+    Fire,
+    /// Start a bulb exposure of this many seconds.
+    StartBulb(f64),
+    /// End the running bulb exposure early.
+    StopBulb,
+}
+
+/// A bulb exposure in progress.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BulbExposure {
+    /// GUI time the exposure started, in seconds.
+    pub start: f64,
+    /// Planned length in seconds.
+    pub duration: f64,
+    /// The exposure was ended early and is being read out.
+    pub stopped: bool,
+}
+
+impl BulbExposure {
+    /// Seconds left until the planned end, never negative.
+    pub fn remaining(&self, now: f64) -> f64 {
+        (self.start + self.duration - now).max(0.0)
+    }
+}
+
+/// Trigger mode selection and bulb exposure state.
+pub struct TriggerControls {
+    /// The selected trigger mode.
+    pub mode: TriggerMode,
+    /// Take exposures in bulb mode, timed by the GUI.
+    pub bulb: bool,
+    /// The bulb exposure currently running, if any.
+    pub bulb_run: Option<BulbExposure>,
+}
+
+impl Default for TriggerControls {
+    fn default() -> Self {
+        Self {
+            mode: TriggerMode::FreeRun,
+            bulb: false,
+            bulb_run: None,
+        }
+    }
+}
+
+impl TriggerControls {
+    /// Notes that a frame arrived at GUI time `now`. Only a frame arriving after the bulb
+    /// exposure's planned or early end finishes it; earlier frames come from other exposures.
+    pub fn frame_received(&mut self, now: f64) {
+        if self
+            .bulb_run
+            .is_some_and(|run| run.stopped || now >= run.start + run.duration)
+        {
+            self.bulb_run = None;
+        }
+    }
+
+    /// Shows the trigger mode, arming state and bulb controls.
+    ///
+    /// `now` is the GUI time in seconds, `armed` whether the camera reports it is waiting for a
+    /// trigger and `exposure` the selected exposure in seconds.
+    pub fn ui(
+        &mut self,
+        ui: &mut Ui,
+        now: f64,
+        armed: bool,
+        exposure: f64,
+    ) -> Option<TriggerAction> {
+        let mut action = None;
+        let mode = self.mode;
+
+        ui.horizontal(|ui| {
+            ui.label("Trigger");
+            let name = match self.mode {
+                TriggerMode::FreeRun => "Free-Run",
+                TriggerMode::Software => "Software",
+                TriggerMode::ExternalEdge(_) => "External Edge",
+                TriggerMode::ExternalLevel(_) => "External Level",
+            };
+            egui::ComboBox::from_id_source("TriggerMode")
+                .selected_text(name)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.mode, TriggerMode::FreeRun, "Free-Run");
+                    ui.selectable_value(&mut self.mode, TriggerMode::Software, "Software");
+                    ui.selectable_value(
+                        &mut self.mode,
+                        TriggerMode::ExternalEdge(TriggerPolarity::Positive),
+                        "External Edge",
+                    );
+                    ui.selectable_value(
+                        &mut self.mode,
+                        TriggerMode::ExternalLevel(TriggerPolarity::Positive),
+                        "External Level",
+                    );
+                });
+
+            match &mut self.mode {
+                TriggerMode::ExternalEdge(p) => {
+                    ui.selectable_value(p, TriggerPolarity::Positive, "Rising");
+                    ui.selectable_value(p, TriggerPolarity::Negative, "Falling");
+                }
+                TriggerMode::ExternalLevel(p) => {
+                    ui.selectable_value(p, TriggerPolarity::Positive, "High");
+                    ui.selectable_value(p, TriggerPolarity::Negative, "Low");
+                }
+                _ => {}
+            }
+        });
+        if self.mode != mode {
+            action = Some(TriggerAction::SetMode(self.mode));
+        }
+
+        if self.mode != TriggerMode::FreeRun {
+            ui.horizontal(|ui| {
+                if armed {
+                    ui.colored_label(egui::Color32::GREEN, "● Armed, waiting for trigger");
+                } else {
+                    ui.colored_label(ui.visuals().weak_text_color(), "○ Not armed");
+                }
+                if self.mode == TriggerMode::Software && ui.button("Fire").clicked() {
+                    action = Some(TriggerAction::Fire);
+                }
+            });
+        }
+
+        ui.add_enabled_ui(self.bulb_run.is_none(), |ui| {
+            ui.checkbox(&mut self.bulb, "Bulb").on_hover_text(
+                "Hold the shutter open for the selected exposure, timed by the GUI.",
+            );
+        });
+        if !self.bulb {
+            return action;
+        }
+
+        match &mut self.bulb_run {
+            Some(run) => {
+                let remaining = run.remaining(now);
+                let elapsed = run.duration - remaining;
+                let progress = (elapsed / run.duration.max(1e-3)) as f32;
+                let text = if run.stopped || remaining <= 0.0 {
+                    "Reading out…".to_owned()
+                } else {
+                    format!(
+                        "{} elapsed, {} left",
+                        format_duration(elapsed),
+                        format_duration(remaining)
+                    )
+                };
+                ui.add(egui::ProgressBar::new(progress).text(text));
+                if ui
+                    .add_enabled(
+                        !run.stopped && remaining > 0.0,
+                        egui::Button::new("Stop Early"),
+                    )
+                    .clicked()
+                {
+                    run.duration = now - run.start;
+                    run.stopped = true;
+                    action = Some(TriggerAction::StopBulb);
+                }
+            }
+            None => {
+                if ui
+                    .button(format!("Start Bulb ({})", format_duration(exposure)))
+                    .clicked()
+                {
+                    self.bulb_run = Some(BulbExposure {
+                        start: now,
+                        duration: exposure,
+                        stopped: false,
+                    });
+                    action = Some(TriggerAction::StartBulb(exposure));
+                }
+            }
+        }
+        action
+    }
+}
@@ -15,7 +15,6 @@ pub async fn start(canvas: web_sys::HtmlCanvasElement) -> Result<(), JsValue> {
     // Redirect `log` message to `console.log` and friends:
     eframe::WebLogger::init(log::LevelFilter::Debug).ok();
 
-    let app = crate::GenCamGUI::default();
     eframe::WebRunner::new()
         .start(
             "gui_canvas",
@@ -24,7 +23,7 @@ pub async fn start(canvas: web_sys::HtmlCanvasElement) -> Result<(), JsValue> {
             Box::new(|cc| {
                 // This gives us image support:
                 egui_extras::install_image_loaders(&cc.egui_ctx);
-                Ok(Box::new(app))
+                Ok(Box::new(crate::GenCamGUI::new(cc)))
             }),
         )
         .await?;